@@ -0,0 +1,118 @@
+//! `--watch-logs`: stream the instance's cloud-init output to the terminal
+//! while fuoco waits, so a boot script can be followed without logging in.
+//! Every provider is reached the same way, over SSH.
+
+use crate::teardown::{self, Target};
+use std::{
+  io::{self, BufRead, BufReader, Write},
+  process::{Child, Stdio},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+  },
+  thread,
+  time::Duration,
+};
+
+const LOG: &str = "/var/log/cloud-init-output.log";
+
+/// How long to wait before trying SSH again while the instance boots.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A running `watch`; `stop` it before the instance goes away.
+pub struct Watch {
+  stopped: Arc<AtomicBool>,
+  child: Arc<Mutex<Option<Child>>>,
+}
+
+impl Watch {
+  pub fn stop(&self) {
+    self.stopped.store(true, Ordering::Relaxed);
+    let mut child = self.child.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(child) = child.as_mut() {
+      let _ = child.kill();
+    }
+  }
+}
+
+/// Follow the cloud-init output on `target`, each line prefixed with
+/// `prefix`. Until sshd answers, and whenever the connection drops, it
+/// tries again; lines already shown are not repeated.
+pub fn watch(target: Target, prefix: String) -> Watch {
+  let stopped = Arc::new(AtomicBool::new(false));
+  let child: Arc<Mutex<Option<Child>>> = Arc::new(Mutex::new(None));
+  let watch = Watch {
+    stopped: stopped.clone(),
+    child: child.clone(),
+  };
+  thread::spawn(move || {
+    let mut shown = 0;
+    let mut waiting = true;
+    while !stopped.load(Ordering::Relaxed) {
+      let tail = format!("tail -n +{} -F {}", shown + 1, LOG);
+      // The log is root's; a --user has passwordless sudo
+      let command = format!(
+        "if [ $(id -u) -eq 0 ]; then {tail}; else sudo -n {tail}; fi",
+        tail = tail
+      );
+      let spawned = teardown::ssh(&target)
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+      let mut spawned = match spawned {
+        Ok(spawned) => spawned,
+        Err(err) => {
+          log::warn!("--watch-logs: failed to run ssh: {}", err);
+          return;
+        }
+      };
+      let stdout = spawned.stdout.take();
+      *child.lock().unwrap_or_else(|e| e.into_inner()) = Some(spawned);
+      // Checked again now that `stop` can reach the child
+      if stopped.load(Ordering::Relaxed) {
+        break;
+      }
+      for line in stdout.into_iter().flat_map(|s| BufReader::new(s).lines()) {
+        let Ok(line) = line else { break };
+        if waiting {
+          status!("Streaming {} from {}...", LOG, target.ip);
+          waiting = false;
+        }
+        shown += 1;
+        print_line(&prefix, &line);
+      }
+      let status = child
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+        .and_then(|mut child| child.wait().ok());
+      match status.and_then(|status| status.code()) {
+        Some(teardown::SSH_FAILED) => thread::sleep(RETRY_INTERVAL),
+        // Killed by `stop` or by the terminal's Ctrl+C
+        None => break,
+        Some(code) => {
+          if !stopped.load(Ordering::Relaxed) {
+            log::warn!(
+              "--watch-logs: could not follow {} (exit status {})",
+              LOG,
+              code
+            );
+          }
+          break;
+        }
+      }
+    }
+  });
+  watch
+}
+
+/// Stdout, unless it is kept clean for --print-ip.
+fn print_line(prefix: &str, line: &str) {
+  if crate::STDOUT_RESERVED.load(Ordering::Relaxed) {
+    let _ = writeln!(io::stderr(), "{}{}", prefix, line);
+  } else {
+    let _ = writeln!(io::stdout(), "{}{}", prefix, line);
+  }
+}
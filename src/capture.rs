@@ -0,0 +1,217 @@
+//! A copy of what Terraform writes on stderr, so a failed apply or destroy
+//! says why even when its output was not shown: atar discards it below
+//! `-vv`. A `terraform` shim first on the PATH runs the real one with its
+//! stderr teed into a file. Both live in a directory of the fuoco process's
+//! own, which no other user can write to.
+
+use anyhow::Result;
+use std::{
+  env,
+  fs::{self, DirBuilder},
+  io::{self, Read, Seek, SeekFrom},
+  os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt},
+  path::{Path, PathBuf},
+  process,
+  sync::OnceLock,
+};
+
+/// How much of the end of the output an error carries.
+const TAIL_LINES: usize = 50;
+
+const SHIM: &str = r#"#!/usr/bin/env bash
+# Installed by fuoco: runs Terraform with a copy of its stderr kept for
+# error messages.
+exec "$FUOCO_REAL_TERRAFORM" "$@" 2> >(tee -a "$FUOCO_TERRAFORM_STDERR" >&2)
+"#;
+
+/// The directory `install` made for the shim and the output.
+static DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Put the shim first on the PATH. Without a terraform on the PATH there
+/// is nothing to wrap.
+pub fn install() -> Result<()> {
+  let Some(terraform) = find_on_path("terraform") else {
+    return Ok(());
+  };
+  let dir = private_dir()?;
+  let shim = dir.join("terraform");
+  fs::write(&shim, SHIM)?;
+  fs::set_permissions(&shim, fs::Permissions::from_mode(0o700))?;
+  if let Err(err) = check_private(&dir, &shim) {
+    let _ = fs::remove_dir_all(&dir);
+    return Err(err);
+  }
+  let mut path = vec![dir.clone()];
+  path.extend(env::split_paths(&env::var_os("PATH").unwrap_or_default()));
+  env::set_var("FUOCO_REAL_TERRAFORM", terraform);
+  env::set_var("FUOCO_TERRAFORM_STDERR", dir.join(STDERR_FILE));
+  env::set_var("PATH", env::join_paths(path)?);
+  let _ = DIR.set(dir);
+  Ok(())
+}
+
+/// Remove the shim and this process's copy of the output.
+pub fn cleanup() {
+  if let Some(dir) = DIR.get() {
+    let _ = fs::remove_dir_all(dir);
+  }
+}
+
+/// Where the output captured so far ends, to pass to `attach` later.
+pub fn mark() -> u64 {
+  file()
+    .and_then(|file| fs::metadata(file).ok())
+    .map_or(0, |metadata| metadata.len())
+}
+
+/// Add the end of what Terraform wrote since `mark` to a failure. With
+/// several deployments in parallel it may include the others' output.
+pub fn attach<T>(result: Result<T>, mark: u64) -> Result<T> {
+  let Err(err) = result else {
+    return result;
+  };
+  match tail(mark) {
+    Some(tail) => {
+      let message = format!("{:#}; Terraform said:\n{}", err, tail);
+      Err(err.context(message))
+    }
+    None => Err(err),
+  }
+}
+
+const STDERR_FILE: &str = "terraform.stderr";
+
+fn file() -> Option<PathBuf> {
+  DIR.get().map(|dir| dir.join(STDERR_FILE))
+}
+
+/// A new directory only we can enter, under `$XDG_RUNTIME_DIR` when there
+/// is one. The name is not guessable and the directory never existed
+/// before, so nobody else can have planted it.
+fn private_dir() -> Result<PathBuf> {
+  let parent = env::var_os("XDG_RUNTIME_DIR")
+    .map(PathBuf::from)
+    .filter(|dir| dir.is_dir())
+    .unwrap_or_else(env::temp_dir);
+  loop {
+    let dir = parent.join(format!(
+      "fuoco-{}-{:016x}",
+      process::id(),
+      rand::random::<u64>()
+    ));
+    match DirBuilder::new().mode(0o700).create(&dir) {
+      Ok(()) => return Ok(dir),
+      Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+      Err(err) => return Err(err.into()),
+    }
+  }
+}
+
+/// Fail unless `dir` is a real directory, owned by whoever owns `ours`
+/// (a file we just wrote) and closed to everyone else.
+fn check_private(dir: &Path, ours: &Path) -> Result<()> {
+  let metadata = fs::symlink_metadata(dir)?;
+  let owner = fs::metadata(ours)?.uid();
+  if !metadata.is_dir()
+    || metadata.uid() != owner
+    || metadata.mode() & 0o077 != 0
+  {
+    anyhow::bail!("{:?} is not a directory of this user closed to others", dir);
+  }
+  Ok(())
+}
+
+/// Everything Terraform wrote since `mark`, without colors; empty when
+/// nothing was captured.
+pub fn since(mark: u64) -> String {
+  let Some(Ok(mut file)) = file().map(fs::File::open) else {
+    return String::new();
+  };
+  let mut bytes = Vec::new();
+  if file.seek(SeekFrom::Start(mark)).is_err()
+    || file.read_to_end(&mut bytes).is_err()
+  {
+    return String::new();
+  }
+  strip_ansi(&String::from_utf8_lossy(&bytes))
+}
+
+/// The last `TAIL_LINES` non-blank lines written since `mark`, indented.
+fn tail(mark: u64) -> Option<String> {
+  let text = since(mark);
+  let lines: Vec<_> = text
+    .lines()
+    .map(str::trim_end)
+    .filter(|line| !line.trim().is_empty())
+    .collect();
+  if lines.is_empty() {
+    return None;
+  }
+  let tail = &lines[lines.len().saturating_sub(TAIL_LINES)..];
+  Some(
+    tail
+      .iter()
+      .map(|line| format!("  {}", line))
+      .collect::<Vec<_>>()
+      .join("\n"),
+  )
+}
+
+/// Drop the escape sequences Terraform colors its diagnostics with.
+fn strip_ansi(text: &str) -> String {
+  let mut plain = String::with_capacity(text.len());
+  let mut chars = text.chars();
+  while let Some(c) = chars.next() {
+    if c != '\x1b' {
+      plain.push(c);
+      continue;
+    }
+    // CSI sequences run to a final byte in @..~
+    if chars.next() == Some('[') {
+      for c in chars.by_ref() {
+        if ('@'..='~').contains(&c) {
+          break;
+        }
+      }
+    }
+  }
+  plain
+}
+
+fn find_on_path(program: &str) -> Option<PathBuf> {
+  env::split_paths(&env::var_os("PATH")?)
+    .map(|dir| dir.join(program))
+    .find(|path| is_executable(path))
+}
+
+fn is_executable(path: &Path) -> bool {
+  fs::metadata(path).is_ok_and(|metadata| {
+    metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn private_dir_is_closed_to_others() {
+    let dir = private_dir().unwrap();
+    let ours = dir.join("probe");
+    fs::write(&ours, "").unwrap();
+    assert_eq!(fs::metadata(&dir).unwrap().mode() & 0o777, 0o700);
+    check_private(&dir, &ours).unwrap();
+    // Someone else could swap the shim in a directory they can write to
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+    assert!(check_private(&dir, &ours).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn private_dirs_are_not_shared() {
+    let (first, second) = (private_dir().unwrap(), private_dir().unwrap());
+    assert_ne!(first, second);
+    fs::remove_dir(first).unwrap();
+    fs::remove_dir(second).unwrap();
+  }
+}
@@ -0,0 +1,213 @@
+//! `fuoco clean`: find the Terraform workspaces, staged templates and
+//! failed-destroy records left behind, and remove the ones no deployment
+//! depends on.
+
+use crate::{failed, lock, workspace, workspace_dir};
+use anyhow::{Context, Result};
+use std::{
+  env, fmt, fs,
+  path::{Path, PathBuf},
+  time::{Duration, SystemTime},
+};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+  /// A fuoco process holds the workspace lock, or Terraform is running.
+  Active,
+  /// The state still holds resources: only `fuoco undeploy` removes it.
+  Deployed,
+  /// The state cannot be parsed; `fuoco undeploy --force` deals with it.
+  Broken,
+  /// Everything in the state was destroyed.
+  Destroyed,
+  /// A workspace without state, or a staged template or record whose
+  /// workspace is gone.
+  Orphaned,
+}
+
+impl Status {
+  fn removable(self) -> bool {
+    matches!(self, Status::Destroyed | Status::Orphaned)
+  }
+}
+
+impl fmt::Display for Status {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let status = match self {
+      Status::Active => "active",
+      Status::Deployed => "deployed",
+      Status::Broken => "broken",
+      Status::Destroyed => "destroyed",
+      Status::Orphaned => "orphaned",
+    };
+    f.pad(status)
+  }
+}
+
+struct Entry {
+  kind: &'static str,
+  path: PathBuf,
+  status: Status,
+  age: Duration,
+}
+
+/// Print every workspace, staged template and record with its status, and with `yes` delete
+/// the removable ones not modified for `older_than`.
+pub fn run(yes: bool, older_than: Option<Duration>) -> Result<()> {
+  let entries = scan()?;
+  if entries.is_empty() {
+    println!("Nothing to clean");
+    return Ok(());
+  }
+  let older_than = older_than.unwrap_or_default();
+  let remove =
+    |entry: &Entry| entry.status.removable() && entry.age >= older_than;
+  println!(
+    "{:<10} {:<9} {:>5}  {:<6} PATH",
+    "STATUS", "KIND", "AGE", "REMOVE"
+  );
+  for entry in &entries {
+    println!(
+      "{:<10} {:<9} {:>5}  {:<6} {}",
+      entry.status,
+      entry.kind,
+      format_age(entry.age),
+      if remove(entry) { "yes" } else { "no" },
+      entry.path.display()
+    );
+  }
+  let removable: Vec<&Entry> = entries.iter().filter(|e| remove(e)).collect();
+  if !yes {
+    println!(
+      "\n{} of {} entries would be removed; pass --yes to remove them",
+      removable.len(),
+      entries.len()
+    );
+    return Ok(());
+  }
+  for entry in &removable {
+    let result = if entry.path.is_dir() {
+      fs::remove_dir_all(&entry.path)
+    } else {
+      fs::remove_file(&entry.path)
+    };
+    result.with_context(|| format!("Failed to remove {:?}", entry.path))?;
+  }
+  println!("\nRemoved {} of {} entries", removable.len(), entries.len());
+  Ok(())
+}
+
+fn scan() -> Result<Vec<Entry>> {
+  let mut entries = Vec::new();
+  for path in list(&env::temp_dir().join("atar"))? {
+    if path.is_dir() {
+      entries.push(Entry {
+        kind: "workspace",
+        status: workspace_status(&path),
+        age: age(&path),
+        path,
+      });
+    }
+  }
+  // The copy of the template each workspace was made from
+  for path in list(&workspace::templates_dir())? {
+    if path.is_dir() {
+      entries.push(Entry {
+        kind: "template",
+        status: dependent_status(workspace_dir(&path.join("main.tf")).ok()),
+        age: age(&path),
+        path,
+      });
+    }
+  }
+  if let Some(dir) = failed::dir() {
+    for path in list(&dir)? {
+      if path.extension().is_some_and(|ext| ext == "json") {
+        entries.push(Entry {
+          kind: "record",
+          status: record_status(&path),
+          age: age(&path),
+          path,
+        });
+      }
+    }
+  }
+  Ok(entries)
+}
+
+fn list(dir: &Path) -> Result<Vec<PathBuf>> {
+  if !dir.exists() {
+    return Ok(Vec::new());
+  }
+  let mut paths = fs::read_dir(dir)
+    .with_context(|| format!("Failed to read {:?}", dir))?
+    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+    .collect::<Vec<_>>();
+  paths.sort();
+  Ok(paths)
+}
+
+/// Time since `path` or, for a workspace, its state was last written.
+fn age(path: &Path) -> Duration {
+  [path.to_path_buf(), path.join("terraform.tfstate")]
+    .iter()
+    .filter_map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+    .max()
+    .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+    .unwrap_or_default()
+}
+
+fn workspace_status(workspace: &Path) -> Status {
+  if lock::is_held(workspace)
+    || workspace.join(".terraform.tfstate.lock.info").exists()
+  {
+    return Status::Active;
+  }
+  let Ok(state) = fs::read_to_string(workspace.join("terraform.tfstate"))
+  else {
+    return Status::Orphaned;
+  };
+  let Ok(state) = serde_json::from_str::<serde_json::Value>(&state) else {
+    return Status::Broken;
+  };
+  // Data sources are only read; they do not keep anything running
+  let managed = state["resources"].as_array().map_or(0, |resources| {
+    resources.iter().filter(|r| r["mode"] == "managed").count()
+  });
+  if managed > 0 {
+    Status::Deployed
+  } else {
+    Status::Destroyed
+  }
+}
+
+fn record_status(record: &Path) -> Status {
+  let workspace = fs::read_to_string(record)
+    .ok()
+    .and_then(|record| serde_json::from_str::<serde_json::Value>(&record).ok())
+    .and_then(|record| record["workspace"].as_str().map(PathBuf::from));
+  dependent_status(workspace)
+}
+
+/// A staged template or record lives as long as its workspace still needs
+/// it.
+fn dependent_status(workspace: Option<PathBuf>) -> Status {
+  match workspace {
+    Some(workspace) if workspace.is_dir() => {
+      match workspace_status(&workspace) {
+        status if status.removable() => Status::Orphaned,
+        status => status,
+      }
+    }
+    _ => Status::Orphaned,
+  }
+}
+
+fn format_age(age: Duration) -> String {
+  let secs = age.as_secs();
+  match secs {
+    0..=3599 => format!("{}m", secs / 60),
+    3600..=86399 => format!("{}h", secs / 3600),
+    _ => format!("{}d", secs / 86400),
+  }
+}
@@ -0,0 +1,241 @@
+//! A cheap authenticated call to the provider before Terraform runs, so
+//! missing or expired credentials fail at once with how to fix them,
+//! instead of after `terraform init` as a provider error.
+
+use crate::{
+  exit::{self, WithStatus},
+  Provider,
+};
+use anyhow::Result;
+use serde_json::Value;
+use std::{
+  env, fmt,
+  process::{Command, Stdio},
+  time::Duration,
+};
+
+const HETZNER_SERVERS_URL: &str =
+  "https://api.hetzner.cloud/v1/servers?per_page=1";
+const GOOGLE_TOKENINFO_URL: &str = "https://oauth2.googleapis.com/tokeninfo";
+const CHECK_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// AWS CLI errors that say nothing about the credentials, only that STS
+/// could not be reached.
+const AWS_UNREACHABLE: &[&str] =
+  &["Could not connect to the endpoint", "Connect timeout"];
+
+/// What the check found.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+  /// The provider accepted the credentials, as this identity when it says.
+  Valid(Option<String>),
+  /// Missing or rejected: the error, and how to fix it.
+  Invalid { error: String, hint: String },
+  /// Nothing to tell: no CLI to ask, the provider could not be reached,
+  /// or there is no check for it.
+  Unknown(String),
+}
+
+impl fmt::Display for Outcome {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Outcome::Valid(Some(identity)) => write!(f, "valid ({})", identity),
+      Outcome::Valid(None) => f.write_str("valid"),
+      Outcome::Invalid { error, hint } => write!(f, "{} ({})", error, hint),
+      Outcome::Unknown(reason) => write!(f, "not checked: {}", reason),
+    }
+  }
+}
+
+/// Check the credentials Terraform will use for `provider`. `region` is
+/// where the AWS CLI is pointed; STS answers in any of them.
+pub fn check(provider: &Provider, region: Option<&str>) -> Outcome {
+  match provider {
+    Provider::AWS => aws(region),
+    Provider::GCP => gcp(),
+    Provider::Hetzner => hetzner(),
+    _ => Outcome::Unknown(format!("no credential check for {:?}", provider)),
+  }
+}
+
+/// `check`, failing unless the credentials are valid or could not be
+/// checked.
+pub fn require(provider: &Provider, region: Option<&str>) -> Result<()> {
+  match check(provider, region) {
+    Outcome::Valid(identity) => {
+      log::debug!(
+        "{:?} credentials valid{}",
+        provider,
+        identity
+          .map(|id| format!(" for {}", id))
+          .unwrap_or_default()
+      );
+      Ok(())
+    }
+    Outcome::Unknown(reason) => {
+      log::debug!("{:?} credentials not checked: {}", provider, reason);
+      Ok(())
+    }
+    Outcome::Invalid { error, hint } => Err(anyhow::anyhow!(
+      "the {:?} credentials are missing or invalid: {}\n  {} (or pass \
+       --skip-credential-check to deploy anyway)",
+      provider,
+      error,
+      hint
+    ))
+    .exit_status(exit::INVALID),
+  }
+}
+
+/// STS GetCallerIdentity, through the AWS CLI so that profiles, SSO and
+/// the rest of its credential chain work as they do for Terraform.
+fn aws(region: Option<&str>) -> Outcome {
+  let mut command = Command::new("aws");
+  command.args([
+    "sts",
+    "get-caller-identity",
+    "--query",
+    "Arn",
+    "--output",
+    "text",
+  ]);
+  if let Some(region) = region {
+    command.args(["--region", region]);
+  }
+  let stderr = match run(command) {
+    Ok(Ok(arn)) => return Outcome::Valid(Some(arn)),
+    Ok(Err(stderr)) => stderr,
+    Err(reason) => return Outcome::Unknown(reason),
+  };
+  if AWS_UNREACHABLE.iter().any(|text| stderr.contains(text)) {
+    return Outcome::Unknown(stderr);
+  }
+  let profile = env::var("AWS_PROFILE")
+    .map(|profile| format!(" --profile {}", profile))
+    .unwrap_or_default();
+  let hint = if stderr.to_lowercase().contains("sso")
+    || stderr.contains("Token has expired")
+  {
+    format!("run aws sso login{}", profile)
+  } else {
+    format!(
+      "run aws configure{}, or set AWS_ACCESS_KEY_ID and \
+       AWS_SECRET_ACCESS_KEY",
+      profile
+    )
+  };
+  Outcome::Invalid {
+    error: stderr,
+    hint,
+  }
+}
+
+/// A token from the same sources the Google provider reads, checked
+/// against the tokeninfo endpoint.
+fn gcp() -> Outcome {
+  let hint = "run gcloud auth application-default login";
+  let token = if let Ok(token) = env::var("GOOGLE_OAUTH_ACCESS_TOKEN") {
+    token
+  } else if env::var_os("GOOGLE_CREDENTIALS").is_some() {
+    return Outcome::Unknown(
+      "GOOGLE_CREDENTIALS is only read by Terraform".to_string(),
+    );
+  } else {
+    // Reads GOOGLE_APPLICATION_CREDENTIALS too
+    let mut command = Command::new("gcloud");
+    command.args(["auth", "application-default", "print-access-token"]);
+    match run(command) {
+      Ok(Ok(token)) => token,
+      Ok(Err(error)) => {
+        return Outcome::Invalid {
+          error,
+          hint: hint.to_string(),
+        }
+      }
+      Err(reason) => return Outcome::Unknown(reason),
+    }
+  };
+  // In the body, where no proxy or server log keeps it
+  match agent()
+    .post(GOOGLE_TOKENINFO_URL)
+    .send_form([("access_token", token.as_str())])
+  {
+    Ok(mut response) => {
+      let info: Option<Value> = response
+        .body_mut()
+        .read_to_string()
+        .ok()
+        .and_then(|body| serde_json::from_str(&body).ok());
+      let email = info
+        .as_ref()
+        .and_then(|info| info["email"].as_str())
+        .map(str::to_string);
+      Outcome::Valid(email)
+    }
+    Err(ureq::Error::StatusCode(code)) if (400..500).contains(&code) => {
+      Outcome::Invalid {
+        error: "the access token was rejected".to_string(),
+        hint: hint.to_string(),
+      }
+    }
+    Err(err) => Outcome::Unknown(redact(&err.to_string(), &token)),
+  }
+}
+
+/// The cheapest authenticated call of the Hetzner API: one server.
+fn hetzner() -> Outcome {
+  let hint = "set HCLOUD_TOKEN (which fuoco passes to Terraform as \
+              TF_VAR_token) to an API token of the project, from Security > \
+              API tokens in the Hetzner Console";
+  let Ok(token) = crate::hetzner_token() else {
+    return Outcome::Invalid {
+      error: "TF_VAR_token is not set".to_string(),
+      hint: hint.to_string(),
+    };
+  };
+  match agent()
+    .get(HETZNER_SERVERS_URL)
+    .header("Authorization", format!("Bearer {}", token))
+    .call()
+  {
+    Ok(_) => Outcome::Valid(None),
+    Err(ureq::Error::StatusCode(code @ (401 | 403))) => Outcome::Invalid {
+      error: format!("the token was rejected (HTTP {})", code),
+      hint: hint.to_string(),
+    },
+    Err(err) => Outcome::Unknown(err.to_string()),
+  }
+}
+
+/// `text` with `secret` masked, for errors that may quote a request.
+fn redact(text: &str, secret: &str) -> String {
+  if secret.is_empty() {
+    return text.to_string();
+  }
+  text.replace(secret, "<redacted>")
+}
+
+fn agent() -> ureq::Agent {
+  ureq::Agent::config_builder()
+    .timeout_global(Some(CHECK_TIMEOUT))
+    .build()
+    .into()
+}
+
+/// What `command` prints on success, or the first line of its stderr on
+/// failure; `Err` when it could not be run at all.
+fn run(mut command: Command) -> Result<Result<String, String>, String> {
+  let program = command.get_program().to_string_lossy().into_owned();
+  let output = command
+    .stdin(Stdio::null())
+    .output()
+    .map_err(|_| format!("could not run {} (is it installed?)", program))?;
+  if !output.status.success() {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let line = stderr.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+    return Ok(Err(format!("{} failed: {}", program, line.trim())));
+  }
+  Ok(Ok(
+    String::from_utf8_lossy(&output.stdout).trim().to_string(),
+  ))
+}
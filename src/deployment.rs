@@ -0,0 +1,707 @@
+//! The deploy/destroy lifecycle on its own: no prompts, signal handlers or
+//! panic hooks, so the caller decides when the deployment goes away.
+
+use crate::{
+  capture, credentials,
+  events::Event,
+  exit::{self, WithStatus},
+  failed, location, lock, provider_str, regions, resolve_region, retry, task,
+  timings::Timings,
+  undeploy_command,
+  watchdog::{self, KillHandle},
+  workspace, workspace_dir, DeployParams,
+};
+use anyhow::{Context, Result};
+use atar::{deploy as lib_deploy, undeploy as lib_undeploy};
+use sha2::{Digest, Sha256};
+use std::{
+  collections::HashMap,
+  fs, io,
+  path::Path,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant},
+};
+
+/// Retries for a destroy whatever the error: giving up leaks a paid VM.
+const DESTROY_RETRIES: u32 = 3;
+
+/// What a destroy gets even once --timeout has run out: stopping it any
+/// sooner would leak the resources it was tearing down.
+const MIN_DESTROY_TIME: Duration = Duration::from_secs(5 * 60);
+
+/// The SHA-256 of the template a workspace was set up with, kept inside it.
+const TEMPLATE_HASH_FILE: &str = ".fuoco-template.sha256";
+
+/// What a kept workspace must lose to start from scratch: the state of the
+/// previous deployment. `.terraform` keeps the initialized providers.
+const STATE_FILES: &[&str] = &["terraform.tfstate", "terraform.tfstate.backup"];
+
+/// What creates the resources of a deployment, given its params,
+/// Terraform variables and the handle that stops it, and returns the
+/// Terraform outputs.
+pub type Apply = Arc<
+  dyn Fn(
+      &DeployParams,
+      &HashMap<String, String>,
+      &KillHandle,
+    ) -> Result<HashMap<String, String>>
+    + Send
+    + Sync,
+>;
+
+/// What destroys the resources of a deployment, given its params and
+/// Terraform variables.
+pub type Destroy = Arc<
+  dyn Fn(&DeployParams, &HashMap<String, String>) -> Result<()> + Send + Sync,
+>;
+
+/// A deployment that has not been applied yet.
+pub struct Deployment {
+  params: DeployParams,
+  /// The Terraform variables, resolved once so the random region that was
+  /// confirmed is the one deployed.
+  hash_map: HashMap<String, String>,
+  apply: Apply,
+  destroy: Destroy,
+  kill: KillHandle,
+}
+
+impl Deployment {
+  pub fn new(params: DeployParams) -> Self {
+    let hash_map = params.to_atar_map();
+    Deployment {
+      params,
+      hash_map,
+      apply: Arc::new(apply),
+      destroy: Arc::new(undeploy),
+      kill: KillHandle::default(),
+    }
+  }
+
+  /// Stop the apply when `kill` is used, e.g. from another thread: its
+  /// Terraform is stopped, what it created destroyed and `deploy` fails
+  /// with `watchdog::Killed`. Other deployments are left alone.
+  pub fn with_kill_handle(mut self, kill: KillHandle) -> Self {
+    self.kill = kill;
+    self
+  }
+
+  /// Create the resources with `apply` instead of `terraform apply`.
+  pub fn with_apply(mut self, apply: Apply) -> Self {
+    self.apply = apply;
+    self
+  }
+
+  /// Destroy the resources with `destroy` instead of `terraform destroy`.
+  /// Whichever way the deployment ends, it is called at most once.
+  pub fn with_destroy(mut self, destroy: Destroy) -> Self {
+    self.destroy = destroy;
+    self
+  }
+
+  pub fn params(&self) -> &DeployParams {
+    &self.params
+  }
+
+  /// The variables Terraform will be applied with.
+  pub fn variables(&self) -> &HashMap<String, String> {
+    &self.hash_map
+  }
+
+  /// Fail unless the provider accepts the credentials Terraform will use,
+  /// or they cannot be checked. Nothing with --skip-credential-check.
+  pub fn check_credentials(&self) -> Result<()> {
+    if self.params.skip_credential_check {
+      return Ok(());
+    }
+    credentials::require(
+      &self.params.provider,
+      self.hash_map.get("region").map(String::as_str),
+    )
+  }
+
+  /// Prepare the workspace, run the pre-deploy hook and apply the template.
+  /// A failed apply destroys whatever it created before returning.
+  pub fn deploy(self) -> Result<DeployHandle> {
+    let Deployment {
+      params,
+      mut hash_map,
+      apply,
+      destroy,
+      kill,
+    } = self;
+    let timings = Arc::new(Timings::default());
+    let lock = timings.time("prepare", || -> Result<_> {
+      let template = params.identity(&hash_map).stage()?;
+      if params.region_rtt.is_some() || params.region_seed.is_some() {
+        if let Err(err) = workspace::record_region_choice(
+          &template,
+          params.region_rtt,
+          params.region_seed,
+        ) {
+          log::warn!("could not record how the region was picked: {:#}", err);
+        }
+      }
+      let lock = lock::acquire(&workspace_dir(&template)?, params.wait_lock)?;
+      prepare_workspace(&params, &template)?;
+      params.run_hook(
+        "pre-deploy",
+        &params.pre_deploy_hook,
+        &HashMap::new(),
+      )?;
+      Ok(lock)
+    })?;
+    // Nothing was created yet, so there is nothing to destroy
+    kill.check("terraform apply")?;
+    params.emit(Event::DeployStarted);
+    let outputs = match timings.time("apply", || {
+      apply_in_zones(&params, &mut hash_map, &apply, &destroy, &kill)
+    }) {
+      Ok(outputs) => outputs,
+      Err(err) => {
+        params.emit(Event::DeployFailed {
+          error: err.to_string(),
+        });
+        let code = if destroy_partial_deploy(&params, &hash_map, &destroy) {
+          exit::DEPLOY_FAILED
+        } else {
+          exit::LEAKED
+        };
+        // A timeout keeps its own status
+        if err.is::<watchdog::TimedOut>() {
+          return Err(err);
+        }
+        return Err(err).exit_status(code);
+      }
+    };
+    params.emit(Event::DeploySucceeded { outputs: &outputs });
+    let handle = DeployHandle {
+      state: Arc::new(State {
+        params,
+        hash_map,
+        outputs,
+        destroy,
+        destroyed: AtomicBool::new(false),
+        timings,
+        lock: Mutex::new(Some(lock)),
+      }),
+      deployed_at: Instant::now(),
+    };
+    // The handle owns the cleanup from here on, so a failing hook only warns
+    let params = handle.params();
+    if let Err(err) =
+      params.run_hook("post-deploy", &params.post_deploy_hook, handle.outputs())
+    {
+      log::warn!("{:#}", err);
+    }
+    params.run_callback(handle.outputs());
+    Ok(handle)
+  }
+}
+
+/// A live deployment. Dropping it destroys the resources; `keep` leaves
+/// them running instead. A destroy that fails for good is recorded by
+/// `failed` with the command to retry it.
+pub struct DeployHandle {
+  state: Arc<State>,
+  deployed_at: Instant,
+}
+
+impl DeployHandle {
+  pub fn params(&self) -> &DeployParams {
+    &self.state.params
+  }
+
+  /// The variables the deployment was applied with.
+  pub fn variables(&self) -> &HashMap<String, String> {
+    &self.state.hash_map
+  }
+
+  /// Terraform outputs, as strings.
+  pub fn outputs(&self) -> &HashMap<String, String> {
+    &self.state.outputs
+  }
+
+  pub fn uptime(&self) -> Duration {
+    self.deployed_at.elapsed()
+  }
+
+  /// When the apply succeeded.
+  pub fn deployed_at(&self) -> Instant {
+    self.deployed_at
+  }
+
+  /// How long each phase took so far; destroying adds to it.
+  pub fn timings(&self) -> Arc<Timings> {
+    self.state.timings.clone()
+  }
+
+  /// A handle that can destroy the deployment from elsewhere, e.g. a panic
+  /// hook or another thread.
+  pub fn destroyer(&self) -> Destroyer {
+    Destroyer {
+      state: self.state.clone(),
+    }
+  }
+
+  /// Run the pre-destroy steps and destroy the resources.
+  pub fn destroy(self) -> Result<()> {
+    self.state.destroy()
+  }
+
+  /// `destroy` on a worker thread, as a future. The destroy goes on even
+  /// if the future is dropped.
+  pub fn destroy_async(self) -> task::Task<Result<()>> {
+    task::spawn(move || self.destroy())
+  }
+
+  /// Leave the resources running: the workspace keeps the state for
+  /// `fuoco undeploy`, which may run in this same process.
+  pub fn keep(self) {
+    self.state.params.emit(Event::Detached);
+    self.state.lock.lock().unwrap().take();
+    std::mem::forget(self);
+  }
+}
+
+impl Drop for DeployHandle {
+  fn drop(&mut self) {
+    // Already done through destroy() or a Destroyer
+    if self.state.destroyed.load(Ordering::SeqCst) {
+      return;
+    }
+    if let Err(err) = self.state.destroy() {
+      log::error!("Failed to destroy Terraform resources: {}", err);
+    }
+  }
+}
+
+/// Destroys the deployment of the `DeployHandle` it came from.
+#[derive(Clone)]
+pub struct Destroyer {
+  state: Arc<State>,
+}
+
+impl Destroyer {
+  /// Whether a destroy has started, from here or from the handle.
+  pub fn started(&self) -> bool {
+    self.state.destroyed.load(Ordering::SeqCst)
+  }
+
+  pub fn destroy(&self) -> Result<()> {
+    self.state.destroy()
+  }
+}
+
+struct State {
+  params: DeployParams,
+  hash_map: HashMap<String, String>,
+  outputs: HashMap<String, String>,
+  destroy: Destroy,
+  destroyed: AtomicBool,
+  timings: Arc<Timings>,
+  /// Held until the resources are destroyed or the handle is kept.
+  lock: Mutex<Option<lock::WorkspaceLock>>,
+}
+
+impl State {
+  /// Run the pre-destroy steps and `lib_undeploy`, unless another exit path
+  /// already did: a second destroy would race the first or fail against an
+  /// empty state.
+  fn destroy(&self) -> Result<()> {
+    if self.destroyed.swap(true, Ordering::SeqCst) {
+      status!("Resources already destroyed");
+      return Ok(());
+    }
+    let params = &self.params;
+    if params.teardown.is_some() || params.pre_destroy_hook.is_some() {
+      self
+        .timings
+        .time("teardown", || params.run_pre_destroy_hook(&self.outputs));
+    }
+    params.emit(Event::DestroyStarted);
+    match self
+      .timings
+      .time("destroy", || (self.destroy)(params, &self.hash_map))
+    {
+      Ok(()) => {
+        params.emit(Event::DestroySucceeded);
+        Ok(())
+      }
+      Err(err) => {
+        params.emit(Event::DestroyFailed {
+          error: err.to_string(),
+        });
+        record_failed_destroy(params, &self.hash_map, &err);
+        Err(err).exit_status(exit::DESTROY_FAILED)
+      }
+    }
+  }
+}
+
+/// `lib_deploy` with retries, under --deploy-timeout and what is left of
+/// --timeout: a zone out of capacity can keep an apply going for half an
+/// hour before it fails.
+fn apply(
+  params: &DeployParams,
+  hash_map: &HashMap<String, String>,
+  kill: &KillHandle,
+) -> Result<HashMap<String, String>> {
+  let template = params.identity(hash_map).template();
+  let workspace = workspace_dir(&template)?;
+  let params = params.clone();
+  let hash_map = hash_map.clone();
+  let mark = capture::mark();
+  let remaining = params.deadline.map(until);
+  let timeout = match (params.deploy_timeout, remaining) {
+    (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+    (timeout, remaining) => timeout.or(remaining),
+  };
+  let result = watchdog::run_in(
+    "terraform apply",
+    &workspace,
+    timeout,
+    kill,
+    move |stopped| {
+      retry::with_retries("terraform apply", params.retries, stopped, || {
+        lib_deploy(&template, &hash_map, params.verbosity.terraform())
+      })
+    },
+  );
+  capture::attach(result, mark)
+}
+
+/// `apply`, then with --az-fallback again in each other availability zone
+/// of the region for as long as the zone tried has no capacity, destroying
+/// each failed attempt first. `hash_map` is left with the zone last tried.
+fn apply_in_zones(
+  params: &DeployParams,
+  hash_map: &mut HashMap<String, String>,
+  apply: &Apply,
+  destroy: &Destroy,
+  kill: &KillHandle,
+) -> Result<HashMap<String, String>> {
+  let mut result = apply(params, hash_map, kill);
+  if !params.az_fallback || !out_of_capacity(&result) {
+    return result;
+  }
+  let mut zones = match regions::aws_availability_zones(&hash_map["region"]) {
+    Ok(zones) => zones,
+    Err(err) => {
+      log::warn!("--az-fallback: could not list the zones: {:#}", err);
+      return result;
+    }
+  };
+  // Without --availability-zone, AWS's pick is known only if the error
+  // names it
+  let error = result.as_ref().err().map(|err| format!("{:#}", err));
+  let mut tried = hash_map.get("availability_zone").cloned().or_else(|| {
+    let error = error.unwrap_or_default();
+    zones
+      .iter()
+      .find(|zone| error.contains(zone.as_str()))
+      .cloned()
+  });
+  // The zones after the one tried, then those before it
+  if let Some(position) =
+    zones.iter().position(|zone| Some(zone) == tried.as_ref())
+  {
+    zones.rotate_left(position + 1);
+    zones.pop();
+  }
+  for zone in zones {
+    if !out_of_capacity(&result) {
+      break;
+    }
+    log::warn!(
+      "no capacity for {} in {}; trying {}",
+      hash_map["instance_type"],
+      tried.as_deref().unwrap_or("the zone AWS picked"),
+      zone
+    );
+    if !destroy_partial_deploy(params, hash_map, destroy) {
+      return result;
+    }
+    hash_map.insert("availability_zone".to_string(), zone.clone());
+    result = apply(params, hash_map, kill);
+    tried = Some(zone);
+  }
+  result
+}
+
+fn out_of_capacity<T>(result: &Result<T>) -> bool {
+  matches!(result, Err(err) if retry::is_capacity_error(err))
+}
+
+/// `lib_undeploy`, retried on any error since a destroy that gives up
+/// leaks the VM, but under --destroy-timeout so it cannot hang forever.
+fn undeploy(
+  params: &DeployParams,
+  hash_map: &HashMap<String, String>,
+) -> Result<()> {
+  let template = params.identity(hash_map).template();
+  let workspace = workspace_dir(&template)?;
+  let timeout = match params.deadline {
+    Some(deadline) => params
+      .destroy_timeout
+      .min(until(deadline).max(MIN_DESTROY_TIME)),
+    None => params.destroy_timeout,
+  };
+  let debug = params.verbosity.terraform();
+  let retries = params.retries.max(DESTROY_RETRIES);
+  let hash_map = hash_map.clone();
+  let mark = capture::mark();
+  // Never killed: the destroy is what a killed apply ends with
+  let result = watchdog::run_in(
+    "terraform destroy",
+    &workspace,
+    Some(timeout),
+    &KillHandle::default(),
+    move |stopped| {
+      retry::retry_when(
+        "terraform destroy",
+        retries,
+        stopped,
+        |_| true,
+        || lib_undeploy(&template, &hash_map, debug),
+      )
+    },
+  );
+  capture::attach(result, mark)?;
+  failed::clear(&workspace);
+  Ok(())
+}
+
+fn until(deadline: Instant) -> Duration {
+  deadline.saturating_duration_since(Instant::now())
+}
+
+/// The resources may still be up and billing: say how to retry, and keep
+/// a record that outlives the terminal.
+fn record_failed_destroy(
+  params: &DeployParams,
+  hash_map: &HashMap<String, String>,
+  err: &anyhow::Error,
+) {
+  let undeploy = undeploy_command(params, hash_map);
+  eprintln!();
+  log::error!(
+    "the resources could NOT be destroyed and may still be running. Retry \
+     with:\n  {}",
+    undeploy
+  );
+  let workspace = workspace_dir(&params.identity(hash_map).template())
+    .unwrap_or_else(|_| Path::new("").to_path_buf());
+  let record = failed::Record {
+    name: &params.name,
+    provider: provider_str(&params.provider),
+    region: location(hash_map),
+    workspace: &workspace,
+    error: format!("{:#}", err),
+    undeploy,
+  };
+  match failed::save(&record) {
+    Ok(path) => eprintln!("Recorded in {}", path.display()),
+    Err(err) => {
+      log::warn!("could not record the failed destroy: {:#}", err)
+    }
+  }
+}
+
+/// Start from an empty state, or with `--reuse` keep the existing one so
+/// Terraform only changes what differs. The workspace itself, with the
+/// providers `terraform init` set up, is kept unless the template changed
+/// or `--fresh-workspace` asks for a new one.
+fn prepare_workspace(params: &DeployParams, template: &Path) -> Result<()> {
+  let work = workspace_dir(template)?;
+  let content = fs::read(template)
+    .with_context(|| format!("Failed to read {:?}", template))?;
+  let hash = format!("{:x}", Sha256::digest(&content));
+  let same_template = fs::read_to_string(work.join(TEMPLATE_HASH_FILE))
+    .is_ok_and(|stored| stored.trim() == hash);
+  if params.reuse && work.exists() {
+    // Keep the state, but bring the template up to date
+    fs::copy(template, work.join("main.tf"))
+      .context("Failed to refresh the template in the Terraform workspace")?;
+    if resolve_region(&params.provider, params.region.as_deref()).is_none() {
+      log::warn!(
+        "--reuse without --region picks a new random region, and with it \
+         a new workspace: there is no earlier deployment to reuse"
+      );
+    }
+  } else if work.exists() && same_template && !params.fresh_workspace {
+    for file in STATE_FILES {
+      match fs::remove_file(work.join(file)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+          return Err(err).context("Failed to reset the Terraform state");
+        }
+        _ => {}
+      }
+    }
+    log::debug!("Keeping the Terraform workspace {:?}", work);
+  } else if work.exists() {
+    // Empty the cached Terraform workspace so template changes are picked up
+    lock::remove_all_but_lock(&work)
+      .context("Failed to remove stale Terraform workspace")?;
+  }
+  fs::create_dir_all(&work)
+    .context("Failed to create the Terraform workspace")?;
+  fs::write(work.join(TEMPLATE_HASH_FILE), hash)
+    .context("Failed to record the template hash in the workspace")?;
+  Ok(())
+}
+
+/// A failed apply can leave some resources behind (say, the instance but
+/// not its security group), so tear down whatever made it into the state.
+/// Returns whether that worked.
+fn destroy_partial_deploy(
+  params: &DeployParams,
+  hash_map: &HashMap<String, String>,
+  destroy: &Destroy,
+) -> bool {
+  log::warn!("Deploy failed: destroying any partially created resources...");
+  params.emit(Event::DestroyStarted);
+  match destroy(params, hash_map) {
+    Ok(()) => {
+      params.emit(Event::DestroySucceeded);
+      true
+    }
+    Err(err) => {
+      params.emit(Event::DestroyFailed {
+        error: err.to_string(),
+      });
+      record_failed_destroy(params, hash_map, &err);
+      false
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Provider;
+  use std::{
+    env, panic, path::PathBuf, process, sync::atomic::AtomicUsize, thread,
+  };
+
+  fn workspace() -> PathBuf {
+    static WORKSPACES: AtomicUsize = AtomicUsize::new(0);
+    env::temp_dir().join(format!(
+      "fuoco-test-{}-{}",
+      process::id(),
+      WORKSPACES.fetch_add(1, Ordering::SeqCst)
+    ))
+  }
+
+  /// A deployment handed out as if applied, without Terraform, and how
+  /// many times its resources were destroyed.
+  fn deployed() -> (DeployHandle, Arc<AtomicUsize>) {
+    let workspace = workspace();
+    let deployed = deployed_in(&workspace);
+    // The lock holds on to its file; nothing else needs the directory
+    let _ = fs::remove_dir_all(&workspace);
+    deployed
+  }
+
+  /// `deployed`, holding the lock on `workspace`.
+  fn deployed_in(workspace: &Path) -> (DeployHandle, Arc<AtomicUsize>) {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let params = DeployParams::new(Provider::Hetzner).unwrap();
+    let Deployment {
+      params,
+      hash_map,
+      destroy,
+      ..
+    } = Deployment::new(params).with_destroy(Arc::new(move |_, _| {
+      counter.fetch_add(1, Ordering::SeqCst);
+      // Long enough for a racing caller to arrive mid-destroy
+      thread::sleep(Duration::from_millis(50));
+      Ok(())
+    }));
+    let lock = lock::acquire(workspace, None).unwrap();
+    let handle = DeployHandle {
+      state: Arc::new(State {
+        params,
+        hash_map,
+        outputs: HashMap::new(),
+        destroy,
+        destroyed: AtomicBool::new(false),
+        timings: Arc::new(Timings::default()),
+        lock: Mutex::new(Some(lock)),
+      }),
+      deployed_at: Instant::now(),
+    };
+    (handle, calls)
+  }
+
+  #[test]
+  fn dropping_the_handle_destroys_once() {
+    let (handle, calls) = deployed();
+    drop(handle);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+  }
+
+  #[test]
+  fn destroy_then_drop_destroys_once() {
+    let (handle, calls) = deployed();
+    let destroyer = handle.destroyer();
+    handle.destroy().unwrap();
+    assert!(destroyer.started());
+    destroyer.destroy().unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+  }
+
+  #[test]
+  fn kept_handle_is_not_destroyed() {
+    let (handle, calls) = deployed();
+    handle.keep();
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+  }
+
+  /// `undeploy` locks the workspace before anything else.
+  #[test]
+  fn kept_handle_can_be_undeployed() {
+    let workspace = workspace();
+    let (handle, _) = deployed_in(&workspace);
+    assert!(lock::acquire(&workspace, None).is_err());
+    handle.keep();
+    lock::acquire(&workspace, None).unwrap();
+    let _ = fs::remove_dir_all(&workspace);
+  }
+
+  /// A panic runs the hook, which destroys through a `Destroyer`, then
+  /// unwinds through the handle.
+  #[test]
+  fn panic_destroys_once() {
+    let (handle, calls) = deployed();
+    let destroyer = handle.destroyer();
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(move || {
+      let _handle = handle;
+      destroyer.destroy().unwrap();
+      panic!("simulated");
+    }));
+    assert!(result.is_err());
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+  }
+
+  /// A signal has the main thread destroy the handle while, say, a panic
+  /// on another thread destroys through a `Destroyer`.
+  #[test]
+  fn signal_racing_another_destroy_destroys_once() {
+    let (handle, calls) = deployed();
+    let destroyers: Vec<_> = (0..4).map(|_| handle.destroyer()).collect();
+    let racers: Vec<_> = destroyers
+      .into_iter()
+      .map(|destroyer| thread::spawn(move || destroyer.destroy()))
+      .collect();
+    handle.destroy().unwrap();
+    for racer in racers {
+      racer.join().unwrap().unwrap();
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+  }
+}
@@ -0,0 +1,216 @@
+//! Machine-readable lifecycle events, one JSON object per line on stderr,
+//! or on stdout with `--format json-events`.
+
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use serde_json::Value;
+use std::{
+  collections::HashMap,
+  io::{self, Write},
+  sync::atomic::{AtomicBool, Ordering},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+static TO_STDOUT: AtomicBool = AtomicBool::new(false);
+
+/// Lifecycle points reported with `--events`, named by `type`.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event<'a> {
+  DeployStarted,
+  DeploySucceeded {
+    outputs: &'a HashMap<String, String>,
+  },
+  DeployFailed {
+    error: String,
+  },
+  WaitingForSignal,
+  SignalReceived,
+  /// fuoco exited and left the deployment running (--keep, a declined
+  /// destroy prompt or --on-hangup keep).
+  Detached,
+  InstanceLost,
+  /// --idle-timeout expired; the destroy follows.
+  IdleTimeout,
+  /// --timeout expired; the destroy follows.
+  Timeout,
+  DestroyStarted,
+  DestroySucceeded,
+  DestroyFailed {
+    error: String,
+  },
+}
+
+/// An event as `--format json-events` prints it.
+#[derive(Serialize)]
+struct Envelope<'a> {
+  /// Seconds since the Unix epoch.
+  timestamp: u64,
+  deployment: &'a str,
+  #[serde(flatten)]
+  event: &'a Event<'a>,
+}
+
+/// An event as `--events` and `DeployParams::progress` have always had it,
+/// naming it `event` rather than `type`.
+struct Legacy<'a>(Envelope<'a>);
+
+impl Serialize for Legacy<'_> {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut fields = match serde_json::to_value(self.0.event) {
+      Ok(Value::Object(fields)) => fields,
+      _ => return Err(serde::ser::Error::custom("an event is an object")),
+    };
+    let kind = fields.remove("type");
+    let mut map = serializer.serialize_map(Some(fields.len() + 3))?;
+    map.serialize_entry("timestamp", &self.0.timestamp)?;
+    map.serialize_entry("deployment", self.0.deployment)?;
+    map.serialize_entry("event", &kind)?;
+    for (key, value) in &fields {
+      map.serialize_entry(key, value)?;
+    }
+    map.end()
+  }
+}
+
+/// Write the events on stdout, which then carries nothing else.
+pub fn to_stdout() {
+  TO_STDOUT.store(true, Ordering::Relaxed);
+}
+
+/// `event` for the deployment `name` as the single JSON line `--events`
+/// prints.
+pub fn line(name: &str, event: &Event) -> Option<String> {
+  serde_json::to_string(&Legacy(envelope(now(), name, event))).ok()
+}
+
+/// `event` for the deployment `name` as the single JSON line
+/// `--format json-events` prints.
+pub fn typed_line(name: &str, event: &Event) -> Option<String> {
+  serde_json::to_string(&envelope(now(), name, event)).ok()
+}
+
+fn envelope<'a>(
+  timestamp: u64,
+  deployment: &'a str,
+  event: &'a Event<'a>,
+) -> Envelope<'a> {
+  Envelope {
+    timestamp,
+    deployment,
+    event,
+  }
+}
+
+fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |d| d.as_secs())
+}
+
+/// Write `event` for the deployment `name` as a single JSON line.
+pub fn emit(name: &str, event: &Event) {
+  let stdout = TO_STDOUT.load(Ordering::Relaxed);
+  let line = if stdout {
+    typed_line(name, event)
+  } else {
+    line(name, event)
+  };
+  let Some(line) = line else {
+    return;
+  };
+  if stdout {
+    // Flushed at once: a CI wrapper reacts to each event as it comes
+    let mut stdout = io::stdout().lock();
+    let _ = writeln!(stdout, "{}", line).and_then(|()| stdout.flush());
+  } else {
+    eprintln!("{}", line);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn typed(event: &Event) -> String {
+    serde_json::to_string(&envelope(1760000000, "box", event)).unwrap()
+  }
+
+  fn legacy(event: &Event) -> String {
+    serde_json::to_string(&Legacy(envelope(1760000000, "box", event))).unwrap()
+  }
+
+  #[test]
+  fn every_event_has_a_stable_type() {
+    let outputs = HashMap::new();
+    let events = [
+      (Event::DeployStarted, "deploy_started"),
+      (
+        Event::DeploySucceeded { outputs: &outputs },
+        "deploy_succeeded",
+      ),
+      (
+        Event::DeployFailed {
+          error: String::new(),
+        },
+        "deploy_failed",
+      ),
+      (Event::WaitingForSignal, "waiting_for_signal"),
+      (Event::SignalReceived, "signal_received"),
+      (Event::Detached, "detached"),
+      (Event::InstanceLost, "instance_lost"),
+      (Event::IdleTimeout, "idle_timeout"),
+      (Event::Timeout, "timeout"),
+      (Event::DestroyStarted, "destroy_started"),
+      (Event::DestroySucceeded, "destroy_succeeded"),
+      (
+        Event::DestroyFailed {
+          error: String::new(),
+        },
+        "destroy_failed",
+      ),
+    ];
+    for (event, kind) in &events {
+      let line: Value = serde_json::from_str(&typed(event)).unwrap();
+      assert_eq!(line["type"], *kind);
+      assert_eq!(line["timestamp"], 1760000000);
+      assert_eq!(line["deployment"], "box");
+      assert!(line.get("event").is_none());
+    }
+  }
+
+  #[test]
+  fn json_events_lines() {
+    let outputs =
+      HashMap::from([("public_ip".to_string(), "203.0.113.7".to_string())]);
+    assert_eq!(
+      typed(&Event::DeploySucceeded { outputs: &outputs }),
+      r#"{"timestamp":1760000000,"deployment":"box","type":"deploy_succeeded","outputs":{"public_ip":"203.0.113.7"}}"#
+    );
+    assert_eq!(
+      typed(&Event::DestroyFailed {
+        error: "timed out".to_string()
+      }),
+      r#"{"timestamp":1760000000,"deployment":"box","type":"destroy_failed","error":"timed out"}"#
+    );
+    assert_eq!(
+      typed(&Event::SignalReceived),
+      r#"{"timestamp":1760000000,"deployment":"box","type":"signal_received"}"#
+    );
+  }
+
+  #[test]
+  fn events_lines_keep_the_event_key() {
+    let outputs =
+      HashMap::from([("public_ip".to_string(), "203.0.113.7".to_string())]);
+    assert_eq!(
+      legacy(&Event::DeploySucceeded { outputs: &outputs }),
+      r#"{"timestamp":1760000000,"deployment":"box","event":"deploy_succeeded","outputs":{"public_ip":"203.0.113.7"}}"#
+    );
+    assert_eq!(
+      legacy(&Event::DeployFailed {
+        error: "quota".to_string()
+      }),
+      r#"{"timestamp":1760000000,"deployment":"box","event":"deploy_failed","error":"quota"}"#
+    );
+  }
+}
@@ -0,0 +1,119 @@
+//! Exit statuses, so a script can tell what a failure left behind. The
+//! status travels up to `main` inside the error; anything else exits 1.
+
+use anyhow::Result;
+use std::fmt;
+
+/// The arguments or configuration were rejected before anything was
+/// deployed or destroyed; clap uses it for usage errors too.
+pub const INVALID: i32 = 2;
+
+/// The user gave up on a destroy that would not finish.
+pub const DESTROY_ABANDONED: i32 = 3;
+
+/// --deploy-timeout expired (after cleaning up).
+pub const DEPLOY_TIMEOUT: i32 = 4;
+
+/// terraform apply failed, and whatever it created was destroyed.
+pub const DEPLOY_FAILED: i32 = 5;
+
+/// terraform apply failed, and so did destroying what it created.
+pub const LEAKED: i32 = 6;
+
+/// Destroying a deployment failed; its resources may still be up.
+pub const DESTROY_FAILED: i32 = 7;
+
+/// --timeout expired while the deployment was up, and it was destroyed.
+pub const TIMEOUT: i32 = 8;
+
+/// `error`, to exit with `code`.
+#[derive(Debug)]
+pub struct Status {
+  pub code: i32,
+  error: anyhow::Error,
+}
+
+/// Shown as the error it wraps.
+impl fmt::Display for Status {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.error)
+  }
+}
+
+impl std::error::Error for Status {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    self.error.chain().nth(1)
+  }
+}
+
+pub trait WithStatus<T> {
+  /// Exit with `code` on error, unless the error already has a status.
+  fn exit_status(self, code: i32) -> Result<T>;
+}
+
+impl<T> WithStatus<T> for Result<T> {
+  fn exit_status(self, code: i32) -> Result<T> {
+    self.map_err(|error| {
+      if error.is::<Status>() {
+        error
+      } else {
+        Status { code, error }.into()
+      }
+    })
+  }
+}
+
+/// The status to exit with after `err`.
+pub fn code(err: &anyhow::Error) -> i32 {
+  if let Some(status) = err.downcast_ref::<Status>() {
+    return status.code;
+  }
+  match err.downcast_ref::<crate::watchdog::TimedOut>() {
+    Some(timed_out) if timed_out.what == "terraform apply" => DEPLOY_TIMEOUT,
+    _ => 1,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::watchdog::TimedOut;
+  use std::time::Duration;
+
+  #[test]
+  fn errors_without_a_status_exit_1() {
+    assert_eq!(code(&anyhow::anyhow!("boom")), 1);
+  }
+
+  #[test]
+  fn exit_status_sets_the_code() {
+    let err = Err::<(), _>(anyhow::anyhow!("bad --name"))
+      .exit_status(INVALID)
+      .unwrap_err();
+    assert_eq!(code(&err), INVALID);
+    assert_eq!(err.to_string(), "bad --name");
+  }
+
+  /// The status closest to the failure wins: a cleanup failure stays
+  /// LEAKED even where the caller marks everything else INVALID.
+  #[test]
+  fn inner_status_is_kept() {
+    let err = Err::<(), _>(anyhow::anyhow!("destroy failed"))
+      .exit_status(LEAKED)
+      .exit_status(INVALID)
+      .unwrap_err();
+    assert_eq!(code(&err), LEAKED);
+  }
+
+  #[test]
+  fn deploy_timeout_has_its_own_status() {
+    let timed_out = |what| {
+      anyhow::Error::new(TimedOut {
+        what,
+        after: Duration::from_secs(60),
+      })
+    };
+    assert_eq!(code(&timed_out("terraform apply")), DEPLOY_TIMEOUT);
+    assert_eq!(code(&timed_out("terraform destroy")), 1);
+  }
+}
@@ -0,0 +1,64 @@
+//! Records of destroys that failed, so a leaked VM is not forgotten once the
+//! terminal scrolls away. One file per Terraform workspace, removed again
+//! once a destroy there succeeds.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{
+  env, fs,
+  path::{Path, PathBuf},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Serialize)]
+pub struct Record<'a> {
+  pub name: &'a str,
+  pub provider: &'a str,
+  pub region: &'a str,
+  pub workspace: &'a Path,
+  pub error: String,
+  /// The command that retries the destroy.
+  pub undeploy: String,
+}
+
+/// `$XDG_DATA_HOME/fuoco/failed`, falling back to
+/// `~/.local/share/fuoco/failed`.
+pub fn dir() -> Option<PathBuf> {
+  let base = env::var_os("XDG_DATA_HOME")
+    .filter(|dir| !dir.is_empty())
+    .map(PathBuf::from)
+    .or_else(|| {
+      env::var_os("HOME").map(|home| Path::new(&home).join(".local/share"))
+    })?;
+  Some(base.join("fuoco").join("failed"))
+}
+
+fn path_for(workspace: &Path) -> Option<PathBuf> {
+  let key = workspace.file_name()?.to_string_lossy().into_owned();
+  Some(dir()?.join(format!("{}.json", key)))
+}
+
+/// Write `record`, replacing any earlier one for the same workspace.
+pub fn save(record: &Record) -> Result<PathBuf> {
+  let path = path_for(record.workspace)
+    .context("Cannot determine the fuoco data directory")?;
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)
+      .with_context(|| format!("Failed to create {:?}", parent))?;
+  }
+  let timestamp = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |d| d.as_secs());
+  let mut value = serde_json::to_value(record)?;
+  value["timestamp"] = timestamp.into();
+  fs::write(&path, serde_json::to_string_pretty(&value)?)
+    .with_context(|| format!("Failed to write {:?}", path))?;
+  Ok(path)
+}
+
+/// Forget the failure recorded for `workspace`, if any.
+pub fn clear(workspace: &Path) {
+  if let Some(path) = path_for(workspace) {
+    let _ = fs::remove_file(path);
+  }
+}
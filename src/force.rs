@@ -0,0 +1,101 @@
+//! `undeploy --force`: destroy what Terraform can still reach in a
+//! workspace whose state is corrupt or refers to resources deleted by hand.
+
+use std::{
+  collections::HashMap,
+  path::Path,
+  process::{Command, Stdio},
+};
+
+/// Resource addresses recorded in the workspace state, or `None` when the
+/// state cannot be read.
+pub fn state_list(workspace: &Path) -> Option<Vec<String>> {
+  let output = Command::new("terraform")
+    .arg(format!("-chdir={}", workspace.display()))
+    .args(["state", "list"])
+    .stderr(Stdio::null())
+    .output()
+    .ok()?;
+  if !output.status.success() {
+    return None;
+  }
+  Some(
+    String::from_utf8_lossy(&output.stdout)
+      .lines()
+      .map(str::trim)
+      .filter(|line| !line.is_empty())
+      .map(String::from)
+      .collect(),
+  )
+}
+
+/// Destroy without refreshing, so resources that are already gone do not
+/// fail the plan, then again after a fresh `terraform init` in case the
+/// providers are what broke. If that still fails, destroy each resource
+/// left in the state on its own and carry on past the ones that fail.
+/// Returns the resources still in the state afterwards, which nothing
+/// confirmed destroyed, or `None` when the state cannot be read to tell.
+pub fn destroy(
+  workspace: &Path,
+  vars: &HashMap<String, String>,
+  debug: bool,
+) -> Option<Vec<String>> {
+  if terraform_destroy(workspace, vars, None, debug) {
+    return Some(Vec::new());
+  }
+  if terraform_init(workspace, debug)
+    && terraform_destroy(workspace, vars, None, debug)
+  {
+    return Some(Vec::new());
+  }
+  let mut failed = Vec::new();
+  for address in managed(state_list(workspace)?) {
+    status!("Destroying {}...", address);
+    if !terraform_destroy(workspace, vars, Some(&address), debug) {
+      failed.push(address);
+    }
+  }
+  // A failed destroy may have taken resources depending on it along
+  Some(state_list(workspace).map_or(failed, managed))
+}
+
+/// Data sources are only read, there is nothing to destroy.
+fn managed(addresses: Vec<String>) -> Vec<String> {
+  addresses
+    .into_iter()
+    .filter(|address| !address.starts_with("data."))
+    .collect()
+}
+
+fn terraform_init(workspace: &Path, debug: bool) -> bool {
+  let mut command = Command::new("terraform");
+  command
+    .arg(format!("-chdir={}", workspace.display()))
+    .args(["init", "-input=false"]);
+  if !debug {
+    command.stdout(Stdio::null()).stderr(Stdio::null());
+  }
+  command.status().is_ok_and(|status| status.success())
+}
+
+fn terraform_destroy(
+  workspace: &Path,
+  vars: &HashMap<String, String>,
+  target: Option<&str>,
+  debug: bool,
+) -> bool {
+  let mut command = Command::new("terraform");
+  command
+    .arg(format!("-chdir={}", workspace.display()))
+    .args(["destroy", "-auto-approve", "-input=false", "-refresh=false"]);
+  if let Some(target) = target {
+    command.arg(format!("-target={}", target));
+  }
+  for (key, value) in vars {
+    command.arg("-var").arg(format!("{}={}", key, value));
+  }
+  if !debug {
+    command.stdout(Stdio::null()).stderr(Stdio::null());
+  }
+  command.status().is_ok_and(|status| status.success())
+}
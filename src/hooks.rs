@@ -0,0 +1,48 @@
+//! Local commands run around the deployment lifecycle.
+
+use anyhow::{Context, Result};
+use std::{collections::HashMap, process::Command};
+
+/// Run `command` through `sh -c` with `env` added to fuoco's environment,
+/// and fail unless it exits successfully.
+pub fn run(
+  stage: &str,
+  command: &str,
+  env: &HashMap<String, String>,
+) -> Result<()> {
+  status!("Running {} hook: {}", stage, command);
+  let status = Command::new("sh")
+    .arg("-c")
+    .arg(command)
+    .envs(env)
+    .stdout(crate::child_stdout())
+    .status()
+    .with_context(|| format!("Failed to run {} hook", stage))?;
+  if !status.success() {
+    anyhow::bail!("{} hook exited with {}", stage, status);
+  }
+  Ok(())
+}
+
+/// `FUOCO_OUTPUT_<KEY>` for every Terraform output, e.g.
+/// `FUOCO_OUTPUT_PUBLIC_IP`.
+pub fn output_env(
+  outputs: &HashMap<String, String>,
+) -> HashMap<String, String> {
+  outputs
+    .iter()
+    .map(|(key, value)| {
+      let key = key
+        .chars()
+        .map(|c| {
+          if c.is_ascii_alphanumeric() {
+            c.to_ascii_uppercase()
+          } else {
+            '_'
+          }
+        })
+        .collect::<String>();
+      (format!("FUOCO_OUTPUT_{}", key), value.clone())
+    })
+    .collect()
+}
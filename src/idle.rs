@@ -0,0 +1,110 @@
+//! `--idle-timeout`: destroy a deployment nobody is using. Every provider
+//! is checked the same way, over SSH: the instance is idle while nobody is
+//! logged in, its 1-minute load average stays under `IDLE_LOAD` and it
+//! moves less than `IDLE_TRAFFIC` bytes a minute over the network.
+
+use crate::teardown::{self, Target};
+use anyhow::{Context, Result};
+use std::{
+  thread,
+  time::{Duration, Instant},
+};
+
+const IDLE_LOAD: f64 = 0.1;
+
+/// Bytes a minute, received and sent; the probe's own SSH session is a few
+/// KiB of it.
+const IDLE_TRAFFIC: u64 = 256 * 1024;
+
+const PROBE: &str =
+  "cat /proc/loadavg /proc/net/dev; echo users $(who | wc -l)";
+
+struct Sample {
+  load: f64,
+  /// Bytes received and sent since boot, loopback excluded.
+  traffic: u64,
+  users: u64,
+}
+
+/// Check `target` every `interval` and call `on_idle` once it has been idle
+/// for `timeout`. A check that fails (the instance is unreachable over SSH)
+/// starts the count again: fuoco cannot tell whether it is in use.
+pub fn watch(
+  target: Target,
+  timeout: Duration,
+  interval: Duration,
+  on_idle: impl FnOnce() + Send + 'static,
+) {
+  let max_traffic = IDLE_TRAFFIC * interval.as_secs().max(60) / 60;
+  thread::spawn(move || {
+    let mut previous: Option<Sample> = None;
+    let mut idle_since: Option<Instant> = None;
+    loop {
+      thread::sleep(interval);
+      let sample = match probe(&target) {
+        Ok(sample) => sample,
+        Err(err) => {
+          log::debug!("idle check failed: {:#}", err);
+          previous = None;
+          idle_since = None;
+          continue;
+        }
+      };
+      let idle = previous.as_ref().is_some_and(|previous| {
+        sample.users == 0
+          && sample.load < IDLE_LOAD
+          && sample.traffic.saturating_sub(previous.traffic) < max_traffic
+      });
+      previous = Some(sample);
+      if !idle {
+        idle_since = None;
+        continue;
+      }
+      // Idle since the check before this one
+      let since = *idle_since.get_or_insert_with(|| Instant::now() - interval);
+      if since.elapsed() >= timeout {
+        on_idle();
+        break;
+      }
+    }
+  });
+}
+
+fn probe(target: &Target) -> Result<Sample> {
+  let output = teardown::ssh(target)
+    .arg(PROBE)
+    .output()
+    .context("Failed to run ssh")?;
+  if !output.status.success() {
+    anyhow::bail!("ssh to {} exited with {}", target.ip, output.status);
+  }
+  parse(&String::from_utf8_lossy(&output.stdout))
+    .context("unexpected output from the idle probe")
+}
+
+fn parse(output: &str) -> Option<Sample> {
+  let mut lines = output.lines();
+  let load = lines.next()?.split_whitespace().next()?.parse().ok()?;
+  let mut traffic = 0;
+  let mut users = None;
+  for line in lines {
+    if let Some(count) = line.strip_prefix("users ") {
+      users = count.trim().parse().ok();
+    } else if let Some((interface, counters)) = line.split_once(':') {
+      if interface.trim() == "lo" {
+        continue;
+      }
+      let counters: Vec<u64> = counters
+        .split_whitespace()
+        .filter_map(|counter| counter.parse().ok())
+        .collect();
+      // Received bytes first, sent bytes eight counters later
+      traffic += counters.first()? + counters.get(8)?;
+    }
+  }
+  Some(Sample {
+    load,
+    traffic,
+    users: users?,
+  })
+}
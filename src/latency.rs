@@ -0,0 +1,47 @@
+//! TCP connect-time probes used to rank candidate regions by latency.
+
+use std::{
+  net::{TcpStream, ToSocketAddrs},
+  sync::mpsc,
+  thread,
+  time::{Duration, Instant},
+};
+
+/// Time a TCP handshake to `host:port`, or `None` if it does not resolve or
+/// connect within `timeout`.
+pub fn probe(host: &str, port: u16, timeout: Duration) -> Option<Duration> {
+  let addr = (host, port).to_socket_addrs().ok()?.next()?;
+  let start = Instant::now();
+  TcpStream::connect_timeout(&addr, timeout).ok()?;
+  Some(start.elapsed())
+}
+
+/// Probe every `(label, host, port)` concurrently and return the reachable
+/// ones sorted by round-trip time, fastest first. The whole run is bounded by
+/// `timeout`; probes still pending after that are dropped.
+pub fn rank(
+  targets: Vec<(String, String, u16)>,
+  timeout: Duration,
+) -> Vec<(String, Duration)> {
+  let (tx, rx) = mpsc::channel();
+  let count = targets.len();
+  for (label, host, port) in targets {
+    let tx = tx.clone();
+    thread::spawn(move || {
+      let _ = tx.send((label, probe(&host, port, timeout)));
+    });
+  }
+  drop(tx);
+  let deadline = Instant::now() + timeout;
+  let mut ranked = Vec::new();
+  for _ in 0..count {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    match rx.recv_timeout(remaining) {
+      Ok((label, Some(rtt))) => ranked.push((label, rtt)),
+      Ok((_, None)) => {}
+      Err(_) => break,
+    }
+  }
+  ranked.sort_by_key(|(_, rtt)| *rtt);
+  ranked
+}
@@ -0,0 +1,2861 @@
+//! Ephemeral VMs on AWS, GCP, Hetzner, Oracle Cloud, Linode and Scaleway:
+//! apply a Terraform template through `atar`, hand back its outputs,
+//! destroy it when the handle goes. The `fuoco` binary is this plus the
+//! CLI; prompts, signal handlers and panic hooks are left to the caller.
+//!
+//! Progress goes through the `log` crate, so it shows once a logger is set.
+//!
+//! ```no_run
+//! use fuoco::{DeployParams, Provider};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let params = DeployParams {
+//!   region: Some("eu-central-1".to_string()),
+//!   instance_type: Some("t3.small".to_string()),
+//!   ..DeployParams::new(Provider::AWS)?
+//! };
+//! let handle = fuoco::deploy(params)?;
+//! println!("{:?}", handle.outputs().get("public_ip"));
+//! // Dropping the handle destroys too
+//! handle.destroy()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{Context, Result};
+use atar::undeploy as lib_undeploy;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use clap::ValueEnum;
+use rand::seq::SliceRandom;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+  collections::{BTreeMap, HashMap},
+  env, fmt, fs, io,
+  path::{Path, PathBuf},
+  process::Stdio,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc,
+  },
+  time::{Duration, Instant},
+};
+use style::Table;
+use timings::{Timings, TimingsFormat};
+use userdata::{UserData, WriteFile};
+
+pub use deployment::{DeployHandle, Deployment, Destroyer};
+use events::Event;
+use exit::WithStatus;
+use logging::Verbosity;
+pub use validate::require_compartment_id;
+
+/// Set by `--print-ip` and `--print-vars`: stdout then carries nothing but
+/// what they print, not even the outputs or the hooks' output.
+#[doc(hidden)]
+pub static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+/// Stdout for a child process: fuoco's own, or stderr while it is kept
+/// clean for --print-ip.
+#[doc(hidden)]
+pub fn child_stdout() -> Stdio {
+  if STDOUT_RESERVED.load(Ordering::Relaxed) {
+    Stdio::from(io::stderr())
+  } else {
+    Stdio::inherit()
+  }
+}
+
+/// Progress messages: stderr unless `--quiet`, and the `--log-file`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! status {
+  ($($arg:tt)*) => {
+    log::info!($($arg)*)
+  };
+}
+
+pub mod bootlog;
+pub mod capture;
+pub mod clean;
+pub mod credentials;
+pub mod deployment;
+pub mod events;
+pub mod exit;
+mod failed;
+mod force;
+mod hooks;
+pub mod idle;
+mod latency;
+mod lock;
+pub mod logging;
+pub mod notify;
+pub mod pricing;
+mod progress;
+pub mod regions;
+pub mod resolved;
+mod retry;
+pub mod status;
+pub mod style;
+pub mod task;
+pub mod teardown;
+pub mod timings;
+pub mod userdata;
+mod validate;
+pub mod watchdog;
+pub mod workspace;
+
+/// The deployment name unless one is given.
+pub const DEFAULT_NAME: &str = "fuoco-ephemeral";
+
+/// Retries for a Terraform run that fails in a way worth retrying.
+pub const DEFAULT_RETRIES: u32 = 2;
+
+/// How long a destroy may run before fuoco gives up on it.
+pub const DEFAULT_DESTROY_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// How often --idle-timeout checks the instance.
+pub const DEFAULT_IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Where --extra-volume is mounted.
+pub const DEFAULT_EXTRA_VOLUME_MOUNT: &str = "/mnt/scratch";
+
+/// Everything a deploy is run with. `new` gives what `fuoco deploy` runs
+/// with when no flag is passed, to override field by field.
+#[derive(Clone)]
+pub struct DeployParams {
+  pub verbosity: Verbosity,
+  pub events: bool,
+  /// Each event as the JSON line `--events` writes, for a caller that
+  /// follows the deployment from another thread or task.
+  pub progress: Option<mpsc::Sender<String>>,
+  pub yes: bool,
+  pub print_ip: bool,
+  pub reuse: bool,
+  pub fresh_workspace: bool,
+  /// Leave out the authenticated call `credentials::check` makes before
+  /// Terraform runs.
+  pub skip_credential_check: bool,
+  pub wait_lock: Option<Duration>,
+  pub retries: u32,
+  pub deploy_timeout: Option<Duration>,
+  pub destroy_timeout: Duration,
+  /// --timeout: when the whole cycle, destroy included, must be over. The
+  /// apply is cut short to meet it; the destroy still gets a few minutes.
+  pub deadline: Option<Instant>,
+  pub timings: TimingsFormat,
+  pub params_format: ParamsFormat,
+  pub keep: bool,
+  pub idle_timeout: Option<Duration>,
+  pub idle_check_interval: Duration,
+  pub watch_logs: bool,
+  pub on_hangup: HangupAction,
+  /// --workdir, which `undeploy` needs to find the same workspace.
+  pub workdir: Option<PathBuf>,
+  pub name: String,
+  pub instance_type: Option<String>,
+  /// Refuse an `instance_type` fuoco does not know, rather than warn.
+  pub strict_instance_type: bool,
+  pub arch: Option<Arch>,
+  pub provider: Provider,
+  pub region: Option<String>,
+  /// Prefixes or globs the region fuoco picks must match, when `region`
+  /// and the provider's environment leave the choice to it.
+  pub region_filter: Vec<String>,
+  /// Regions fuoco must not pick.
+  pub region_exclude: Vec<String>,
+  /// The round-trip time measured to `region`, when it was picked as the
+  /// closest; shown with the params and recorded with the deployment.
+  pub region_rtt: Option<Duration>,
+  /// The seed `region` was drawn with, when picked at random; the same
+  /// seed, provider and candidates always draw the same region.
+  pub region_seed: Option<u64>,
+  /// The USD/hour `region` was picked as the cheapest for.
+  pub hourly_price: Option<f64>,
+  pub script_path: Vec<PathBuf>,
+  /// Size of the script read from stdin, shown instead of `-`.
+  pub stdin_bytes: Option<usize>,
+  pub script_url: Option<String>,
+  /// SHA-256 of the downloaded script, checked against --script-sha256.
+  pub script_sha256: Option<String>,
+  pub script: Option<String>,
+  pub cloud_config_path: Option<PathBuf>,
+  /// The --cloud-config document with the --file entries merged in.
+  pub cloud_config: Option<String>,
+  pub cloud_init_path: Option<PathBuf>,
+  /// The --cloud-init user data, which replaces everything fuoco generates.
+  pub cloud_init: Option<String>,
+  pub files: Vec<WriteFile>,
+  pub env: Vec<EnvVar>,
+  pub template_path: PathBuf,
+  pub inbound_rules: Option<Vec<InboundRule>>,
+  pub ssh_public_key_path: Vec<String>,
+  /// Contents of each --ssh-public-key-path, in order.
+  pub ssh_public_keys: Vec<String>,
+  /// A key pair already registered with the provider, authorized instead
+  /// of uploading `ssh_public_keys` (AWS and Hetzner).
+  pub key_name: Option<String>,
+  pub ssh_port: Option<u16>,
+  pub compartment_id: Option<String>,
+  pub vpc_id: Option<String>,
+  pub subnet_id: Option<String>,
+  /// AWS: the availability zone to launch in, rather than AWS's pick.
+  pub availability_zone: Option<String>,
+  /// AWS: when the zone has no capacity for the instance type, destroy
+  /// the attempt and apply again in the region's next zone.
+  pub az_fallback: bool,
+  pub no_public_ip: bool,
+  pub static_ip: bool,
+  pub spot: bool,
+  pub spot_max_price: Option<String>,
+  pub disk_size: Option<u32>,
+  pub extra_volume: Option<u32>,
+  pub extra_volume_mount: String,
+  pub image: Option<String>,
+  pub user: Option<String>,
+  pub hostname: Option<String>,
+  pub tags: BTreeMap<String, String>,
+  /// --metadata: key/value pairs the instance reads at runtime, kept apart
+  /// from `tags`.
+  pub metadata: BTreeMap<String, String>,
+  pub pre_deploy_hook: Option<String>,
+  pub post_deploy_hook: Option<String>,
+  pub pre_destroy_hook: Option<String>,
+  pub teardown: Option<teardown::Script>,
+  pub notifier: Option<notify::Notifier>,
+  pub callback_url: Option<String>,
+  pub sensitive_outputs: Vec<String>,
+  pub extra_vars: Vec<TemplateVar>,
+}
+
+/// Everything an undeploy is run with; `new` gives the defaults.
+pub struct UndeployParams {
+  pub verbosity: Verbosity,
+  pub yes: bool,
+  pub force: bool,
+  pub instance_type: Option<String>,
+  pub arch: Option<Arch>,
+  pub provider: Provider,
+  pub name: String,
+  pub region: String,
+  pub template_path: PathBuf,
+  /// The copy of the template atar runs, which keys the workspace.
+  pub workspace_template: PathBuf,
+  pub compartment_id: Option<String>,
+  pub teardown: Option<teardown::Script>,
+  pub ssh_public_key_path: Option<String>,
+  pub ssh_port: Option<u16>,
+  pub retries: u32,
+  pub destroy_timeout: Duration,
+  pub timings: TimingsFormat,
+  pub params_format: ParamsFormat,
+  pub wait_lock: Option<Duration>,
+  pub extra_vars: Vec<TemplateVar>,
+}
+
+impl fmt::Display for DeployParams {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mut table = Table::new("Deploy params");
+    table.row("name", &self.name);
+    table.row("verbosity", self.verbosity);
+    table.row("retries", self.retries);
+    if let Some(deploy_timeout) = self.deploy_timeout {
+      table.row("deploy_timeout", format_elapsed(deploy_timeout));
+    }
+    if let Some(deadline) = self.deadline {
+      table.row(
+        "timeout",
+        format_elapsed(deadline.saturating_duration_since(Instant::now())),
+      );
+    }
+    let arch =
+      resolve_arch(&self.provider, self.arch, self.instance_type.as_ref());
+    let default_instance_type =
+      resolve_default_instance_type(&self.provider, arch);
+    table.row(
+      "instance_type",
+      self
+        .instance_type
+        .as_ref()
+        .map_or(default_instance_type, |s| format!("[{}]", s)),
+    );
+    table.row("arch", arch);
+    table.row("provider", format!("{:?}", self.provider));
+    let resolved = resolve_region(&self.provider, self.region.as_deref());
+    // GCP shows the region here and the zone on a row of its own
+    let zone = match (&self.provider, &resolved) {
+      (Provider::GCP, Some((location, _))) => {
+        Some(match split_gcp_location(location) {
+          (_, Some(_)) => location.clone(),
+          (_, None) => "[Random]".to_string(),
+        })
+      }
+      (Provider::GCP, None) => Some("[Random]".to_string()),
+      _ => None,
+    };
+    let resolved = resolved.map(|(location, source)| match self.provider {
+      Provider::GCP => (split_gcp_location(&location).0.to_string(), source),
+      _ => (location, source),
+    });
+    let region = match (resolved, self.region_rtt) {
+      (Some((region, _)), Some(rtt)) => {
+        format!("{} [closest, {} ms]", region, rtt.as_millis())
+      }
+      (Some((region, "--region")), None) => region,
+      (Some((region, source)), None) => format!("{} [{}]", region, source),
+      (None, _) => "[Random]".to_string(),
+    };
+    table.row("region", region);
+    if let Some(zone) = zone {
+      table.row("zone", zone);
+    }
+    if !self.region_filter.is_empty() {
+      table.row("region_filter", self.region_filter.join(", "));
+    }
+    if !self.region_exclude.is_empty() {
+      table.row("region_exclude", self.region_exclude.join(", "));
+    }
+    if let Some(seed) = self.region_seed {
+      table.row("region_seed", seed);
+    }
+    let script_paths: Vec<_> = self
+      .script_path
+      .iter()
+      .map(|path| match self.stdin_bytes {
+        Some(bytes) if is_stdin(path) => format!("<stdin, {} bytes>", bytes),
+        _ => format!("{:?}", path),
+      })
+      .collect();
+    table.row("script_path", format!("[{}]", script_paths.join(", ")));
+    if let Some(cloud_config_path) = &self.cloud_config_path {
+      table.row("cloud_config", format!("{:?}", cloud_config_path));
+    }
+    if let Some(cloud_init_path) = &self.cloud_init_path {
+      table.row("cloud_init", format!("{:?} (verbatim)", cloud_init_path));
+    }
+    if !self.files.is_empty() {
+      table.row(
+        "files",
+        format!(
+          "[{}]",
+          self
+            .files
+            .iter()
+            .map(|file| file.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+        ),
+      );
+    }
+    if !self.env.is_empty() {
+      // Values are often secrets, so only the names are shown
+      table.row(
+        "env",
+        format!(
+          "[{}]",
+          self
+            .env
+            .iter()
+            .map(|var| format!("{}=***", var.key))
+            .collect::<Vec<_>>()
+            .join(", ")
+        ),
+      );
+    }
+    if let Some(url) = &self.script_url {
+      table.row("script_url", url);
+      table.row(
+        "script_sha256",
+        self.script_sha256.as_deref().unwrap_or("[unknown]"),
+      );
+    }
+    let user_data = self.encoded_user_data();
+    if user_data.gzip {
+      table.row(
+        "user_data",
+        format!(
+          "{} bytes, gzipped to {} bytes",
+          user_data.raw_size, user_data.size
+        ),
+      );
+    } else {
+      table.row("user_data", format!("{} bytes", user_data.size));
+    }
+    table.row("template_path", format!("{:?}", self.template_path));
+    let inbound_rules =
+      resolve_inbound_rules(self.inbound_rules.as_ref(), self.ssh_port);
+    table.row(
+      "inbound_rules",
+      format!(
+        "[{}]",
+        inbound_rules
+          .iter()
+          .map(|rule| rule.to_string())
+          .collect::<Vec<_>>()
+          .join(", ")
+      ),
+    );
+    if let Some(key_name) = &self.key_name {
+      table.row("key_name", key_name);
+    } else if self.ssh_public_key_path.is_empty() {
+      table.row("ssh_public_key_path", "[None found]");
+    } else {
+      table.row(
+        "ssh_public_key_path",
+        format!("{:?}", self.ssh_public_key_path),
+      );
+    }
+    if let Some(ssh_port) = self.ssh_port {
+      table.row("ssh_port", ssh_port);
+    }
+    if let Some(compartment_id) = &self.compartment_id {
+      table.row("compartment_id", compartment_id);
+    }
+    if let (Some(vpc_id), Some(subnet_id)) = (&self.vpc_id, &self.subnet_id) {
+      table.row("network", format!("{} / {}", vpc_id, subnet_id));
+    }
+    if self.availability_zone.is_some() || self.az_fallback {
+      let zone = self.availability_zone.as_deref().unwrap_or("[AWS's pick]");
+      table.row(
+        "availability_zone",
+        if self.az_fallback {
+          format!("{} (falling back to the others)", zone)
+        } else {
+          zone.to_string()
+        },
+      );
+    }
+    if self.no_public_ip {
+      table.row("public_ip", "none");
+    } else if self.static_ip {
+      table.row("public_ip", "static");
+    }
+    table.row("spot", self.spot);
+    if let Some(idle_timeout) = self.idle_timeout {
+      table.row(
+        "idle_timeout",
+        format!(
+          "{} (checked every {})",
+          format_elapsed(idle_timeout),
+          format_elapsed(self.idle_check_interval)
+        ),
+      );
+    }
+    if self.watch_logs {
+      table.row("watch_logs", true);
+    }
+    table.row("estimated_cost", self.estimated_cost());
+    if let Some(spot_max_price) = &self.spot_max_price {
+      table.row("spot_max_price", format!("${}/hr", spot_max_price));
+    }
+    table.row(
+      "disk_size",
+      self
+        .disk_size
+        .map_or("[Template default]".to_string(), |s| format!("{} GiB", s)),
+    );
+    if let Some(extra_volume) = self.extra_volume {
+      table.row(
+        "extra_volume",
+        format!("{} GiB at {}", extra_volume, self.extra_volume_mount),
+      );
+    }
+    table.row(
+      "image",
+      self.image.as_deref().unwrap_or("[Template default]"),
+    );
+    table.row("user", self.user.as_deref().unwrap_or("[Template default]"));
+    table.row(
+      "hostname",
+      self.hostname.as_deref().unwrap_or("[Provider default]"),
+    );
+    if !self.tags.is_empty() {
+      table.row(
+        "tags",
+        format!(
+          "[{}]",
+          self
+            .tags
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(", ")
+        ),
+      );
+    }
+    if !self.metadata.is_empty() {
+      let keys: Vec<_> = self.metadata.keys().map(String::as_str).collect();
+      table.row("metadata", format!("[{}]", keys.join(", ")));
+    }
+    for (stage, hook) in [
+      ("pre_deploy_hook", &self.pre_deploy_hook),
+      ("post_deploy_hook", &self.post_deploy_hook),
+      ("pre_destroy_hook", &self.pre_destroy_hook),
+    ] {
+      if let Some(hook) = hook {
+        table.row(stage, hook);
+      }
+    }
+    write_teardown(&mut table, &self.teardown);
+    if let Some(notifier) = &self.notifier {
+      table.row(
+        "notify",
+        format!("{} ({:?})", notifier.redacted_url(), notifier.format()),
+      );
+    }
+    if let Some(callback_url) = &self.callback_url {
+      table.row("callback", notify::redact_url(callback_url));
+    }
+    write_extra_vars(&mut table, &self.extra_vars);
+    write!(f, "{}", table)
+  }
+}
+
+impl DeployParams {
+  /// `fuoco deploy --provider <provider>`: the template fuoco picks for
+  /// it, the SSH keys found in `~/.ssh` and a random region.
+  pub fn new(provider: Provider) -> Result<Self> {
+    let (template_path, _) = resolve_template(&provider, &None, false)?;
+    let ssh_public_key_path = resolve_ssh_public_key_paths(Vec::new());
+    Ok(DeployParams {
+      verbosity: Verbosity::new(0, false),
+      events: false,
+      progress: None,
+      yes: true,
+      print_ip: false,
+      reuse: false,
+      fresh_workspace: false,
+      skip_credential_check: false,
+      wait_lock: None,
+      retries: DEFAULT_RETRIES,
+      deploy_timeout: None,
+      destroy_timeout: DEFAULT_DESTROY_TIMEOUT,
+      deadline: None,
+      timings: TimingsFormat::Text,
+      params_format: ParamsFormat::Text,
+      keep: false,
+      idle_timeout: None,
+      idle_check_interval: DEFAULT_IDLE_CHECK_INTERVAL,
+      watch_logs: false,
+      on_hangup: HangupAction::Destroy,
+      workdir: None,
+      name: DEFAULT_NAME.to_string(),
+      instance_type: None,
+      strict_instance_type: false,
+      arch: None,
+      provider,
+      region: None,
+      region_filter: Vec::new(),
+      region_exclude: Vec::new(),
+      region_rtt: None,
+      region_seed: None,
+      hourly_price: None,
+      script_path: Vec::new(),
+      stdin_bytes: None,
+      script_url: None,
+      script_sha256: None,
+      script: None,
+      cloud_config_path: None,
+      cloud_config: None,
+      cloud_init_path: None,
+      cloud_init: None,
+      files: Vec::new(),
+      env: Vec::new(),
+      template_path,
+      inbound_rules: None,
+      ssh_public_keys: read_ssh_public_keys(&ssh_public_key_path)?,
+      key_name: None,
+      ssh_public_key_path,
+      ssh_port: None,
+      compartment_id: None,
+      vpc_id: None,
+      subnet_id: None,
+      availability_zone: None,
+      az_fallback: false,
+      no_public_ip: false,
+      static_ip: false,
+      spot: false,
+      spot_max_price: None,
+      disk_size: None,
+      extra_volume: None,
+      extra_volume_mount: DEFAULT_EXTRA_VOLUME_MOUNT.to_string(),
+      image: None,
+      user: None,
+      hostname: None,
+      tags: BTreeMap::new(),
+      metadata: BTreeMap::new(),
+      pre_deploy_hook: None,
+      post_deploy_hook: None,
+      pre_destroy_hook: None,
+      teardown: None,
+      notifier: None,
+      callback_url: None,
+      sensitive_outputs: Vec::new(),
+      extra_vars: Vec::new(),
+    })
+  }
+
+  /// What keys the deployment's workspace; `hash_map` holds the resolved
+  /// region.
+  pub fn identity<'a>(
+    &'a self,
+    hash_map: &'a HashMap<String, String>,
+  ) -> workspace::Identity<'a> {
+    workspace::Identity {
+      template: &self.template_path,
+      provider: provider_str(&self.provider),
+      name: &self.name,
+      region: location(hash_map),
+    }
+  }
+
+  /// Check the params against what the provider accepts, so a bad one
+  /// fails before anything is created. `deploy` runs it too.
+  pub fn validate(&self) -> Result<()> {
+    validate::deploy_params(self).exit_status(exit::INVALID)
+  }
+
+  pub fn to_atar_map(&self) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    map.insert("name".to_string(), self.name.clone());
+    // Convert each field to a String and insert it into the map
+    let arch =
+      resolve_arch(&self.provider, self.arch, self.instance_type.as_ref());
+    let default_instance_type =
+      resolve_default_instance_type(&self.provider, arch);
+    map.insert(
+      "instance_type".to_string(),
+      self
+        .instance_type
+        .as_ref()
+        .map_or(default_instance_type, |s| s.clone()),
+    );
+    map.insert("arch".to_string(), arch.to_string());
+    let region = match resolve_region(&self.provider, self.region.as_deref()) {
+      Some((region, _)) => region,
+      // `deploy` has already rejected a filter that matches nothing
+      None => resolve_random_region(
+        &self.provider,
+        &self.region_filter,
+        &self.region_exclude,
+        self.region_seed.unwrap_or_else(rand::random),
+      )
+      .unwrap_or_else(|_| random_region(regions::available(&self.provider).0)),
+    };
+    // GCP instances live in zones: the template gets the zone apart
+    if let Provider::GCP = self.provider {
+      let zone = resolve_gcp_zone(&region);
+      let (region, _) = split_gcp_location(&zone);
+      map.insert("region".to_string(), region.to_string());
+      map.insert("zone".to_string(), zone.clone());
+    } else {
+      map.insert("region".to_string(), region);
+    }
+    let user_data = self.encoded_user_data();
+    map.insert("user_data".to_string(), user_data.payload);
+    if user_data.gzip {
+      map.insert("user_data_base64".to_string(), "true".to_string());
+    }
+    let final_inbound_rules =
+      resolve_inbound_rules(self.inbound_rules.as_ref(), self.ssh_port);
+    let inbound_rules_json =
+      serde_json::to_string(&final_inbound_rules).unwrap();
+    map.insert("inbound_rules".to_string(), inbound_rules_json);
+    // Key material rather than paths, so templates never touch the
+    // invoking machine's filesystem
+    map.insert(
+      "ssh_public_keys".to_string(),
+      serde_json::to_string(&self.ssh_public_keys).unwrap(),
+    );
+    // The optional ones are left out when unset, so custom templates need
+    // not declare them
+    if let Some(key_name) = &self.key_name {
+      map.insert("key_name".to_string(), key_name.clone());
+    }
+    if let Some(zone) = &self.availability_zone {
+      map.insert("availability_zone".to_string(), zone.clone());
+    }
+    if let Some(image) = &self.image {
+      map.insert("image".to_string(), image.clone());
+    }
+    if let Some(user) = &self.user {
+      map.insert("user".to_string(), user.clone());
+    }
+    if let Some(hostname) = &self.hostname {
+      map.insert("hostname".to_string(), hostname.clone());
+    }
+    if !self.tags.is_empty() {
+      map.insert(
+        "tags".to_string(),
+        serde_json::to_string(&self.tags).unwrap(),
+      );
+    }
+    if !self.metadata.is_empty() {
+      map.insert(
+        "metadata".to_string(),
+        serde_json::to_string(&self.metadata).unwrap(),
+      );
+    }
+    if self.static_ip {
+      map.insert("static_ip".to_string(), "true".to_string());
+    }
+    // Only the OCI template declares a compartment variable
+    if let (Provider::Oracle, Some(compartment_id)) =
+      (&self.provider, &self.compartment_id)
+    {
+      map.insert("compartment_id".to_string(), compartment_id.clone());
+    }
+    // Left out when unset so the templates create or pick their own network
+    if let Some(vpc_id) = &self.vpc_id {
+      map.insert("vpc_id".to_string(), vpc_id.clone());
+    }
+    if let Some(subnet_id) = &self.subnet_id {
+      map.insert("subnet_id".to_string(), subnet_id.clone());
+    }
+    if self.no_public_ip {
+      map.insert("assign_public_ip".to_string(), "false".to_string());
+    }
+    if self.spot {
+      map.insert("use_spot".to_string(), "true".to_string());
+    }
+    if let Some(spot_max_price) = &self.spot_max_price {
+      map.insert("spot_max_price".to_string(), spot_max_price.clone());
+    }
+    // Left out when unset so the templates keep their image defaults
+    if let Some(disk_size) = self.disk_size {
+      map.insert("disk_size".to_string(), disk_size.to_string());
+    }
+    if let Some(extra_volume) = self.extra_volume {
+      map.insert("extra_volume_size".to_string(), extra_volume.to_string());
+    }
+    insert_extra_vars(&mut map, &self.extra_vars);
+    map
+  }
+
+  /// On-demand price of the instance, also shown for spot since spot prices
+  /// float and are only ever lower.
+  pub fn estimated_cost(&self) -> String {
+    pricing::describe(self.hourly_usd())
+  }
+
+  /// The on-demand USD/hour of the instance: the price `region` was picked
+  /// for, or the estimate for the region when the type is cataloged.
+  pub fn hourly_usd(&self) -> Option<f64> {
+    if self.hourly_price.is_some() {
+      return self.hourly_price;
+    }
+    let provider = provider_str(&self.provider);
+    let instance_type = self.resolved_instance_type();
+    match resolve_region(&self.provider, self.region.as_deref()) {
+      Some((region, _)) => {
+        pricing::regional_hourly_usd(provider, &instance_type, &region)
+      }
+      None => pricing::hourly_usd(provider, &instance_type),
+    }
+  }
+
+  /// --instance-type, or the provider's default for the architecture.
+  pub fn resolved_instance_type(&self) -> String {
+    let arch =
+      resolve_arch(&self.provider, self.arch, self.instance_type.as_ref());
+    self
+      .instance_type
+      .clone()
+      .unwrap_or_else(|| resolve_default_instance_type(&self.provider, arch))
+  }
+
+  /// Run the hook for `stage`, if one was given. Hooks see the deployment
+  /// name, provider and every Terraform output as FUOCO_* variables.
+  fn run_hook(
+    &self,
+    stage: &str,
+    hook: &Option<String>,
+    outputs: &HashMap<String, String>,
+  ) -> Result<()> {
+    let Some(command) = hook else {
+      return Ok(());
+    };
+    let mut env = hooks::output_env(outputs);
+    env.insert("FUOCO_NAME".to_string(), self.name.clone());
+    env.insert(
+      "FUOCO_PROVIDER".to_string(),
+      provider_str(&self.provider).to_string(),
+    );
+    hooks::run(stage, command, &env)
+  }
+
+  /// POST the outputs to --callback-url, the sensitive ones redacted. A
+  /// failure only warns: the deployment is up either way.
+  fn run_callback(&self, outputs: &HashMap<String, String>) {
+    let Some(url) = &self.callback_url else {
+      return;
+    };
+    let outputs: BTreeMap<_, _> = outputs
+      .iter()
+      .map(|(key, value)| {
+        let value = if self.sensitive_outputs.contains(key) {
+          "[redacted]"
+        } else {
+          value.as_str()
+        };
+        (key, value)
+      })
+      .collect();
+    let body = serde_json::json!({
+      "name": self.name,
+      "provider": provider_str(&self.provider),
+      "outputs": outputs,
+    });
+    if let Err(err) = notify::post_json(url, &body) {
+      log::warn!("callback to {} failed: {:#}", notify::redact_url(url), err);
+    }
+  }
+
+  /// Run the teardown script, then the pre-destroy hook; neither may stop
+  /// the destroy.
+  fn run_pre_destroy_hook(&self, outputs: &HashMap<String, String>) {
+    if let Some(script) = &self.teardown {
+      run_teardown_script(
+        script,
+        &self.provider,
+        outputs,
+        self.ssh_public_key_path.first().map(String::as_str),
+        self.ssh_port,
+      );
+    }
+    if let Err(err) =
+      self.run_hook("pre-destroy", &self.pre_destroy_hook, outputs)
+    {
+      log::warn!("{:#}", err);
+    }
+  }
+
+  /// Report a lifecycle event when `--events` is on.
+  pub fn emit(&self, event: Event) {
+    if let Ok(event) = serde_json::to_string(&event) {
+      log::debug!(target: "fuoco::event", "{}: {}", self.name, event);
+    }
+    if let Some(notifier) = &self.notifier {
+      self.notify(notifier, &event);
+    }
+    if self.events {
+      events::emit(&self.name, &event);
+    }
+    if let Some(progress) = &self.progress {
+      if let Some(line) = events::line(&self.name, &event) {
+        // Nobody listening anymore is no reason to stop
+        let _ = progress.send(line);
+      }
+    }
+  }
+
+  /// Forward the events people get notified about to the webhook.
+  fn notify(&self, notifier: &notify::Notifier, event: &Event) {
+    let instance_type = self.resolved_instance_type();
+    let notification = match event {
+      Event::DeploySucceeded { outputs } => {
+        notify::Notification::DeploySucceeded {
+          provider: provider_str(&self.provider),
+          region: outputs
+            .get("zone")
+            .or_else(|| outputs.get("region"))
+            .map_or("unknown", |r| r.as_str()),
+          instance_type: &instance_type,
+          public_ip: public_ip(&self.provider, outputs),
+        }
+      }
+      Event::DestroyStarted => notify::Notification::DestroyStarted,
+      Event::DestroySucceeded => notify::Notification::DestroyFinished {
+        success: true,
+        error: None,
+      },
+      Event::DestroyFailed { error } => notify::Notification::DestroyFinished {
+        success: false,
+        error: Some(error),
+      },
+      _ => return,
+    };
+    notifier.send(&self.name, &notification);
+  }
+
+  /// The rendered user data, gzipped if it would not fit otherwise.
+  pub fn encoded_user_data(&self) -> userdata::Encoded {
+    userdata::encode(
+      self.user_data().render(),
+      &user_data_limit(&self.provider),
+    )
+  }
+
+  fn user_data(&self) -> UserData {
+    UserData {
+      script: self.script.clone(),
+      extra_volume_mount: self
+        .extra_volume
+        .map(|_| self.extra_volume_mount.clone()),
+      user: self.user.clone(),
+      // AWS key pairs hold a single key; the boot script installs the rest
+      authorized_keys: match self.provider {
+        Provider::AWS => self.ssh_public_keys.iter().skip(1).cloned().collect(),
+        _ => Vec::new(),
+      },
+      cloud_config: self.cloud_config.clone(),
+      verbatim: self.cloud_init.clone(),
+      ssh_port: self.ssh_port,
+      env: self
+        .env
+        .iter()
+        .map(|var| (var.key.clone(), var.value.clone()))
+        .collect(),
+    }
+  }
+}
+
+impl fmt::Display for UndeployParams {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mut table = Table::new("Undeploy params");
+    table.row("name", &self.name);
+    table.row("verbosity", self.verbosity);
+    if self.force {
+      table.row("force", "true");
+    }
+    table.row("retries", self.retries);
+    let arch =
+      resolve_arch(&self.provider, self.arch, self.instance_type.as_ref());
+    let default_instance_type =
+      resolve_default_instance_type(&self.provider, arch);
+    table.row(
+      "instance_type",
+      self
+        .instance_type
+        .as_ref()
+        .map_or(default_instance_type, |s| format!("[{}]", s)),
+    );
+    table.row("arch", arch);
+    table.row("provider", format!("{:?}", self.provider));
+    table.row("region", &self.region);
+    table.row("template_path", format!("{:?}", self.template_path));
+    if let Some(compartment_id) = &self.compartment_id {
+      table.row("compartment_id", compartment_id);
+    }
+    write_teardown(&mut table, &self.teardown);
+    table.row("destroy_timeout", format_elapsed(self.destroy_timeout));
+    write_extra_vars(&mut table, &self.extra_vars);
+    write!(f, "{}", table)
+  }
+}
+
+impl UndeployParams {
+  /// `fuoco undeploy --yes` for the deployment `name` in `region`, found
+  /// with the template fuoco picks for `provider`.
+  pub fn new(provider: Provider, name: &str, region: &str) -> Result<Self> {
+    let (template_path, _) = resolve_template(&provider, &None, false)?;
+    let workspace_template = workspace::Identity {
+      template: &template_path,
+      provider: provider_str(&provider),
+      name,
+      region,
+    }
+    .find()?;
+    Ok(UndeployParams {
+      verbosity: Verbosity::new(0, false),
+      yes: true,
+      force: false,
+      instance_type: None,
+      arch: None,
+      provider,
+      name: name.to_string(),
+      region: region.to_string(),
+      template_path,
+      workspace_template,
+      compartment_id: None,
+      teardown: None,
+      ssh_public_key_path: None,
+      ssh_port: None,
+      retries: DEFAULT_RETRIES,
+      destroy_timeout: DEFAULT_DESTROY_TIMEOUT,
+      timings: TimingsFormat::Text,
+      params_format: ParamsFormat::Text,
+      wait_lock: None,
+      extra_vars: Vec::new(),
+    })
+  }
+
+  pub fn to_atar_map(&self) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    // Convert each field to a String and insert it into the map
+    let arch =
+      resolve_arch(&self.provider, self.arch, self.instance_type.as_ref());
+    let default_instance_type =
+      resolve_default_instance_type(&self.provider, arch);
+    map.insert(
+      "instance_type".to_string(),
+      self
+        .instance_type
+        .as_ref()
+        .map_or(default_instance_type, |s| s.clone()),
+    );
+    map.insert("arch".to_string(), arch.to_string());
+    // Undeploy takes a full GCP zone, which the template gets apart
+    if let Provider::GCP = self.provider {
+      let (region, _) = split_gcp_location(&self.region);
+      map.insert("region".to_string(), region.to_string());
+      map.insert("zone".to_string(), self.region.clone());
+    } else {
+      map.insert("region".to_string(), self.region.clone());
+    }
+    map.insert("name".to_string(), self.name.clone());
+    // Only the OCI template declares a compartment variable
+    if let (Provider::Oracle, Some(compartment_id)) =
+      (&self.provider, &self.compartment_id)
+    {
+      map.insert("compartment_id".to_string(), compartment_id.clone());
+    }
+    insert_extra_vars(&mut map, &self.extra_vars);
+    map
+  }
+}
+
+/// Variables fuoco sets itself; --var may only replace them with
+/// --var-override.
+pub const FUOCO_VARIABLES: &[&str] = &[
+  "name",
+  "instance_type",
+  "arch",
+  "region",
+  "zone",
+  "user_data",
+  "user_data_base64",
+  "inbound_rules",
+  "ssh_public_keys",
+  "key_name",
+  "compartment_id",
+  "vpc_id",
+  "subnet_id",
+  "availability_zone",
+  "assign_public_ip",
+  "static_ip",
+  "use_spot",
+  "spot_max_price",
+  "disk_size",
+  "extra_volume_size",
+  "image",
+  "user",
+  "hostname",
+  "tags",
+  "metadata",
+];
+
+/// A `key=value` Terraform variable passed through untouched.
+#[derive(Clone, Debug)]
+pub struct TemplateVar {
+  pub key: String,
+  pub value: String,
+}
+
+impl std::str::FromStr for TemplateVar {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (key, value) = s
+      .split_once('=')
+      .ok_or_else(|| "Variable must be in format key=value".to_string())?;
+    if key.is_empty() {
+      return Err("Variable name must not be empty".into());
+    }
+    Ok(TemplateVar {
+      key: key.to_string(),
+      value: value.to_string(),
+    })
+  }
+}
+
+/// A `KEY=VALUE` pair exported to the startup script.
+#[derive(Clone)]
+pub struct EnvVar {
+  pub key: String,
+  pub value: String,
+}
+
+impl std::str::FromStr for EnvVar {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (key, value) = s
+      .split_once('=')
+      .ok_or_else(|| "Variable must be in format KEY=VALUE".to_string())?;
+    let valid = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+      && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+      return Err(format!("{:?} is not a valid shell variable name", key));
+    }
+    Ok(EnvVar {
+      key: key.to_string(),
+      value: value.to_string(),
+    })
+  }
+}
+
+fn insert_extra_vars(
+  map: &mut HashMap<String, String>,
+  extra_vars: &[TemplateVar],
+) {
+  for var in extra_vars {
+    map.insert(var.key.clone(), var.value.clone());
+  }
+}
+
+fn write_teardown(table: &mut Table, teardown: &Option<teardown::Script>) {
+  if let Some(script) = teardown {
+    table.row(
+      "teardown_script",
+      format!("{:?} (timeout {}s)", script.path, script.timeout.as_secs()),
+    );
+  }
+}
+
+fn write_extra_vars(table: &mut Table, extra_vars: &[TemplateVar]) {
+  for (i, var) in extra_vars.iter().enumerate() {
+    let key = if i == 0 { "extra_vars" } else { "" };
+    table.row(key, format!("{}={}", var.key, var.value));
+  }
+}
+
+/// CPU architectures the templates know how to pick images for.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Arch {
+  #[value(name = "x86_64")]
+  X86_64,
+  Arm64,
+}
+
+impl fmt::Display for Arch {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Arch::X86_64 => write!(f, "x86_64"),
+      Arch::Arm64 => write!(f, "arm64"),
+    }
+  }
+}
+
+/// What to do when the controlling terminal goes away (SIGHUP).
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum HangupAction {
+  /// Destroy the deployment, as on SIGTERM.
+  Destroy,
+  /// Exit and leave the deployment running for a later `fuoco undeploy`.
+  Keep,
+}
+
+/// How the params are printed before a deploy or undeploy.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ParamsFormat {
+  /// A table, with placeholders for what fuoco resolves later.
+  Text,
+  /// One JSON object with the resolved values: see `resolved`.
+  Json,
+}
+
+/// Supported cloud providers. The aliases are accepted but left out of
+/// the help.
+#[derive(ValueEnum, Clone, Debug)]
+pub enum Provider {
+  #[value(aliases = ["amazon", "ec2"])]
+  AWS,
+  #[value(aliases = ["google", "gce"])]
+  GCP,
+  #[value(alias = "hcloud")]
+  Hetzner,
+  #[value(aliases = ["oci", "oracle-cloud"])]
+  Oracle,
+  #[value(alias = "akamai")]
+  Linode,
+  #[value(alias = "scw")]
+  Scaleway,
+}
+
+/// A port opened to the internet, `tcp:8080` on the command line.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct InboundRule {
+  pub protocol: String,
+  pub port_number: u16,
+}
+
+impl std::str::FromStr for InboundRule {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() != 2 {
+      return Err("Inbound rule must be in format protocol:port".into());
+    }
+    let protocol = parts[0].to_string();
+    let port_number = parts[1]
+      .parse::<u16>()
+      .map_err(|_| "Invalid port number".to_string())?;
+    Ok(InboundRule {
+      protocol,
+      port_number,
+    })
+  }
+}
+
+/// Canonical `protocol:port` form, the same syntax `from_str` accepts.
+impl fmt::Display for InboundRule {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}", self.protocol, self.port_number)
+  }
+}
+
+/// `-` stands for stdin wherever a script path is accepted.
+pub fn is_stdin(path: &Path) -> bool {
+  path.as_os_str() == "-"
+}
+
+/// User data limits per provider. AWS counts the decoded bytes; OCI and
+/// Linode count the base64 they are sent as; GCP, Hetzner and Scaleway take
+/// plain text only, so they cannot be sent gzipped.
+pub fn user_data_limit(provider: &Provider) -> userdata::Limit {
+  let (max_bytes, base64, gzip) = match provider {
+    Provider::AWS => (16 * 1024, false, true),
+    Provider::GCP => (256 * 1024, false, false),
+    Provider::Hetzner => (32 * 1024, false, false),
+    Provider::Oracle => (32_000, true, true),
+    Provider::Linode => (65_535, true, true),
+    Provider::Scaleway => (32 * 1024, false, false),
+  };
+  userdata::Limit {
+    max_bytes,
+    base64,
+    gzip,
+  }
+}
+
+/// Where the template for a run came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TemplateSource {
+  /// `--template <DIR>`.
+  Flag,
+  /// `~/.config/fuoco/templates/<provider>/main.tf`.
+  Override,
+  Builtin,
+}
+
+impl fmt::Display for TemplateSource {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let source = match self {
+      TemplateSource::Flag => "custom",
+      TemplateSource::Override => "override",
+      TemplateSource::Builtin => "builtin",
+    };
+    write!(f, "{}", source)
+  }
+}
+
+/// The provider's name in templates, workspaces and records.
+pub fn provider_str(provider: &Provider) -> &'static str {
+  match provider {
+    Provider::AWS => "aws",
+    Provider::GCP => "gcp",
+    Provider::Hetzner => "hetzner",
+    Provider::Oracle => "oracle",
+    Provider::Linode => "linode",
+    Provider::Scaleway => "scaleway",
+  }
+}
+
+/// Determine the path to the Terraform template for the given provider.
+pub fn template_path(provider_str: &str) -> Result<PathBuf> {
+  let manifest =
+    env::var("CARGO_MANIFEST_DIR").context("CARGO_MANIFEST_DIR is not set")?;
+  let mut path = PathBuf::from(manifest);
+  path.push("templates");
+  path.push(provider_str);
+  path.push("main.tf");
+  Ok(path)
+}
+
+/// `$XDG_CONFIG_HOME/fuoco`, falling back to `~/.config/fuoco`.
+pub fn config_dir() -> Option<PathBuf> {
+  let base = env::var_os("XDG_CONFIG_HOME")
+    .filter(|dir| !dir.is_empty())
+    .map(PathBuf::from)
+    .or_else(|| {
+      env::var_os("HOME").map(|home| Path::new(&home).join(".config"))
+    })?;
+  Some(base.join("fuoco"))
+}
+
+/// `$XDG_CACHE_HOME/fuoco`, falling back to `~/.cache/fuoco`.
+pub fn cache_dir() -> Option<PathBuf> {
+  let base = env::var_os("XDG_CACHE_HOME")
+    .filter(|dir| !dir.is_empty())
+    .map(PathBuf::from)
+    .or_else(|| {
+      env::var_os("HOME").map(|home| Path::new(&home).join(".cache"))
+    })?;
+  Some(base.join("fuoco"))
+}
+
+/// Pick the template for `provider`: `<dir>/main.tf` from --template, then
+/// the user's override under the config directory, then the built-in one.
+/// Deploy and undeploy both go through here so they always agree on the
+/// template, and therefore on the workspace. Paths are canonicalized because
+/// the workspace is keyed on them.
+pub fn resolve_template(
+  provider: &Provider,
+  template: &Option<PathBuf>,
+  no_template_override: bool,
+) -> Result<(PathBuf, TemplateSource)> {
+  let provider_str = provider_str(provider);
+  if let Some(dir) = template {
+    let dir = fs::canonicalize(dir)
+      .with_context(|| format!("Template directory {:?} not found", dir))?;
+    let path = dir.join("main.tf");
+    if !path.is_file() {
+      anyhow::bail!("Template directory {:?} has no main.tf", dir);
+    }
+    return Ok((path, TemplateSource::Flag));
+  }
+  if !no_template_override {
+    let path = config_dir()
+      .map(|dir| dir.join("templates").join(provider_str).join("main.tf"));
+    if let Some(path) = path.filter(|path| path.is_file()) {
+      let path = fs::canonicalize(&path)
+        .with_context(|| format!("Failed to resolve template {:?}", path))?;
+      return Ok((path, TemplateSource::Override));
+    }
+  }
+  Ok((template_path(provider_str)?, TemplateSource::Builtin))
+}
+
+/// `1h 02m 03s`.
+pub fn format_elapsed(elapsed: Duration) -> String {
+  let secs = elapsed.as_secs();
+  format!("{}h {:02}m {:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Where sshd listens unless --ssh-port moves it.
+pub const DEFAULT_SSH_PORT: u16 = 22;
+
+/// Keys tried, in order, when no --ssh-public-key-path is given.
+const DEFAULT_SSH_PUBLIC_KEYS: &[&str] = &[
+  "~/.ssh/id_rsa.pub",
+  "~/.ssh/id_ed25519.pub",
+  "~/.ssh/id_ecdsa.pub",
+];
+
+/// Key types OpenSSH writes into `.pub` files.
+const SSH_KEY_TYPES: &[&str] = &[
+  "ssh-rsa",
+  "ssh-dss",
+  "ssh-ed25519",
+  "ecdsa-sha2-nistp256",
+  "ecdsa-sha2-nistp384",
+  "ecdsa-sha2-nistp521",
+  "sk-ssh-ed25519@openssh.com",
+  "sk-ecdsa-sha2-nistp256@openssh.com",
+];
+
+/// `~/rest` under $HOME; anything else as is.
+pub fn expand_home(path: &str) -> PathBuf {
+  match (path.strip_prefix("~/"), env::var_os("HOME")) {
+    (Some(rest), Some(home)) => Path::new(&home).join(rest),
+    _ => PathBuf::from(path),
+  }
+}
+
+/// The --ssh-public-key-path values, or the first default key that exists.
+pub fn resolve_ssh_public_key_paths(paths: Vec<String>) -> Vec<String> {
+  if !paths.is_empty() {
+    return paths;
+  }
+  DEFAULT_SSH_PUBLIC_KEYS
+    .iter()
+    .find(|path| expand_home(path).is_file())
+    .map(|path| vec![path.to_string()])
+    .unwrap_or_default()
+}
+
+/// Read every public key up front, so a typo fails before anything is
+/// deployed rather than halfway through the apply.
+pub fn read_ssh_public_keys(paths: &[String]) -> Result<Vec<String>> {
+  paths
+    .iter()
+    .map(|path| {
+      let expanded = expand_home(path);
+      if !expanded.is_file() {
+        anyhow::bail!("SSH public key {:?} does not exist", path);
+      }
+      let key = fs::read_to_string(&expanded)
+        .with_context(|| format!("Failed to read SSH public key {:?}", path))?;
+      let key = key.trim();
+      validate_ssh_public_key(path, key)?;
+      Ok(key.to_string())
+    })
+    .collect()
+}
+
+/// `<type> <base64> [comment]` on a single line, as in `id_ed25519.pub`.
+fn validate_ssh_public_key(path: &str, key: &str) -> Result<()> {
+  if key.contains("PRIVATE KEY") {
+    anyhow::bail!(
+      "{:?} is a private key; pass the matching .pub file instead",
+      path
+    );
+  }
+  let mut fields = key.split_whitespace();
+  let key_type = fields.next().unwrap_or("");
+  let valid = !key.contains('\n')
+    && SSH_KEY_TYPES.contains(&key_type)
+    && fields
+      .next()
+      .is_some_and(|blob| BASE64.decode(blob).is_ok());
+  if !valid {
+    anyhow::bail!(
+      "{:?} does not look like an SSH public key (expected one line like \
+       \"ssh-ed25519 AAAA... comment\")",
+      path
+    );
+  }
+  Ok(())
+}
+
+/// Run `script` on the deployed VM. Teardown goes ahead whatever happens,
+/// so failures are only reported.
+fn run_teardown_script(
+  script: &teardown::Script,
+  provider: &Provider,
+  outputs: &HashMap<String, String>,
+  ssh_public_key_path: Option<&str>,
+  ssh_port: Option<u16>,
+) {
+  let Some(target) =
+    ssh_target(provider, outputs, ssh_public_key_path, ssh_port)
+  else {
+    log::warn!("no IP to run the teardown script on");
+    return;
+  };
+  if let Err(err) = teardown::run(&target, script) {
+    log::warn!("{:#}; destroying anyway", err);
+  }
+}
+
+/// How to log in to the deployed VM: the `ssh_user` output, the private
+/// half of `ssh_public_key_path`, and --ssh-port.
+pub fn ssh_target(
+  provider: &Provider,
+  outputs: &HashMap<String, String>,
+  ssh_public_key_path: Option<&str>,
+  ssh_port: Option<u16>,
+) -> Option<teardown::Target> {
+  Some(teardown::Target {
+    ip: instance_ip(provider, outputs)?.to_string(),
+    port: ssh_port.unwrap_or(DEFAULT_SSH_PORT),
+    user: outputs
+      .get("ssh_user")
+      .cloned()
+      .unwrap_or_else(|| "root".to_string()),
+    identity: ssh_public_key_path.and_then(teardown::identity_for),
+  })
+}
+
+/// The `fuoco undeploy` invocation that finds this deployment's workspace
+/// and destroys it.
+pub fn undeploy_command(
+  params: &DeployParams,
+  hash_map: &HashMap<String, String>,
+) -> String {
+  let mut args = vec![
+    "fuoco".to_string(),
+    "undeploy".to_string(),
+    "--provider".to_string(),
+    provider_str(&params.provider).to_string(),
+    "--region".to_string(),
+    location(hash_map).to_string(),
+    "--name".to_string(),
+    params.name.clone(),
+    "--instance-type".to_string(),
+    hash_map["instance_type"].clone(),
+    "--arch".to_string(),
+    hash_map["arch"].clone(),
+  ];
+  // The template's directory is part of what keys the workspace
+  if let Some(dir) = params.template_path.parent() {
+    args.push("--template".to_string());
+    args.push(dir.display().to_string());
+  }
+  if let Some(workdir) = &params.workdir {
+    args.push("--workdir".to_string());
+    args.push(workdir.display().to_string());
+  }
+  if let Some(compartment_id) = &params.compartment_id {
+    args.push("--compartment-id".to_string());
+    args.push(compartment_id.clone());
+  }
+  // Set by --aws-profile too
+  if let (Provider::AWS, Ok(profile)) =
+    (&params.provider, env::var("AWS_PROFILE"))
+  {
+    args.push("--aws-profile".to_string());
+    args.push(profile);
+  }
+  for var in &params.extra_vars {
+    args.push("--var".to_string());
+    args.push(format!("{}={}", var.key, var.value));
+  }
+  if params
+    .extra_vars
+    .iter()
+    .any(|var| FUOCO_VARIABLES.contains(&var.key.as_str()))
+  {
+    args.push("--var-override".to_string());
+  }
+  args
+    .iter()
+    .map(|arg| {
+      let plain = !arg.is_empty()
+        && arg
+          .chars()
+          .all(|c| c.is_ascii_alphanumeric() || "-_./=:,@".contains(c));
+      if plain {
+        arg.clone()
+      } else {
+        userdata::shell_quote(arg)
+      }
+    })
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+/// atar keeps its workspaces under the system temp dir, so --workdir works
+/// by pointing TMPDIR at it before anything else runs. Terraform inherits
+/// it too, which keeps its scratch files on the same disk.
+pub fn use_workdir(workdir: &Path) -> Result<()> {
+  fs::create_dir_all(workdir)
+    .with_context(|| format!("Failed to create workdir {:?}", workdir))?;
+  let workdir = fs::canonicalize(workdir)
+    .with_context(|| format!("Failed to resolve workdir {:?}", workdir))?;
+  env::set_var("TMPDIR", workdir);
+  Ok(())
+}
+
+/// The Hetzner template reads its API token from the `token` variable,
+/// while Hetzner's own tools read HCLOUD_TOKEN: pass that on to Terraform
+/// as TF_VAR_token. An existing TF_VAR_token wins.
+pub fn use_hetzner_token() {
+  if env::var_os("TF_VAR_token").is_some() {
+    return;
+  }
+  if let Some(token) = env::var_os("HCLOUD_TOKEN") {
+    env::set_var("TF_VAR_token", token);
+  }
+}
+
+/// The Hetzner API token Terraform will be given.
+pub(crate) fn hetzner_token() -> Result<String> {
+  env::var("TF_VAR_token").context("TF_VAR_token is not set")
+}
+
+/// The Terraform workspace atar uses for `template_path`: one directory per
+/// template directory, named after the SHA-256 of its path.
+pub fn workspace_dir(template_path: &Path) -> Result<PathBuf> {
+  let template_dir = template_path
+    .parent()
+    .context("Cannot determine Terraform directory")?;
+  let mut hasher = Sha256::new();
+  hasher.update(template_dir.to_string_lossy().as_bytes());
+  let hash = format!("{:x}", hasher.finalize());
+  Ok(env::temp_dir().join("atar").join(hash))
+}
+
+/// Output names that carry the instance's public IP, in order of preference.
+/// The built-in templates all use `public_ip`; the rest match what each
+/// provider calls the attribute, for custom templates that pass it through.
+pub fn public_ip_output_keys(provider: &Provider) -> &'static [&'static str] {
+  match provider {
+    Provider::AWS => &["public_ip", "public_ip_address"],
+    Provider::GCP => &["public_ip", "nat_ip"],
+    Provider::Hetzner => &["public_ip", "ipv4_address"],
+    Provider::Oracle => &["public_ip", "public_ip_address"],
+    Provider::Linode => &["public_ip", "ip_address"],
+    Provider::Scaleway => &["public_ip", "address"],
+  }
+}
+
+/// The address to reach the instance on: its public IP, or the `private_ip`
+/// output of a --no-public-ip deployment.
+pub fn instance_ip<'a>(
+  provider: &Provider,
+  outputs: &'a HashMap<String, String>,
+) -> Option<&'a str> {
+  public_ip(provider, outputs).or_else(|| {
+    outputs
+      .get("private_ip")
+      .map(|ip| ip.as_str())
+      .filter(|ip| !ip.is_empty())
+  })
+}
+
+/// The deployment's public IP, from whichever known output the template set.
+fn public_ip<'a>(
+  provider: &Provider,
+  outputs: &'a HashMap<String, String>,
+) -> Option<&'a str> {
+  public_ip_output_keys(provider)
+    .iter()
+    .filter_map(|key| outputs.get(*key))
+    .map(|ip| ip.as_str())
+    .find(|ip| !ip.is_empty())
+}
+
+/// Apply `params` once they `validate`. A failed apply destroys whatever
+/// it created; once it succeeds, the handle destroys the deployment when
+/// dropped.
+pub fn deploy(params: DeployParams) -> Result<DeployHandle> {
+  deploy_until_killed(params, watchdog::KillHandle::default())
+}
+
+/// `deploy`, whose apply stops once `kill` is used.
+fn deploy_until_killed(
+  mut params: DeployParams,
+  kill: watchdog::KillHandle,
+) -> Result<DeployHandle> {
+  params.validate()?;
+  if resolve_region(&params.provider, params.region.as_deref()).is_none() {
+    region_candidates(
+      &params.provider,
+      &params.region_filter,
+      &params.region_exclude,
+    )?;
+    // Drawn here rather than in `to_atar_map` so that it gets recorded
+    params.region_seed.get_or_insert_with(rand::random);
+  }
+  let deployment = Deployment::new(params).with_kill_handle(kill);
+  deployment.check_credentials()?;
+  deployment.deploy()
+}
+
+/// `deploy` on a worker thread, as a future any executor can poll.
+/// Dropping the future before it is ready stops this deployment's
+/// Terraform and destroys whatever the apply had created.
+pub fn deploy_async(params: DeployParams) -> task::Task<Result<DeployHandle>> {
+  let kill = watchdog::KillHandle::default();
+  let worker_kill = kill.clone();
+  task::spawn(move || deploy_until_killed(params, worker_kill))
+    .on_drop(move || kill.kill())
+}
+
+/// Destroy a deployment that was left running, e.g. with
+/// `DeployHandle::keep`. Unless `params.yes`, `confirm` is asked first,
+/// with the Terraform variables and the outputs the workspace holds.
+pub fn undeploy(
+  params: &UndeployParams,
+  confirm: impl FnOnce(
+    &HashMap<String, String>,
+    &Result<HashMap<String, String>>,
+  ) -> Result<bool>,
+) -> Result<()> {
+  let hash_map = params.to_atar_map();
+  match params.params_format {
+    ParamsFormat::Text => status!("{}", params),
+    ParamsFormat::Json => status!(
+      "{}",
+      serde_json::to_string(&resolved::undeploy(params, &hash_map))?
+    ),
+  }
+  let workspace = workspace_dir(&params.workspace_template)?;
+  let existed = workspace.exists();
+  // Locking creates the workspace, which would hide a legacy one next time
+  let _lock = existed
+    .then(|| lock::acquire(&workspace, params.wait_lock))
+    .transpose()?;
+  let outputs = if existed {
+    teardown::workspace_outputs(&workspace)
+  } else {
+    Err(anyhow::anyhow!("no Terraform workspace at {:?}", workspace))
+  };
+  if !params.yes && !confirm(&hash_map, &outputs)? {
+    anyhow::bail!("Undeploy cancelled");
+  }
+  let timings = Timings::default();
+  if let Some(script) = &params.teardown {
+    timings.time("teardown", || match &outputs {
+      Ok(outputs) => run_teardown_script(
+        script,
+        &params.provider,
+        outputs,
+        params.ssh_public_key_path.as_deref(),
+        params.ssh_port,
+      ),
+      Err(err) => log::warn!(
+        "skipping the teardown script, no deployment found: {:#}",
+        err
+      ),
+    });
+  }
+  let result = timings
+    .time("destroy", || {
+      if params.force {
+        return force_undeploy(params, &hash_map, &workspace);
+      }
+      undeploy_with_timeout(params, &hash_map)?;
+      failed::clear(&workspace);
+      Ok(())
+    })
+    .exit_status(exit::DESTROY_FAILED);
+  timings.print(params.timings, None);
+  // A legacy workspace runs the user's own template, with no record beside
+  if params.workspace_template != params.template_path {
+    let recorded = workspace::record_timings(
+      &params.workspace_template,
+      "undeploy",
+      timings.to_json(),
+    );
+    if let Err(err) = recorded {
+      log::warn!("could not record the timings: {:#}", err);
+    }
+  }
+  result
+}
+
+/// `undeploy --force`: get past a broken state as far as Terraform can,
+/// then forget the workspace and say what may still be running.
+fn force_undeploy(
+  params: &UndeployParams,
+  hash_map: &HashMap<String, String>,
+  workspace: &Path,
+) -> Result<()> {
+  // Read first: the state may be unreadable once the destroy has failed
+  let recorded = force::state_list(workspace).unwrap_or_default();
+  let left = match undeploy_with_timeout(params, hash_map) {
+    Ok(()) => Some(Vec::new()),
+    Err(err) => {
+      log::warn!(
+        "{:#}; destroying without a refresh, resource by resource",
+        err
+      );
+      let workspace = workspace.to_path_buf();
+      let hash_map = hash_map.clone();
+      let debug = params.verbosity.terraform();
+      watchdog::run(
+        "terraform destroy",
+        Some(params.destroy_timeout),
+        move |_| Ok(force::destroy(&workspace, &hash_map, debug)),
+      )
+      .unwrap_or_else(|err| {
+        log::warn!("{:#}", err);
+        None
+      })
+    }
+  };
+  if workspace.exists() {
+    fs::remove_dir_all(workspace).with_context(|| {
+      format!("Failed to remove the Terraform workspace {:?}", workspace)
+    })?;
+  }
+  failed::clear(workspace);
+  let left = match left {
+    Some(left) => left,
+    // Nothing is known past what the state held before the destroy
+    None if recorded.is_empty() => {
+      vec!["(unknown: the state was unreadable)".to_string()]
+    }
+    None => recorded,
+  };
+  if left.is_empty() {
+    status!("Destroyed; removed the workspace {:?}", workspace);
+    return Ok(());
+  }
+  eprintln!("\nNot confirmed destroyed, these resources may still exist:");
+  for address in &left {
+    eprintln!("  {}", address);
+  }
+  anyhow::bail!(
+    "the workspace was removed, but some resources may be left: check the \
+     {} console ({})",
+    provider_str(&params.provider),
+    location(hash_map)
+  )
+}
+
+/// `lib_undeploy` under --destroy-timeout, so a destroy stuck on some
+/// resource cannot block forever.
+fn undeploy_with_timeout(
+  params: &UndeployParams,
+  hash_map: &HashMap<String, String>,
+) -> Result<()> {
+  let template_path = params.workspace_template.clone();
+  let hash_map = hash_map.clone();
+  let (debug, retries) = (params.verbosity.terraform(), params.retries);
+  let mark = capture::mark();
+  let result = watchdog::run(
+    "terraform destroy",
+    Some(params.destroy_timeout),
+    move |stopped| {
+      retry::with_retries("terraform destroy", retries, stopped, || {
+        lib_undeploy(&template_path, &hash_map, debug)
+      })
+    },
+  )
+  .with_context(|| {
+    format!(
+      "check the {} console for leftover resources",
+      provider_str(&params.provider)
+    )
+  });
+  capture::attach(result, mark)
+}
+
+/// Environment variables the provider's own tools read the default region
+/// from, in order of precedence.
+pub fn region_env_vars(provider: &Provider) -> &'static [&'static str] {
+  match provider {
+    Provider::AWS => &["AWS_REGION", "AWS_DEFAULT_REGION"],
+    Provider::GCP => &["CLOUDSDK_COMPUTE_ZONE", "CLOUDSDK_COMPUTE_REGION"],
+    Provider::Oracle => &["OCI_CLI_REGION"],
+    Provider::Scaleway => &["SCW_DEFAULT_ZONE"],
+    // Neither hcloud nor linode-cli reads a region from the environment
+    Provider::Hetzner | Provider::Linode => &[],
+  }
+}
+
+/// The region to deploy to and where it came from: `--region`, then the
+/// provider's environment variable. `None` leaves the choice to
+/// --region-strategy.
+pub fn resolve_region(
+  provider: &Provider,
+  cli_region: Option<&str>,
+) -> Option<(String, &'static str)> {
+  if let Some(region) = cli_region {
+    return Some((region.to_string(), "--region"));
+  }
+  region_env_vars(provider).iter().find_map(|var| {
+    let region = env::var(var).ok().filter(|region| !region.is_empty())?;
+    Some((region, *var))
+  })
+}
+
+/// The regions fuoco may pick for `provider`: those it knows (see
+/// `regions::available`) that match any of `filter` (all of them when it is
+/// empty), minus `exclude`. A pattern with `*` or `?` is a glob, anything
+/// else a prefix.
+pub fn region_candidates(
+  provider: &Provider,
+  filter: &[String],
+  exclude: &[String],
+) -> Result<Vec<&'static str>> {
+  let (available, _) = regions::available(provider);
+  for region in exclude {
+    let region = region.as_str();
+    if !available.contains(&region)
+      && !known_regions(provider).contains(&region)
+      && !opt_in_regions(provider).contains(&region)
+    {
+      anyhow::bail!(
+        "--region-exclude {}: not a {:?} region fuoco knows ({})",
+        region,
+        provider,
+        available.join(", ")
+      );
+    }
+  }
+  let matching: Vec<_> = available
+    .iter()
+    .copied()
+    .filter(|region| {
+      filter.is_empty()
+        || filter.iter().any(|pattern| region_matches(pattern, region))
+    })
+    .collect();
+  if matching.is_empty() {
+    anyhow::bail!(
+      "--region-filter {} matches none of the {:?} regions fuoco knows: {}",
+      filter.join(","),
+      provider,
+      available.join(", ")
+    );
+  }
+  let candidates: Vec<_> = matching
+    .iter()
+    .copied()
+    .filter(|region| !exclude.iter().any(|excluded| excluded == region))
+    .collect();
+  if candidates.is_empty() {
+    anyhow::bail!(
+      "--region-exclude leaves no {:?} region to pick from ({})",
+      provider,
+      matching.join(", ")
+    );
+  }
+  Ok(candidates)
+}
+
+fn region_matches(pattern: &str, region: &str) -> bool {
+  if !pattern.contains(['*', '?']) {
+    return region.starts_with(pattern);
+  }
+  fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+      (None, None) => true,
+      (Some(b'*'), _) => {
+        glob(&pattern[1..], text)
+          || (!text.is_empty() && glob(pattern, &text[1..]))
+      }
+      (Some(b'?'), Some(_)) => glob(&pattern[1..], &text[1..]),
+      (Some(p), Some(t)) if p == t => glob(&pattern[1..], &text[1..]),
+      _ => false,
+    }
+  }
+  glob(pattern.as_bytes(), region.as_bytes())
+}
+
+/// A region drawn with `seed` from the `region_candidates`; on GCP, a zone
+/// of it. The same seed, provider and candidates always draw the same one.
+pub fn resolve_random_region(
+  provider: &Provider,
+  filter: &[String],
+  exclude: &[String],
+  seed: u64,
+) -> Result<String> {
+  let candidates = region_candidates(provider, filter, exclude)?;
+  Ok(draw_region(provider, &candidates, seed))
+}
+
+/// The region `seed` draws from `candidates`; on GCP, a zone of it.
+fn draw_region(provider: &Provider, candidates: &[&str], seed: u64) -> String {
+  let mut rng = SplitMix64(seed);
+  let region = candidates[rng.below(candidates.len())];
+  match provider {
+    Provider::GCP => {
+      let suffixes = gcp_zone_suffixes(region);
+      format!("{}-{}", region, suffixes[rng.below(suffixes.len())])
+    }
+    _ => region.to_string(),
+  }
+}
+
+/// SplitMix64. Written out rather than taken from `rand`, whose seeded
+/// generators may change output between releases and break replays.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+  fn next(&mut self) -> u64 {
+    self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = self.0;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+  }
+
+  /// A number below `n`; the modulo bias is negligible for region lists.
+  fn below(&mut self, n: usize) -> usize {
+    (self.next() % n as u64) as usize
+  }
+}
+
+fn random_region(candidates: &[&str]) -> String {
+  candidates
+    .choose(&mut rand::thread_rng())
+    .expect("no region to choose from")
+    .to_string()
+}
+
+const REGION_PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How many of the fastest regions to list.
+const REGION_PROBE_SHOWN: usize = 3;
+
+/// Probe every one of the `region_candidates` and return the one with the
+/// lowest latency, with its round-trip time.
+pub fn resolve_closest_region(
+  provider: &Provider,
+  filter: &[String],
+  exclude: &[String],
+) -> Result<(String, Duration)> {
+  let targets: Vec<(String, String, u16)> =
+    region_candidates(provider, filter, exclude)?
+      .iter()
+      .filter_map(|region| {
+        region_probe_host(provider, region)
+          .map(|host| (region.to_string(), host, 443))
+      })
+      .collect();
+  if targets.is_empty() {
+    anyhow::bail!(
+      "--region-strategy closest is not supported for {:?}: it has no \
+       per-region endpoint to measure",
+      provider
+    );
+  }
+  status!("Probing {} regions for latency...", targets.len());
+  let ranked = latency::rank(targets, REGION_PROBE_TIMEOUT);
+  for (region, rtt) in ranked.iter().take(REGION_PROBE_SHOWN) {
+    status!("  {:<16} {} ms", region, rtt.as_millis());
+  }
+  let (region, rtt) = ranked
+    .into_iter()
+    .next()
+    .context("No region answered the latency probe; pass --region instead")?;
+  status!("Closest region: {} ({} ms)", region, rtt.as_millis());
+  Ok((region, rtt))
+}
+
+/// The `region_candidates` where `instance_type` costs the least, with its
+/// USD/hour. Without a price for any of them, one picked at random: pricing
+/// never stops a deploy.
+pub fn resolve_cheapest_region(
+  provider: &Provider,
+  instance_type: &str,
+  filter: &[String],
+  exclude: &[String],
+) -> Result<(String, Option<f64>)> {
+  let candidates = region_candidates(provider, filter, exclude)?;
+  let mut priced = pricing::compare_regions(
+    provider_str(provider),
+    instance_type,
+    &candidates,
+  );
+  if priced.is_empty() {
+    log::warn!(
+      "no price known for {} on {:?}; picking a region at random",
+      instance_type,
+      provider
+    );
+    return Ok((random_region(&candidates), None));
+  }
+  // Stable, so ties go to the first listed region
+  priced.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+  status!("Hourly price of {} by region:", instance_type);
+  for (region, price) in priced.iter().take(REGION_PRICES_SHOWN) {
+    status!("  {:<16} ~${:.4}/hr", region, price);
+  }
+  let (region, price) = priced.swap_remove(0);
+  status!("Cheapest region: {} (~${:.4}/hr)", region, price);
+  Ok((region, Some(price)))
+}
+
+/// How many of the cheapest regions to list.
+const REGION_PRICES_SHOWN: usize = 5;
+
+/// A host that terminates inside `region`, so its handshake time reflects
+/// the distance to that region.
+fn region_probe_host(provider: &Provider, region: &str) -> Option<String> {
+  match provider {
+    Provider::AWS => Some(format!("ec2.{}.amazonaws.com", region)),
+    // Google answers on its regional hostnames from the nearest edge, which
+    // says nothing about where the region is.
+    Provider::GCP => None,
+    Provider::Hetzner => Some(format!("{}-speed.hetzner.com", region)),
+    Provider::Oracle => Some(format!("iaas.{}.oraclecloud.com", region)),
+    // Linode's speedtest hosts are named after cities, not region IDs
+    Provider::Linode => None,
+    Provider::Scaleway => None,
+  }
+}
+
+/// Where a deployment lives, from its Terraform variables: the GCP zone,
+/// or the region elsewhere. Undeploy and the workspace are keyed on it.
+pub fn location(hash_map: &HashMap<String, String>) -> &str {
+  hash_map.get("zone").unwrap_or(&hash_map["region"])
+}
+
+/// Split a GCP location into its region and, for a zone, the zone letter.
+pub(crate) fn split_gcp_location(location: &str) -> (&str, Option<&str>) {
+  match location.rsplit_once('-') {
+    Some((region, suffix))
+      if suffix.len() == 1
+        && suffix.chars().all(|c| c.is_ascii_lowercase()) =>
+    {
+      (region, Some(suffix))
+    }
+    _ => (location, None),
+  }
+}
+
+/// Accept a GCP region (`us-central1`) or zone (`us-central1-a`); undeploy
+/// needs the exact zone the deploy used, so it requires a zone.
+pub fn validate_gcp_location(location: &str, require_zone: bool) -> Result<()> {
+  let (region, suffix) = split_gcp_location(location);
+  let region_shape = region.split('-').count() == 2
+    && region.ends_with(|c: char| c.is_ascii_digit())
+    && region
+      .chars()
+      .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+  if !region_shape {
+    anyhow::bail!(
+      "{:?} is not a GCP region or zone (e.g. us-central1 or us-central1-a)",
+      location
+    );
+  }
+  match suffix {
+    Some(suffix) if !gcp_zone_suffixes(region).contains(&suffix) => {
+      anyhow::bail!(
+        "{:?} is not a zone of {} (valid zones: {})",
+        location,
+        region,
+        gcp_zone_suffixes(region)
+          .iter()
+          .map(|s| format!("{}-{}", region, s))
+          .collect::<Vec<_>>()
+          .join(", ")
+      )
+    }
+    None if require_zone => anyhow::bail!(
+      "GCP needs the full zone the deployment used (e.g. {}-{}), not the \
+       region {}",
+      region,
+      gcp_zone_suffixes(region)[0],
+      region
+    ),
+    _ => Ok(()),
+  }
+}
+
+/// Turn a GCP region into one of its zones; zones are returned unchanged.
+pub fn resolve_gcp_zone(location: &str) -> String {
+  match split_gcp_location(location) {
+    (_, Some(_)) => location.to_string(),
+    (region, None) => resolve_random_zone(region),
+  }
+}
+
+fn resolve_random_zone(region: &str) -> String {
+  let suffix = gcp_zone_suffixes(region)
+    .choose(&mut rand::thread_rng())
+    .expect("Cannot resolve random zone for GCP");
+  format!("{}-{}", region, suffix)
+}
+
+/// Zone letters per GCP region; most regions use a, b and c.
+fn gcp_zone_suffixes(region: &str) -> &'static [&'static str] {
+  match region {
+    "us-central1" => &["a", "b", "c", "f"],
+    "us-east1" | "europe-west1" => &["b", "c", "d"],
+    _ => &["a", "b", "c"],
+  }
+}
+
+/// The most rules, inbound and outbound together, the firewall of each
+/// built-in template can hold, and how many outbound rules it adds itself.
+pub fn firewall_rule_limit(provider: &Provider) -> Option<(usize, usize)> {
+  match provider {
+    // 60 inbound rules a security group; egress has its own 60
+    Provider::AWS => Some((61, 1)),
+    // One firewall rule lists every port
+    Provider::GCP => None,
+    // The template opens no firewall
+    Provider::Hetzner => None,
+    // 120 rules a network security group; security lists allow more
+    Provider::Oracle => Some((120, 1)),
+    // 25 rules a firewall; the outbound policy accepts everything
+    Provider::Linode => Some((25, 0)),
+    // The security group's default policies stand in for outbound rules
+    Provider::Scaleway => None,
+  }
+}
+
+/// Regions (Hetzner: locations) fuoco picks from when none is given and
+/// no fresh live list is cached (see `regions`). Only those every account
+/// has enabled: see `opt_in_regions`.
+pub fn known_regions(provider: &Provider) -> &'static [&'static str] {
+  match provider {
+    Provider::AWS => &[
+      "us-east-1",
+      "us-east-2",
+      "us-west-1",
+      "us-west-2",
+      "ap-south-1",
+      "ap-northeast-3",
+      "ap-northeast-2",
+      "ap-southeast-1",
+      "ap-southeast-2",
+      "ap-northeast-1",
+      "ca-central-1",
+      "eu-central-1",
+      "eu-west-1",
+      "eu-west-2",
+      "eu-west-3",
+      "eu-north-1",
+      "sa-east-1",
+    ],
+    Provider::GCP => &[
+      "us-central1",
+      "us-east1",
+      "us-east4",
+      "us-west1",
+      "us-west2",
+      "us-west3",
+      "us-west4",
+      "northamerica-northeast1",
+      "southamerica-east1",
+      "europe-west1",
+      "europe-west2",
+      "europe-west3",
+      "europe-west4",
+      "europe-west6",
+      "europe-west8",
+      "europe-west9",
+      "europe-north1",
+      "europe-southwest1",
+      "asia-east1",
+      "asia-east2",
+      "asia-northeast1",
+      "asia-northeast2",
+      "asia-northeast3",
+      "asia-south1",
+      "asia-south2",
+      "asia-southeast1",
+      "asia-southeast2",
+      "australia-southeast1",
+      "australia-southeast2",
+      "me-central1",
+      "me-west1",
+    ],
+    Provider::Hetzner => &["fsn1", "nbg1", "hel1", "ash", "hil"],
+    Provider::Oracle => &[
+      "us-ashburn-1",
+      "us-phoenix-1",
+      "us-sanjose-1",
+      "us-chicago-1",
+      "ca-toronto-1",
+      "ca-montreal-1",
+      "sa-saopaulo-1",
+      "uk-london-1",
+      "eu-frankfurt-1",
+      "eu-amsterdam-1",
+      "eu-zurich-1",
+      "eu-paris-1",
+      "eu-stockholm-1",
+      "eu-madrid-1",
+      "eu-milan-1",
+      "ap-tokyo-1",
+      "ap-osaka-1",
+      "ap-seoul-1",
+      "ap-mumbai-1",
+      "ap-singapore-1",
+      "ap-sydney-1",
+      "ap-melbourne-1",
+    ],
+    Provider::Linode => &[
+      "us-east",
+      "us-central",
+      "us-west",
+      "us-southeast",
+      "ca-central",
+      "eu-west",
+      "eu-central",
+      "ap-south",
+      "ap-northeast",
+      "ap-west",
+      "ap-southeast",
+    ],
+    // Zones, which every instance lives in; those with DEV1 instances
+    Provider::Scaleway => &["fr-par-1", "nl-ams-1", "pl-waw-1"],
+  }
+}
+
+/// Regions an account has to enable before deploying there, so fuoco
+/// never picks them itself; `--region` still reaches them.
+pub fn opt_in_regions(provider: &Provider) -> &'static [&'static str] {
+  match provider {
+    Provider::AWS => &[
+      "af-south-1",
+      "ap-east-1",
+      "ap-south-2",
+      "ap-southeast-3",
+      "ap-southeast-4",
+      "ca-west-1",
+      "eu-central-2",
+      "eu-south-1",
+      "eu-south-2",
+      "il-central-1",
+      "me-central-1",
+      "me-south-1",
+    ],
+    _ => &[],
+  }
+}
+
+/// Root disk size bounds in GiB, or `None` when the disk cannot be resized.
+pub fn disk_size_limits(provider: &Provider) -> Option<(u32, u32)> {
+  match provider {
+    Provider::AWS => Some((8, 16384)),
+    Provider::GCP => Some((10, 65536)),
+    Provider::Hetzner => None,
+    Provider::Oracle => Some((50, 32768)),
+    Provider::Linode => None,
+    Provider::Scaleway => None,
+  }
+}
+
+/// Extra data volume size bounds in GiB.
+pub fn extra_volume_limits(provider: &Provider) -> (u32, u32) {
+  match provider {
+    Provider::AWS => (1, 16384),
+    Provider::GCP => (10, 65536),
+    Provider::Hetzner => (10, 10240),
+    Provider::Oracle => (50, 32768),
+    Provider::Linode => (10, 10240),
+    Provider::Scaleway => (5, 10000),
+  }
+}
+
+/// The --inbound-rule list, or SSH alone when there is none. --ssh-port
+/// opens its port in place of 22, or next to the explicit rules.
+pub fn resolve_inbound_rules(
+  rules: Option<&Vec<InboundRule>>,
+  ssh_port: Option<u16>,
+) -> Vec<InboundRule> {
+  let ssh_rule = |port_number| InboundRule {
+    protocol: "tcp".to_string(),
+    port_number,
+  };
+  let Some(rules) = rules else {
+    return vec![ssh_rule(ssh_port.unwrap_or(DEFAULT_SSH_PORT))];
+  };
+  let mut rules = rules.clone();
+  if let Some(rule) = ssh_port.map(ssh_rule) {
+    if !rules.contains(&rule) {
+      rules.push(rule);
+    }
+  }
+  rules
+}
+
+/// The instance type deployed when none is given.
+pub fn resolve_default_instance_type(
+  provider: &Provider,
+  arch: Arch,
+) -> String {
+  match (provider, arch) {
+    (Provider::AWS, Arch::X86_64) => "t3.micro".to_string(),
+    (Provider::AWS, Arch::Arm64) => "t4g.nano".to_string(),
+    (Provider::GCP, Arch::X86_64) => "e2-micro".to_string(),
+    (Provider::GCP, Arch::Arm64) => "t2a-standard-1".to_string(),
+    (Provider::Hetzner, Arch::X86_64) => "cx11".to_string(),
+    (Provider::Hetzner, Arch::Arm64) => "cax11".to_string(),
+    (Provider::Oracle, Arch::X86_64) => "VM.Standard.E2.1.Micro".to_string(),
+    (Provider::Oracle, Arch::Arm64) => "VM.Standard.A1.Flex".to_string(),
+    // No Arm plans; require_arch_support rejects arm64 before this matters
+    (Provider::Linode, _) => "g6-nanode-1".to_string(),
+    (Provider::Scaleway, Arch::X86_64) => "DEV1-S".to_string(),
+    (Provider::Scaleway, Arch::Arm64) => "COPARM1-2C-8G".to_string(),
+  }
+}
+
+/// Curated subset of each provider's instance catalog.
+pub fn known_instance_types(provider: &Provider) -> &'static [&'static str] {
+  match provider {
+    Provider::AWS => &[
+      "t2.nano",
+      "t2.micro",
+      "t2.small",
+      "t2.medium",
+      "t2.large",
+      "t2.xlarge",
+      "t2.2xlarge",
+      "t3.nano",
+      "t3.micro",
+      "t3.small",
+      "t3.medium",
+      "t3.large",
+      "t3.xlarge",
+      "t3.2xlarge",
+      "t3a.nano",
+      "t3a.micro",
+      "t3a.small",
+      "t3a.medium",
+      "t3a.large",
+      "t3a.xlarge",
+      "t3a.2xlarge",
+      "t4g.nano",
+      "t4g.micro",
+      "t4g.small",
+      "t4g.medium",
+      "t4g.large",
+      "t4g.xlarge",
+      "t4g.2xlarge",
+      "m5.large",
+      "m5.xlarge",
+      "m5.2xlarge",
+      "m5.4xlarge",
+      "m6i.large",
+      "m6i.xlarge",
+      "m6i.2xlarge",
+      "m6i.4xlarge",
+      "m6g.medium",
+      "m6g.large",
+      "m6g.xlarge",
+      "m6g.2xlarge",
+      "m6g.4xlarge",
+      "m7g.medium",
+      "m7g.large",
+      "m7g.xlarge",
+      "m7g.2xlarge",
+      "c5.large",
+      "c5.xlarge",
+      "c5.2xlarge",
+      "c5.4xlarge",
+      "c6i.large",
+      "c6i.xlarge",
+      "c6i.2xlarge",
+      "c6i.4xlarge",
+      "c6g.medium",
+      "c6g.large",
+      "c6g.xlarge",
+      "c6g.2xlarge",
+      "c7g.medium",
+      "c7g.large",
+      "c7g.xlarge",
+      "c7g.2xlarge",
+      "r5.large",
+      "r5.xlarge",
+      "r5.2xlarge",
+      "r6i.large",
+      "r6i.xlarge",
+      "r6g.large",
+      "r6g.xlarge",
+      "a1.medium",
+      "a1.large",
+      "a1.xlarge",
+    ],
+    Provider::GCP => &[
+      "f1-micro",
+      "g1-small",
+      "e2-micro",
+      "e2-small",
+      "e2-medium",
+      "e2-standard-2",
+      "e2-standard-4",
+      "e2-standard-8",
+      "e2-standard-16",
+      "e2-highcpu-2",
+      "e2-highcpu-4",
+      "e2-highcpu-8",
+      "e2-highmem-2",
+      "e2-highmem-4",
+      "n1-standard-1",
+      "n1-standard-2",
+      "n1-standard-4",
+      "n1-standard-8",
+      "n2-standard-2",
+      "n2-standard-4",
+      "n2-standard-8",
+      "n2d-standard-2",
+      "n2d-standard-4",
+      "n2d-standard-8",
+      "t2d-standard-1",
+      "t2d-standard-2",
+      "t2d-standard-4",
+      "t2a-standard-1",
+      "t2a-standard-2",
+      "t2a-standard-4",
+      "t2a-standard-8",
+      "c3-standard-4",
+      "c3-standard-8",
+      "c4a-standard-1",
+      "c4a-standard-2",
+      "c4a-standard-4",
+    ],
+    Provider::Hetzner => &[
+      "cx11", "cx21", "cx22", "cx31", "cx32", "cx41", "cx42", "cx51", "cx52",
+      "cpx11", "cpx21", "cpx31", "cpx41", "cpx51", "cax11", "cax21", "cax31",
+      "cax41", "ccx13", "ccx23", "ccx33", "ccx43", "ccx53", "ccx63",
+    ],
+    Provider::Oracle => &[
+      "VM.Standard.A1.Flex",
+      "VM.Standard.A2.Flex",
+      "VM.Standard.E2.1.Micro",
+      "VM.Standard.E3.Flex",
+      "VM.Standard.E4.Flex",
+      "VM.Standard.E5.Flex",
+      "VM.Standard3.Flex",
+      "VM.Standard2.1",
+      "VM.Standard2.2",
+      "VM.Standard2.4",
+    ],
+    Provider::Linode => &[
+      "g6-nanode-1",
+      "g6-standard-1",
+      "g6-standard-2",
+      "g6-standard-4",
+      "g6-standard-6",
+      "g6-standard-8",
+      "g6-dedicated-2",
+      "g6-dedicated-4",
+      "g6-dedicated-8",
+      "g7-highmem-1",
+      "g7-highmem-2",
+    ],
+    Provider::Scaleway => &[
+      "DEV1-S",
+      "DEV1-M",
+      "DEV1-L",
+      "DEV1-XL",
+      "PLAY2-PICO",
+      "PLAY2-NANO",
+      "PLAY2-MICRO",
+      "PRO2-XXS",
+      "PRO2-XS",
+      "PRO2-S",
+      "GP1-XS",
+      "GP1-S",
+      "COPARM1-2C-8G",
+      "COPARM1-4C-16G",
+      "COPARM1-8C-32G",
+    ],
+  }
+}
+
+/// Pick the architecture from --arch, then from the instance type, then from
+/// the provider's cheapest default.
+pub fn resolve_arch(
+  provider: &Provider,
+  arch: Option<Arch>,
+  instance_type: Option<&String>,
+) -> Arch {
+  arch
+    .or_else(|| instance_type.and_then(|t| instance_type_arch(provider, t)))
+    .unwrap_or(match provider {
+      Provider::Oracle => Arch::Arm64,
+      _ => Arch::X86_64,
+    })
+}
+
+/// Infer the architecture of an instance type from its family naming.
+/// Returns `None` for types the catalog does not recognise.
+pub fn instance_type_arch(
+  provider: &Provider,
+  instance_type: &str,
+) -> Option<Arch> {
+  let instance_type = instance_type.to_lowercase();
+  match provider {
+    Provider::AWS => {
+      let family = instance_type.split('.').next()?;
+      // Graviton families carry a "g" right after the generation number
+      // (t4g, m7g, c7gn, ...); a1 is the first-generation Graviton.
+      let generation_end = family.find(|c: char| c.is_ascii_digit())?;
+      let suffix = family[generation_end..]
+        .trim_start_matches(|c: char| c.is_ascii_digit());
+      if family == "a1" || suffix.starts_with('g') {
+        Some(Arch::Arm64)
+      } else {
+        Some(Arch::X86_64)
+      }
+    }
+    Provider::GCP => {
+      let family = instance_type.split('-').next()?;
+      if family == "t2a" || family == "c4a" {
+        Some(Arch::Arm64)
+      } else {
+        Some(Arch::X86_64)
+      }
+    }
+    Provider::Hetzner => {
+      if instance_type.starts_with("cax") {
+        Some(Arch::Arm64)
+      } else if instance_type.starts_with("cx")
+        || instance_type.starts_with("cpx")
+        || instance_type.starts_with("ccx")
+      {
+        Some(Arch::X86_64)
+      } else {
+        None
+      }
+    }
+    Provider::Oracle => {
+      let series = instance_type.split('.').nth(2)?;
+      if series.starts_with('a') {
+        Some(Arch::Arm64)
+      } else {
+        Some(Arch::X86_64)
+      }
+    }
+    Provider::Linode => Some(Arch::X86_64),
+    Provider::Scaleway => {
+      if instance_type.starts_with("coparm") || instance_type.starts_with("amp")
+      {
+        Some(Arch::Arm64)
+      } else {
+        Some(Arch::X86_64)
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn params(provider: Provider) -> DeployParams {
+    let mut params = DeployParams::new(provider).unwrap();
+    params.ssh_public_keys = Vec::new();
+    params
+  }
+
+  #[test]
+  fn static_ip_reaches_the_template() {
+    for provider in [Provider::AWS, Provider::GCP, Provider::Hetzner] {
+      let mut params = params(provider.clone());
+      assert_eq!(params.to_atar_map().get("static_ip"), None);
+      params.static_ip = true;
+      let map = params.to_atar_map();
+      assert_eq!(map["static_ip"], "true", "{:?}", provider);
+    }
+  }
+
+  /// The address is one of the template's resources, so `terraform
+  /// destroy` releases it with the rest.
+  #[test]
+  fn templates_release_the_static_ip() {
+    for provider in [Provider::AWS, Provider::GCP, Provider::Hetzner] {
+      let path = template_path(provider_str(&provider)).unwrap();
+      let template = fs::read_to_string(&path).unwrap();
+      let words: Vec<_> = template.split_whitespace().collect();
+      let template = words.join(" ");
+      assert!(template.contains("variable \"static_ip\""), "{:?}", path);
+      assert!(
+        template.contains("count = var.static_ip ? 1 : 0"),
+        "{:?} allocates no address",
+        path
+      );
+      assert!(!template.contains("prevent_destroy"), "{:?}", path);
+    }
+  }
+
+  #[test]
+  fn atar_map_of_the_defaults() {
+    let params = DeployParams {
+      region: Some("nbg1".to_string()),
+      ..params(Provider::Hetzner)
+    };
+    let map = params.to_atar_map();
+    assert_eq!(map["name"], DEFAULT_NAME);
+    assert_eq!(map["instance_type"], "cx11");
+    assert_eq!(map["arch"], "x86_64");
+    assert_eq!(map["region"], "nbg1");
+    assert_eq!(map["ssh_public_keys"], "[]");
+    assert_eq!(
+      map["inbound_rules"],
+      r#"[{"protocol":"tcp","port_number":22}]"#
+    );
+    for key in [
+      "key_name",
+      "image",
+      "tags",
+      "metadata",
+      "disk_size",
+      "use_spot",
+    ] {
+      assert!(!map.contains_key(key), "{} is set", key);
+    }
+  }
+
+  #[test]
+  fn atar_map_of_the_flags() {
+    let params = DeployParams {
+      region: Some("us-central1-b".to_string()),
+      instance_type: Some("t2a-standard-2".to_string()),
+      ssh_port: Some(2222),
+      spot: true,
+      disk_size: Some(20),
+      metadata: BTreeMap::from([("team".to_string(), "infra".to_string())]),
+      extra_vars: vec!["use_spot=false".parse().unwrap()],
+      ..params(Provider::GCP)
+    };
+    let map = params.to_atar_map();
+    assert_eq!(map["instance_type"], "t2a-standard-2");
+    assert_eq!(map["arch"], "arm64");
+    // The zone goes apart from its region
+    assert_eq!(map["region"], "us-central1");
+    assert_eq!(map["zone"], "us-central1-b");
+    assert_eq!(
+      map["inbound_rules"],
+      r#"[{"protocol":"tcp","port_number":2222}]"#
+    );
+    assert_eq!(map["disk_size"], "20");
+    assert_eq!(map["metadata"], r#"{"team":"infra"}"#);
+    // --var wins over what fuoco sets
+    assert_eq!(map["use_spot"], "false");
+  }
+
+  #[test]
+  fn region_flag_comes_first() {
+    for provider in Provider::value_variants() {
+      assert_eq!(
+        resolve_region(provider, Some("somewhere")),
+        Some(("somewhere".to_string(), "--region"))
+      );
+    }
+    // Nothing in the environment names a Hetzner region
+    assert_eq!(resolve_region(&Provider::Hetzner, None), None);
+  }
+
+  #[test]
+  fn default_instance_types_match_their_arch() {
+    for provider in Provider::value_variants() {
+      for arch in [Arch::X86_64, Arch::Arm64] {
+        let instance_type = resolve_default_instance_type(provider, arch);
+        assert!(
+          known_instance_types(provider).contains(&instance_type.as_str()),
+          "{} is not a known {:?} type",
+          instance_type,
+          provider
+        );
+        // Linode has no Arm plans to default to
+        if !matches!(provider, Provider::Linode) {
+          assert_eq!(
+            instance_type_arch(provider, &instance_type),
+            Some(arch),
+            "{}",
+            instance_type
+          );
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn region_candidates_filter_and_exclude() {
+    let strings = |list: &[&str]| -> Vec<String> {
+      list.iter().map(|s| s.to_string()).collect()
+    };
+    let aws = Provider::AWS;
+    let all = region_candidates(&aws, &[], &[]).unwrap();
+    assert!(all.contains(&"eu-west-1"));
+    let europe = region_candidates(&aws, &strings(&["eu-"]), &[]).unwrap();
+    assert!(!europe.is_empty());
+    assert!(europe.iter().all(|region| region.starts_with("eu-")));
+    let globbed = region_candidates(&aws, &strings(&["eu-*-1"]), &[]).unwrap();
+    assert!(globbed.contains(&"eu-west-1"));
+    assert!(!globbed.contains(&"eu-west-2"));
+    let excluded =
+      region_candidates(&aws, &strings(&["eu-"]), &strings(&["eu-west-1"]))
+        .unwrap();
+    assert_eq!(excluded.len(), europe.len() - 1);
+    assert!(!excluded.contains(&"eu-west-1"));
+    assert!(region_candidates(&aws, &strings(&["mars-"]), &[]).is_err());
+    assert!(region_candidates(&aws, &[], &strings(&["mars-1"])).is_err());
+    assert!(region_candidates(
+      &aws,
+      &strings(&["eu-west-1"]),
+      &strings(&["eu-west-1"])
+    )
+    .is_err());
+  }
+
+  #[test]
+  fn provider_aliases() {
+    for (alias, provider) in [
+      ("amazon", "aws"),
+      ("ec2", "aws"),
+      ("google", "gcp"),
+      ("gce", "gcp"),
+      ("hcloud", "hetzner"),
+      ("oci", "oracle"),
+      ("oracle-cloud", "oracle"),
+      ("akamai", "linode"),
+      ("scw", "scaleway"),
+    ] {
+      let parsed = Provider::from_str(alias, true).unwrap();
+      assert_eq!(provider_str(&parsed), provider, "{}", alias);
+    }
+    assert!(Provider::from_str("azure", true).is_err());
+  }
+
+  #[test]
+  fn defaults_validate() {
+    for provider in Provider::value_variants() {
+      let params = DeployParams {
+        compartment_id: Some("ocid1.compartment.oc1..x".to_string()),
+        ..params(provider.clone())
+      };
+      params.validate().unwrap();
+    }
+  }
+
+  #[test]
+  fn invalid_params_are_rejected() {
+    let rejected = [
+      DeployParams {
+        name: "Not_A_Name".to_string(),
+        ..params(Provider::Hetzner)
+      },
+      DeployParams {
+        spot: true,
+        ..params(Provider::Hetzner)
+      },
+      DeployParams {
+        static_ip: true,
+        ..params(Provider::Linode)
+      },
+      DeployParams {
+        image: Some("ubuntu-24.04".to_string()),
+        ..params(Provider::AWS)
+      },
+      DeployParams {
+        instance_type: Some("t9.huge".to_string()),
+        strict_instance_type: true,
+        ..params(Provider::AWS)
+      },
+      DeployParams {
+        disk_size: Some(20),
+        ..params(Provider::Hetzner)
+      },
+      DeployParams {
+        key_name: Some("laptop".to_string()),
+        ..params(Provider::GCP)
+      },
+      DeployParams {
+        metadata: BTreeMap::from([("a".to_string(), "b".to_string())]),
+        ..params(Provider::Linode)
+      },
+      DeployParams {
+        region: Some("nowhere".to_string()),
+        ..params(Provider::GCP)
+      },
+      params(Provider::Oracle),
+    ];
+    for params in rejected {
+      let err = params.validate().unwrap_err();
+      assert_eq!(exit::code(&err), exit::INVALID, "{}", err);
+    }
+  }
+
+  /// A seed must draw the same region in every release, or `--region-seed`
+  /// no longer replays a run. Drawn from the built-in lists, which a
+  /// cached live list would replace.
+  #[test]
+  fn seeds_draw_the_same_regions() {
+    for (provider, pins) in [
+      (Provider::AWS, ["eu-west-1", "ca-central-1", "eu-west-2"]),
+      (
+        Provider::GCP,
+        ["europe-north1-a", "asia-northeast1-b", "asia-southeast1-b"],
+      ),
+      (Provider::Hetzner, ["fsn1", "fsn1", "ash"]),
+      (
+        Provider::Oracle,
+        ["us-phoenix-1", "eu-amsterdam-1", "eu-amsterdam-1"],
+      ),
+      (Provider::Linode, ["us-central", "ap-west", "ap-west"]),
+      (Provider::Scaleway, ["nl-ams-1", "pl-waw-1", "nl-ams-1"]),
+    ] {
+      for (seed, pin) in [0, 1, 42].into_iter().zip(pins) {
+        let region = draw_region(&provider, known_regions(&provider), seed);
+        assert_eq!(region, pin, "{:?} seed {}", provider, seed);
+      }
+    }
+  }
+
+  #[test]
+  fn seeded_draws_repeat() {
+    let filter = vec!["eu-".to_string()];
+    let first = resolve_random_region(&Provider::AWS, &filter, &[], 7).unwrap();
+    assert!(first.starts_with("eu-"));
+    for _ in 0..5 {
+      let again = resolve_random_region(&Provider::AWS, &filter, &[], 7);
+      assert_eq!(again.unwrap(), first);
+    }
+  }
+}
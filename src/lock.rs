@@ -0,0 +1,143 @@
+//! An advisory lock on a Terraform workspace, held for a whole deploy or
+//! undeploy, so two fuoco processes never run Terraform in the same one or
+//! wipe it under each other. The kernel drops the lock when the process
+//! exits, however it exits.
+
+use anyhow::{Context, Result};
+use std::{
+  fs::{self, File, OpenOptions, TryLockError},
+  io::{self, Write},
+  path::Path,
+  process::{self, Command, Stdio},
+  thread,
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+pub const LOCK_FILE: &str = ".fuoco.lock";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Holds the lock until dropped.
+pub struct WorkspaceLock {
+  _file: File,
+}
+
+/// Lock `workspace`, creating it if needed. If another process holds it,
+/// fail at once or, with `wait`, keep trying for that long. The lock of a
+/// process that died was released with it, so there is nothing to break.
+pub fn acquire(
+  workspace: &Path,
+  wait: Option<Duration>,
+) -> Result<WorkspaceLock> {
+  fs::create_dir_all(workspace)
+    .with_context(|| format!("Failed to create {:?}", workspace))?;
+  let path = workspace.join(LOCK_FILE);
+  let deadline = Instant::now() + wait.unwrap_or_default();
+  let mut waiting = false;
+  loop {
+    let mut file = OpenOptions::new()
+      .create(true)
+      .truncate(false)
+      .read(true)
+      .write(true)
+      .open(&path)
+      .with_context(|| format!("Failed to open {:?}", path))?;
+    match file.try_lock() {
+      Ok(()) => {
+        let previous =
+          fs::read_to_string(&path).ok().and_then(|s| parse_owner(&s));
+        if let Some((pid, _)) = previous.filter(|(pid, _)| !is_running(*pid)) {
+          log::info!(
+            "taking over the lock left on {:?} by PID {}, no longer running",
+            workspace,
+            pid
+          );
+        }
+        let started = SystemTime::now()
+          .duration_since(UNIX_EPOCH)
+          .map_or(0, |d| d.as_secs());
+        file.set_len(0)?;
+        write!(file, "{} {}", process::id(), started)?;
+        return Ok(WorkspaceLock { _file: file });
+      }
+      Err(TryLockError::WouldBlock) => {}
+      Err(TryLockError::Error(err)) => {
+        return Err(err).with_context(|| format!("Failed to lock {:?}", path))
+      }
+    }
+    // Someone holds the lock, whatever the file says: a new holder locks
+    // before it writes its PID over the last one's
+    let owner = fs::read_to_string(&path)
+      .ok()
+      .and_then(|s| parse_owner(&s))
+      .filter(|(pid, _)| is_running(*pid));
+    let holder = match owner {
+      Some((pid, started)) => format!(
+        "PID {} (started {} ago)",
+        pid,
+        crate::format_elapsed(elapsed_since(started))
+      ),
+      None => "another process".to_string(),
+    };
+    if Instant::now() >= deadline {
+      anyhow::bail!(
+        "workspace {:?} is in use by {}; pass --wait-lock to wait for it",
+        workspace,
+        holder
+      );
+    }
+    if !waiting {
+      status!("Waiting for {} to release {:?}...", holder, workspace);
+      waiting = true;
+    }
+    thread::sleep(POLL_INTERVAL);
+  }
+}
+
+/// Whether a running process holds the lock on `workspace`.
+pub fn is_held(workspace: &Path) -> bool {
+  let path = workspace.join(LOCK_FILE);
+  let Ok(file) = File::open(&path) else {
+    return false;
+  };
+  matches!(file.try_lock_shared(), Err(TryLockError::WouldBlock))
+}
+
+/// Empty `workspace` but for the lock file, which must stay: removing it
+/// would let a second process lock a new file while this one holds the old.
+pub fn remove_all_but_lock(workspace: &Path) -> io::Result<()> {
+  for entry in fs::read_dir(workspace)? {
+    let path = entry?.path();
+    if path.file_name().is_some_and(|name| name == LOCK_FILE) {
+      continue;
+    }
+    if path.is_dir() {
+      fs::remove_dir_all(&path)?;
+    } else {
+      fs::remove_file(&path)?;
+    }
+  }
+  Ok(())
+}
+
+/// The PID and start time (seconds since the epoch) written by `acquire`.
+fn parse_owner(content: &str) -> Option<(u32, u64)> {
+  let mut fields = content.split_whitespace();
+  Some((fields.next()?.parse().ok()?, fields.next()?.parse().ok()?))
+}
+
+fn is_running(pid: u32) -> bool {
+  // kill -0 also fails for another user's process, which /proc still shows
+  Path::new("/proc").join(pid.to_string()).exists()
+    || Command::new("kill")
+      .args(["-0", &pid.to_string()])
+      .stderr(Stdio::null())
+      .status()
+      .is_ok_and(|status| status.success())
+}
+
+fn elapsed_since(secs: u64) -> Duration {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH + Duration::from_secs(secs))
+    .unwrap_or_default()
+}
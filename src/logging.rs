@@ -0,0 +1,202 @@
+//! The `log` backend: progress and warnings on stderr, and with
+//! `--log-file` every record, lifecycle events included, as timestamped
+//! JSON lines for a post-mortem. Terraform's own log goes beside it.
+
+use crate::style::{self, Stream};
+use anyhow::{Context, Result};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use std::{
+  env, fmt,
+  fs::{File, OpenOptions},
+  io::Write,
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex, OnceLock,
+  },
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+static LOGGER: Logger = Logger {
+  file: Mutex::new(None),
+};
+
+/// Set from `-v` on: debug records reach the console too.
+static CONSOLE_DEBUG: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--quiet`: only warnings and errors reach the console.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+static LOG_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// How much reaches the console: `-v` adds fuoco's debug messages, `-vv`
+/// (or `--debug`) Terraform's output and `-vvv` Terraform's trace log.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+  Normal,
+  Verbose,
+  Terraform,
+  Trace,
+}
+
+impl Verbosity {
+  pub fn new(count: u8, debug: bool) -> Self {
+    let verbosity = match count {
+      0 => Verbosity::Normal,
+      1 => Verbosity::Verbose,
+      2 => Verbosity::Terraform,
+      _ => Verbosity::Trace,
+    };
+    if debug {
+      verbosity.max(Verbosity::Terraform)
+    } else {
+      verbosity
+    }
+  }
+
+  /// Whether Terraform's own output is shown.
+  pub fn terraform(self) -> bool {
+    self >= Verbosity::Terraform
+  }
+}
+
+impl fmt::Display for Verbosity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Verbosity::Normal => "normal",
+      Verbosity::Verbose => "verbose (-v)",
+      Verbosity::Terraform => "terraform output (-vv)",
+      Verbosity::Trace => "terraform trace (-vvv)",
+    })
+  }
+}
+
+struct Logger {
+  file: Mutex<Option<File>>,
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+  /// Seconds since the Unix epoch, to the millisecond.
+  timestamp: f64,
+  level: &'a str,
+  target: &'a str,
+  message: String,
+}
+
+impl Log for Logger {
+  fn enabled(&self, _: &Metadata) -> bool {
+    true
+  }
+
+  fn log(&self, record: &Record) {
+    if record.level() <= Level::Info {
+      crate::progress::clear();
+    }
+    match record.level() {
+      Level::Error => {
+        let line = format!("ERROR: {}", record.args());
+        eprintln!("{}", style::red(line, Stream::Stderr))
+      }
+      Level::Warn => {
+        let line = format!("WARNING: {}", record.args());
+        eprintln!("{}", style::yellow(line, Stream::Stderr))
+      }
+      // stdout is left to what a script would capture: outputs, --print-ip
+      Level::Info if !QUIET.load(Ordering::Relaxed) => {
+        eprintln!("{}", record.args())
+      }
+      Level::Info => {}
+      Level::Debug if CONSOLE_DEBUG.load(Ordering::Relaxed) => {
+        crate::progress::clear();
+        eprintln!("DEBUG: {}", record.args())
+      }
+      // Only for the log file
+      Level::Debug | Level::Trace => {}
+    }
+    let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(file) = file.as_mut() {
+      let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_millis() as f64 / 1000.0);
+      let line = Line {
+        timestamp,
+        level: record.level().as_str(),
+        target: record.target(),
+        message: record.args().to_string(),
+      };
+      if let Ok(line) = serde_json::to_string(&line) {
+        let _ = writeln!(file, "{}", line);
+      }
+    }
+  }
+
+  fn flush(&self) {
+    if let Some(file) =
+      self.file.lock().unwrap_or_else(|e| e.into_inner()).as_mut()
+    {
+      let _ = file.flush();
+    }
+  }
+}
+
+/// Install the logger; until `log_to_file` it only writes to the console.
+pub fn init() {
+  if log::set_logger(&LOGGER).is_ok() {
+    log::set_max_level(LevelFilter::Trace);
+  }
+}
+
+/// Keep progress off the console, spinner included.
+pub fn set_quiet() {
+  QUIET.store(true, Ordering::Relaxed);
+  crate::progress::disable();
+}
+
+/// Show as much as `verbosity` asks for on the console.
+pub fn set_verbosity(verbosity: Verbosity) {
+  CONSOLE_DEBUG.store(verbosity >= Verbosity::Verbose, Ordering::Relaxed);
+  if verbosity.terraform() {
+    // Terraform's own output shows it is alive
+    crate::progress::disable();
+  }
+  if verbosity == Verbosity::Trace {
+    env::set_var("TF_LOG", "TRACE");
+  }
+}
+
+/// Also append every record to `path`, whatever the console shows, and
+/// have Terraform write its log to `<path>.terraform`. atar keeps
+/// Terraform's stdout to itself; it only reaches the console with `-vv`.
+pub fn log_to_file(path: &Path) -> Result<()> {
+  let file = OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(path)
+    .with_context(|| format!("Failed to open log file {:?}", path))?;
+  *LOGGER.file.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+  let _ = LOG_FILE.set(path.to_path_buf());
+  if env::var_os("TF_LOG_PATH").is_none() {
+    let mut terraform_log = path.as_os_str().to_owned();
+    terraform_log.push(".terraform");
+    env::set_var("TF_LOG_PATH", terraform_log);
+    if env::var_os("TF_LOG").is_none() {
+      env::set_var("TF_LOG", "INFO");
+    }
+  }
+  if !QUIET.load(Ordering::Relaxed) {
+    eprintln!("Logging to {}", path.display());
+  }
+  log::debug!(
+    "fuoco {} started (pid {})",
+    env!("CARGO_PKG_VERSION"),
+    std::process::id()
+  );
+  Ok(())
+}
+
+/// The `--log-file`, to point at from error messages.
+pub fn file() -> Option<&'static Path> {
+  LOG_FILE.get().map(PathBuf::as_path)
+}
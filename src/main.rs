@@ -1,48 +1,253 @@
 use anyhow::{Context, Result};
-use atar::{deploy as lib_deploy, undeploy as lib_undeploy};
-use clap::{Parser, Subcommand, ValueEnum};
-use rand::seq::SliceRandom;
-use serde::Serialize;
+use clap::{
+  builder::TypedValueParser, CommandFactory, Parser, Subcommand, ValueEnum,
+};
+use fuoco::{
+  bootlog, cache_dir, capture, clean,
+  deployment::{DeployHandle, Deployment},
+  events::{self, Event},
+  exit::{self, WithStatus},
+  format_elapsed, idle, instance_ip, instance_type_arch, is_stdin,
+  known_instance_types, location,
+  logging::{self, Verbosity},
+  notify, pricing, provider_str, public_ip_output_keys, read_ssh_public_keys,
+  region_env_vars, regions, require_compartment_id, resolve_cheapest_region,
+  resolve_closest_region, resolve_default_instance_type, resolve_gcp_zone,
+  resolve_inbound_rules, resolve_random_region, resolve_region,
+  resolve_ssh_public_key_paths, resolve_template, resolved, ssh_target, status,
+  style, teardown,
+  timings::{Timings, TimingsFormat},
+  undeploy_command, use_hetzner_token, use_workdir,
+  userdata::{self, WriteFile},
+  validate_gcp_location, watchdog, workspace, Arch, DeployParams, EnvVar,
+  HangupAction, InboundRule, ParamsFormat, Provider, TemplateSource,
+  TemplateVar, UndeployParams, DEFAULT_EXTRA_VOLUME_MOUNT, DEFAULT_NAME,
+  DEFAULT_RETRIES, DEFAULT_SSH_PORT, FUOCO_VARIABLES, STDOUT_RESERVED,
+};
 use serde_json;
 use sha2::{Digest, Sha256};
 use signal_hook::{
-  consts::signal::{SIGINT, SIGTERM},
+  consts::signal::{SIGHUP, SIGINT, SIGTERM, SIGUSR1},
   iterator::Signals,
 };
-use std::fmt;
 use std::{
-  collections::HashMap, env, fs, panic, path::PathBuf, process, sync::mpsc,
+  collections::{BTreeMap, HashMap},
+  env, fs,
+  io::{self, IsTerminal, Read, Write},
+  net::{SocketAddr, TcpStream},
+  os::unix::fs::FileTypeExt,
+  panic,
+  path::{Path, PathBuf},
+  process, slice,
+  sync::{atomic::Ordering, mpsc, Arc},
   thread,
+  time::{Duration, Instant},
 };
+use style::Stream;
 
 /// fuoco: Ephemeral VM deployer that applies a Terraform template,
 /// and runs a startup script via cloud-init, then it destroys on exit.
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(
+  author,
+  version,
+  about,
+  long_about = None,
+  after_help = "Exit status:\n  \
+    0  success\n  \
+    1  any other error\n  \
+    2  invalid arguments or configuration; nothing was deployed or destroyed\n  \
+    3  a destroy was abandoned with Ctrl+C; resources were leaked\n  \
+    4  --deploy-timeout expired; what it created was destroyed\n  \
+    5  terraform apply failed; what it created was destroyed\n  \
+    6  terraform apply failed and so did the cleanup; resources may be left\n  \
+    7  the destroy failed; resources may be left\n  \
+    8  --timeout expired; the resources were destroyed"
+)]
 struct Cli {
+  /// Base directory for Terraform workspaces (default: the system temp dir).
+  #[arg(long, global = true, env = "FUOCO_WORKDIR", value_name = "DIR")]
+  workdir: Option<PathBuf>,
+  /// Append a timestamped JSON log of everything fuoco does to this file, whatever the console shows.
+  #[arg(long, global = true, env = "FUOCO_LOG_FILE", value_name = "FILE")]
+  log_file: Option<PathBuf>,
+  /// Print nothing but the results on stdout and warnings or errors on stderr.
+  #[arg(long, short = 'q', global = true)]
+  quiet: bool,
+  /// Never color the output (also NO_COLOR); by default it is colored on a terminal.
+  #[arg(long, global = true)]
+  no_color: bool,
   #[command(subcommand)]
   command: Commands,
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 enum Commands {
   /// Deploy an ephemeral VM and optionally run a startup script.
   Deploy {
-    /// Enable debug mode (show Terraform stdout/stderr).
+    /// Show more: -v fuoco's debug messages, -vv also Terraform's output, -vvv also Terraform's trace log (TF_LOG=TRACE).
+    #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Same as -vv: show Terraform's output.
     #[arg(long, short = 'd')]
     debug: bool,
-    /// Instance type (default: t4g.nano for AWS, e2-micro for GCP, cx11 for Hetzner).
+    /// Emit newline-delimited JSON lifecycle events on stderr.
+    #[arg(long)]
+    events: bool,
+    /// What stdout carries: the outputs as text, or the --events lines instead.
+    #[arg(
+      long,
+      value_enum,
+      default_value = "text",
+      conflicts_with_all = ["print_ip", "print_vars"]
+    )]
+    format: Format,
+    /// Deploy without asking for confirmation (implied when stdin is not a terminal).
+    #[arg(long, short = 'y')]
+    yes: bool,
+    /// Print only the public IP on stdout once deployed; all other output goes to stderr.
+    #[arg(long)]
+    print_ip: bool,
+    /// Print the variables fuoco would pass to Terraform as JSON, then exit without deploying.
+    #[arg(long)]
+    print_vars: bool,
+    /// Apply in the existing Terraform workspace instead of starting from scratch, so only what changed is replaced.
+    #[arg(long)]
+    reuse: bool,
+    /// Delete the Terraform workspace and its initialized providers before deploying, even if the template is unchanged.
+    #[arg(long, conflicts_with = "reuse")]
+    fresh_workspace: bool,
+    /// Deploy without first checking the provider accepts the credentials, for setups the check cannot follow.
+    #[arg(long)]
+    skip_credential_check: bool,
+    /// Wait this long for another fuoco using the same workspace instead of failing at once (e.g. 5m).
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    wait_lock: Option<Duration>,
+    /// Retries for transient Terraform failures (throttling, timeouts, 5xx), with exponential backoff.
+    #[arg(
+      long,
+      visible_alias = "deploy-retries",
+      value_name = "N",
+      default_value_t = DEFAULT_RETRIES
+    )]
+    retries: u32,
+    /// Give up on terraform apply after this long (e.g. 10m), destroy whatever it created and exit with status 4.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    deploy_timeout: Option<Duration>,
+    /// How long terraform destroy may run before it is stopped and fuoco fails (e.g. 90s, 30m, 1h).
+    #[arg(long, value_name = "DURATION", default_value = "30m", value_parser = parse_duration)]
+    destroy_timeout: Duration,
+    /// Hard ceiling on the whole run, deploy, wait and destroy together (e.g. 20m): once it expires fuoco destroys and exits with status 8. The destroy still gets at least 5 minutes.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, conflicts_with = "keep")]
+    timeout: Option<Duration>,
+    /// How to print how long each phase took, at the end.
+    #[arg(long, value_enum, value_name = "FORMAT", default_value = "text")]
+    timings: TimingsFormat,
+    /// How to print the params before deploying: a table, or JSON with the resolved region, instance type and rules, for tooling.
+    #[arg(long, value_enum, value_name = "FORMAT", default_value = "text")]
+    params_format: ParamsFormat,
+    /// Leave the resources running and exit once deployed; prints the undeploy command for later.
+    #[arg(long)]
+    keep: bool,
+    /// Destroy once the instance has been idle this long (e.g. 15m): nobody logged in, and next to no CPU load or network traffic, checked over SSH.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, conflicts_with = "keep")]
+    idle_timeout: Option<Duration>,
+    /// How often --idle-timeout checks the instance.
+    #[arg(long, value_name = "DURATION", default_value = "1m", value_parser = parse_duration)]
+    idle_check_interval: Duration,
+    /// Once the instance answers over SSH, stream its cloud-init output (/var/log/cloud-init-output.log) until fuoco destroys it.
+    #[arg(long, conflicts_with = "keep")]
+    watch_logs: bool,
+    /// What to do when the terminal hangs up (e.g. a dropped SSH session).
+    #[arg(long, value_enum, default_value = "destroy")]
+    on_hangup: HangupAction,
+    /// Deployment name, used for the cloud resources and in events.
+    #[arg(long, short = 'n', default_value = DEFAULT_NAME)]
+    name: String,
+    /// Instance type (default depends on --arch: t3.micro/t4g.nano for AWS, e2-micro/t2a-standard-1 for GCP, cx11/cax11 for Hetzner, VM.Standard.E2.1.Micro/VM.Standard.A1.Flex for Oracle, g6-nanode-1 for Linode, DEV1-S/COPARM1-2C-8G for Scaleway).
     #[arg(long, short = 'i')]
     instance_type: Option<String>,
-    /// Cloud provider to deploy to (aws, gcp, hetzner).
-    #[arg(long, value_enum, short = 'c')]
-    provider: Provider,
-    /// Cloud region (AWS region, GCP zone, or Hetzner location).
+    /// Fail instead of warning when --instance-type is not a known type.
+    #[arg(long)]
+    strict_instance_type: bool,
+    /// CPU architecture (default: inferred from --instance-type, else arm64 for Oracle and x86_64 elsewhere).
+    #[arg(long, value_enum, short = 'a')]
+    arch: Option<Arch>,
+    /// Cloud provider to deploy to (aws, gcp, hetzner, oracle, linode, scaleway); repeat it, or pass `all`, to deploy to several at once.
+    #[arg(long, short = 'c', required = true, value_parser = provider_selection_parser())]
+    provider: Vec<Vec<Provider>>,
+    /// Directory with a custom main.tf to use instead of the built-in template.
+    #[arg(long, value_name = "DIR")]
+    template: Option<PathBuf>,
+    /// Ignore ~/.config/fuoco/templates and use the built-in template.
+    #[arg(long, conflicts_with = "template")]
+    no_template_override: bool,
+    /// Cloud region (AWS region, GCP region or zone, Hetzner location, OCI region, Linode region, or Scaleway zone). Defaults to the provider's environment variable (AWS_REGION, CLOUDSDK_COMPUTE_ZONE, OCI_CLI_REGION, ...).
     #[arg(long, short = 'r')]
     region: Option<String>,
-    /// Path to a Bash script to execute on VM startup.
+    /// How to pick a region when neither --region nor the provider's environment variable gives one.
+    #[arg(long, value_enum, default_value = "random")]
+    region_strategy: RegionStrategy,
+    /// Only let --region-strategy pick a region matching this prefix (eu-) or glob ('us-*'); repeat it or separate with commas to allow several.
+    #[arg(
+      long,
+      value_name = "PATTERN",
+      value_delimiter = ',',
+      conflicts_with = "region"
+    )]
+    region_filter: Vec<String>,
+    /// Never let --region-strategy pick this region (e.g. one not enabled on the account); repeat it or separate with commas. Also read from FUOCO_REGION_EXCLUDE.
+    #[arg(
+      long,
+      value_name = "REGION",
+      value_delimiter = ',',
+      env = "FUOCO_REGION_EXCLUDE",
+      conflicts_with = "region"
+    )]
+    region_exclude: Vec<String>,
+    /// Seed for the random region pick, to replay one: the same seed, provider and filters pick the same region. The seed used is shown with the parameters either way. Also read from FUOCO_REGION_SEED.
+    #[arg(long, value_name = "SEED", env = "FUOCO_REGION_SEED")]
+    region_seed: Option<u64>,
+    /// Path to a Bash script to execute on VM startup ("-" reads stdin).
+    /// Repeat to run several scripts in order, stopping at the first failure.
     #[arg(long, short = 's')]
-    script_path: Option<PathBuf>,
+    script_path: Vec<PathBuf>,
+    /// cloud-config YAML to pass as user data, combined with any script.
+    #[arg(long, value_name = "FILE")]
+    cloud_config: Option<PathBuf>,
+    /// Complete user data handed to cloud-init as is, instead of the boot script and cloud-config fuoco builds.
+    #[arg(
+      long,
+      value_name = "FILE",
+      conflicts_with_all = [
+        "script_path",
+        "script_url",
+        "cloud_config",
+        "env",
+        "env_file",
+        "files",
+        "hostname",
+        "user",
+        "ssh_port",
+      ]
+    )]
+    cloud_init: Option<PathBuf>,
+    /// Environment variable exported to the startup script (repeatable).
+    #[arg(long = "env", value_parser, value_name = "KEY=VALUE")]
+    env: Vec<EnvVar>,
+    /// File of KEY=VALUE lines exported to the startup script (--env wins).
+    #[arg(long, value_name = "FILE")]
+    env_file: Option<PathBuf>,
+    /// Local file to write onto the VM before the startup script runs (repeatable).
+    #[arg(long = "file", value_parser, value_name = "LOCAL:REMOTE[:MODE]")]
+    files: Vec<WriteFile>,
+    /// Download the startup script from an HTTP(S) URL instead.
+    #[arg(long, value_name = "URL", conflicts_with = "script_path")]
+    script_url: Option<String>,
+    /// Expected SHA-256 of the downloaded script (hex).
+    #[arg(long, value_name = "HEX", requires = "script_url")]
+    script_sha256: Option<String>,
     /// Inbound rules in the format protocol:port (e.g., tcp:22).
     #[arg(
       long = "inbound-rule",
@@ -51,439 +256,2057 @@ enum Commands {
       short = 'p'
     )]
     inbound_rules: Option<Vec<InboundRule>>,
-    /// Path to the public key that must be uploaded to the machine
+    /// File of inbound rules, one PROTO:PORT per line (# starts a comment), added to any --inbound-rule.
+    #[arg(long, value_name = "FILE")]
+    inbound_rules_file: Option<PathBuf>,
+    /// Path to a public key that must be uploaded to the machine (repeatable, one per person).
     #[arg(long = "ssh-public-key-path", short = 'k')]
-    ssh_public_key_path: Option<String>,
+    ssh_public_key_path: Vec<String>,
+    /// Existing key pair to authorize, by name, instead of uploading a public key (AWS key pair or Hetzner SSH key).
+    #[arg(long, value_name = "NAME", conflicts_with = "ssh_public_key_path")]
+    key_name: Option<String>,
+    /// Port sshd listens on; opened instead of tcp:22, and added to any --inbound-rule.
+    #[arg(long, value_name = "PORT", value_parser = clap::value_parser!(u16).range(1..))]
+    ssh_port: Option<u16>,
+    /// OCI compartment OCID (required for Oracle).
+    #[arg(long)]
+    compartment_id: Option<String>,
+    /// AWS named profile for the credential check and Terraform, set as AWS_PROFILE.
+    #[arg(long, value_name = "PROFILE")]
+    aws_profile: Option<String>,
+    /// Existing VPC (GCP: network, OCI: VCN) to deploy into instead of creating or using the default one.
+    #[arg(
+      long,
+      visible_alias = "network",
+      value_name = "ID",
+      requires = "subnet_id"
+    )]
+    vpc_id: Option<String>,
+    /// Existing subnet (GCP: subnetwork) in --vpc-id to attach the instance to.
+    #[arg(
+      long,
+      visible_alias = "subnetwork",
+      value_name = "ID",
+      requires = "vpc_id"
+    )]
+    subnet_id: Option<String>,
+    /// AWS availability zone to launch in (e.g. us-east-1a); without --region it also picks the region.
+    #[arg(long, value_name = "ZONE", conflicts_with = "subnet_id")]
+    availability_zone: Option<String>,
+    /// AWS: if the zone has no capacity for the instance type, destroy the attempt and apply again in the region's next availability zone, until one has room.
+    #[arg(long, conflicts_with = "subnet_id")]
+    az_fallback: bool,
+    /// Give the instance no public IP; it is then only reachable from its network (e.g. through a bastion).
+    #[arg(long)]
+    no_public_ip: bool,
+    /// Reserve a static public IP (AWS Elastic IP, GCP static address, Hetzner primary IP) for the instance; it is released when the deployment is destroyed.
+    #[arg(long, conflicts_with = "no_public_ip")]
+    static_ip: bool,
+    /// Request a spot (AWS), Spot VM (GCP) or preemptible (Oracle) instance.
+    /// WARNING: the provider can reclaim it at any time, so the VM may vanish
+    /// mid-session; fuoco then tears down whatever is left and exits.
+    #[arg(long)]
+    spot: bool,
+    /// Maximum hourly price in USD for an AWS spot instance (default: on-demand price).
+    #[arg(long, value_name = "USD", requires = "spot")]
+    spot_max_price: Option<String>,
+    /// Root disk size in GiB (default: the template's image default).
+    #[arg(long, value_name = "GIB")]
+    disk_size: Option<u32>,
+    /// Attach an extra data volume of this size in GiB, destroyed with the VM.
+    #[arg(long, value_name = "GIB")]
+    extra_volume: Option<u32>,
+    /// Where the extra volume is mounted before the startup script runs.
+    #[arg(long, default_value = DEFAULT_EXTRA_VOLUME_MOUNT, requires = "extra_volume")]
+    extra_volume_mount: String,
+    /// Base image: AMI ID (AWS), [project/]image family (GCP), image name (Hetzner), image OCID (Oracle), image ID (Linode) or image label (Scaleway).
+    #[arg(long)]
+    image: Option<String>,
+    /// Shell command run before Terraform applies; failing aborts the deploy.
+    #[arg(long, value_name = "CMD")]
+    pre_deploy_hook: Option<String>,
+    /// Shell command run once deployed, with FUOCO_OUTPUT_* set; failures only warn.
+    #[arg(long, value_name = "CMD")]
+    post_deploy_hook: Option<String>,
+    /// Shell command run right before the resources are destroyed, with FUOCO_OUTPUT_* set.
+    #[arg(long, value_name = "CMD")]
+    pre_destroy_hook: Option<String>,
+    /// Script run on the VM over SSH before it is destroyed (e.g. to upload results).
+    #[arg(long, value_name = "FILE")]
+    teardown_script: Option<PathBuf>,
+    /// Seconds to let --teardown-script run before destroying anyway.
+    #[arg(long, value_name = "SECS", default_value_t = 60)]
+    teardown_timeout: u64,
+    /// Webhook (e.g. Slack) POSTed when the deployment is up and when it is destroyed.
+    #[arg(
+      long,
+      env = "FUOCO_NOTIFY_URL",
+      hide_env_values = true,
+      value_name = "URL"
+    )]
+    notify_url: Option<String>,
+    /// Payload shape for --notify-url.
+    #[arg(long, value_enum, default_value = "slack")]
+    notify_format: notify::NotifyFormat,
+    /// URL to POST the outputs, deployment name and provider to as JSON once deployed; a failure only warns.
+    #[arg(long, value_name = "URL")]
+    callback_url: Option<String>,
+    /// Terraform output sent as "[redacted]" to --callback-url (repeatable).
+    #[arg(
+      long = "sensitive-output",
+      value_name = "NAME",
+      requires = "callback_url"
+    )]
+    sensitive_outputs: Vec<String>,
+    /// Tag or label to put on the instance (repeatable).
+    #[arg(long = "tag", value_parser, value_name = "KEY=VALUE")]
+    tags: Vec<TemplateVar>,
+    /// Instance metadata the VM can read at runtime (repeatable): GCP and OCI metadata, Scaleway user data keys, Hetzner labels, or AWS tags exposed to the metadata service.
+    #[arg(long = "metadata", value_parser, value_name = "KEY=VALUE")]
+    metadata: Vec<TemplateVar>,
+    /// Also tag the instance with the current git repo, branch and commit.
+    #[arg(long)]
+    labels_from_git: bool,
+    /// Login user to create and authorize the SSH key for (default: the image's user).
+    #[arg(long)]
+    user: Option<String>,
+    /// Hostname cloud-init sets on boot (default: the provider's).
+    #[arg(long, value_parser = parse_hostname)]
+    hostname: Option<String>,
+    /// Extra Terraform variable passed verbatim (repeatable).
+    #[arg(long = "var", value_parser, value_name = "KEY=VALUE")]
+    vars: Vec<TemplateVar>,
+    /// JSON object of extra Terraform variables (--var wins on conflicts).
+    #[arg(long, value_name = "FILE")]
+    var_file: Option<PathBuf>,
+    /// Allow --var/--var-file to replace variables fuoco sets itself.
+    #[arg(long)]
+    var_override: bool,
   },
   /// Destroy an existing ephemeral VM deployment.
   Undeploy {
-    /// Enable debug mode (show Terraform stdout/stderr).
+    /// Show more: -v fuoco's debug messages, -vv also Terraform's output, -vvv also Terraform's trace log (TF_LOG=TRACE).
+    #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Same as -vv: show Terraform's output.
     #[arg(long, short = 'd')]
     debug: bool,
-    /// Instance type (default: t4g.nano for AWS, e2-micro for GCP, cx11 for Hetzner).
+    /// Destroy without asking for confirmation (required when stdin is not a terminal).
+    #[arg(long, short = 'y')]
+    yes: bool,
+    /// Destroy what can be destroyed even if the Terraform state is broken, then delete the workspace; may leave resources behind.
+    #[arg(long)]
+    force: bool,
+    /// Instance type (default depends on --arch: t3.micro/t4g.nano for AWS, e2-micro/t2a-standard-1 for GCP, cx11/cax11 for Hetzner, VM.Standard.E2.1.Micro/VM.Standard.A1.Flex for Oracle, g6-nanode-1 for Linode, DEV1-S/COPARM1-2C-8G for Scaleway).
     #[arg(long, short = 'i')]
     instance_type: Option<String>,
-    /// Cloud provider to undeploy (aws, gcp, hetzner).
+    /// CPU architecture (default: inferred from --instance-type, else arm64 for Oracle and x86_64 elsewhere).
+    #[arg(long, value_enum, short = 'a')]
+    arch: Option<Arch>,
+    /// Cloud provider to undeploy (aws, gcp, hetzner, oracle, linode, scaleway).
     #[arg(long, value_enum, short = 'c')]
     provider: Provider,
-    /// Cloud region (AWS region, GCP zone, or Hetzner location).
+    /// Name the deployment was given; with the provider, region and template it picks the workspace.
+    #[arg(long, short = 'n', default_value = DEFAULT_NAME)]
+    name: String,
+    /// Directory with a custom main.tf to use instead of the built-in template.
+    #[arg(long, value_name = "DIR")]
+    template: Option<PathBuf>,
+    /// Ignore ~/.config/fuoco/templates and use the built-in template.
+    #[arg(long, conflicts_with = "template")]
+    no_template_override: bool,
+    /// Cloud region (AWS region, GCP zone, Hetzner location, OCI region, Linode region, or Scaleway zone). Defaults to the provider's environment variable, as for deploy.
     #[arg(long, short = 'r')]
-    region: String,
+    region: Option<String>,
+    /// OCI compartment OCID (required for Oracle).
+    #[arg(long)]
+    compartment_id: Option<String>,
+    /// AWS named profile for the credential check and Terraform, set as AWS_PROFILE.
+    #[arg(long, value_name = "PROFILE")]
+    aws_profile: Option<String>,
+    /// Script run on the VM over SSH before it is destroyed; the VM is found through the Terraform state.
+    #[arg(long, value_name = "FILE")]
+    teardown_script: Option<PathBuf>,
+    /// Seconds to let --teardown-script run before destroying anyway.
+    #[arg(long, value_name = "SECS", default_value_t = 60)]
+    teardown_timeout: u64,
+    /// Public key given at deploy; --teardown-script logs in with its private half.
+    #[arg(long, short = 'k')]
+    ssh_public_key_path: Option<String>,
+    /// --ssh-port given at deploy, for --teardown-script to log in on.
+    #[arg(long, value_name = "PORT", value_parser = clap::value_parser!(u16).range(1..))]
+    ssh_port: Option<u16>,
+    /// Retries for transient Terraform failures (throttling, timeouts, 5xx), with exponential backoff.
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_RETRIES)]
+    retries: u32,
+    /// How long terraform destroy may run before it is stopped and fuoco fails (e.g. 90s, 30m, 1h).
+    #[arg(long, value_name = "DURATION", default_value = "30m", value_parser = parse_duration)]
+    destroy_timeout: Duration,
+    /// How to print how long each phase took, at the end.
+    #[arg(long, value_enum, value_name = "FORMAT", default_value = "text")]
+    timings: TimingsFormat,
+    /// How to print the params before destroying: a table, or JSON with the resolved values, for tooling.
+    #[arg(long, value_enum, value_name = "FORMAT", default_value = "text")]
+    params_format: ParamsFormat,
+    /// Wait this long for another fuoco using the same workspace instead of failing at once (e.g. 5m).
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    wait_lock: Option<Duration>,
+    /// Extra Terraform variable passed verbatim (repeatable).
+    #[arg(long = "var", value_parser, value_name = "KEY=VALUE")]
+    vars: Vec<TemplateVar>,
+    /// JSON object of extra Terraform variables (--var wins on conflicts).
+    #[arg(long, value_name = "FILE")]
+    var_file: Option<PathBuf>,
+    /// Allow --var/--var-file to replace variables fuoco sets itself.
+    #[arg(long)]
+    var_override: bool,
+  },
+  /// List the template each provider resolves to.
+  Templates {
+    /// Ignore ~/.config/fuoco/templates and show the built-in templates.
+    #[arg(long)]
+    no_template_override: bool,
+  },
+  /// List the regions fuoco picks from for each provider, and whether they are built in or fetched live.
+  Regions {
+    /// Only this provider.
+    #[arg(long, value_enum, short = 'c')]
+    provider: Option<Provider>,
+    /// Fetch the regions from the provider's API first (AWS, GCP, Hetzner), with its usual credentials, and cache them for a week.
+    #[arg(long)]
+    refresh: bool,
+  },
+  /// List the instance types fuoco knows for each provider, with their architecture and estimated price.
+  InstanceTypes {
+    /// Only this provider.
+    #[arg(long, value_enum, short = 'c')]
+    provider: Option<Provider>,
+    /// Only instance types of this architecture.
+    #[arg(long, value_enum)]
+    arch: Option<Arch>,
+  },
+  /// Show a deployment's instance: its state in Terraform and with the provider, its IP and uptime.
+  Status {
+    /// Name the deployment was given.
+    #[arg(default_value = DEFAULT_NAME)]
+    name: String,
+    /// Provider of the deployment, when the name is deployed to several.
+    #[arg(long, value_enum, short = 'c')]
+    provider: Option<Provider>,
+    /// Region of the deployment, when the name is deployed to several.
+    #[arg(long, short = 'r')]
+    region: Option<String>,
+    /// --ssh-port given at deploy, to check SSH on.
+    #[arg(long, value_name = "PORT", default_value_t = DEFAULT_SSH_PORT)]
+    ssh_port: u16,
+  },
+  /// List leftover Terraform workspaces, staged templates and failed-destroy records; with --yes, delete the ones no deployment needs.
+  Clean {
+    /// Delete the entries marked for removal instead of only listing them.
+    #[arg(long, short = 'y')]
+    yes: bool,
+    /// Only remove entries not modified for this long (e.g. 12h, 7d).
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    older_than: Option<Duration>,
+  },
+  /// Print a shell completion script, e.g. `fuoco completions bash > /etc/bash_completion.d/fuoco`.
+  Completions {
+    /// Shell to generate completions for.
+    #[arg(value_enum)]
+    shell: clap_complete::Shell,
   },
 }
 
-#[derive(Clone)]
-struct RunDeployParams {
-  debug: bool,
-  instance_type: Option<String>,
-  provider: Provider,
-  region: Option<String>,
-  script_path: Option<PathBuf>,
-  template_path: PathBuf,
-  inbound_rules: Option<Vec<InboundRule>>,
-  ssh_public_key_path: Option<String>,
-}
-
-struct RunUndeployParams {
-  debug: bool,
-  instance_type: Option<String>,
-  provider: Provider,
-  region: String,
-  template_path: PathBuf,
-}
-
-impl fmt::Debug for RunDeployParams {
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    // Manually printing fields as key-value pairs
-    write!(f, "Deploy params \n")?;
-    write!(f, "  debug: {},\n", self.debug)?;
-    let default_instance_type = resolve_default_instance_type(&self.provider);
-    write!(
-      f,
-      "  instance_type: {},\n",
-      self
-        .instance_type
-        .as_ref()
-        .map_or(default_instance_type, |s| format!("[{}]", s))
-    )?;
-    write!(f, "  provider: {:?},\n", self.provider)?;
-    write!(
-      f,
-      "  region: {},\n",
-      self.region.as_ref().map_or("[Random]", |s| s)
-    )?;
-    let defulat_inbound_rules = resolve_default_inbound_rule();
-    write!(f, "  script_path: {:?},\n", self.script_path)?;
-    write!(f, "  template_path: {:?}\n", self.template_path)?;
-    write!(
-      f,
-      "  inbound_rules: {:?}\n",
-      self
-        .inbound_rules
-        .as_ref()
-        .map_or(defulat_inbound_rules, |s| s.clone())
-    )?;
-    write!(
-      f,
-      "  ssh_public_key_path: {:?}\n",
-      self.ssh_public_key_path.as_ref().map_or("[Default]", |s| s)
-    )?;
-    write!(f, "")
-  }
-}
-
-impl RunDeployParams {
-  fn to_atar_map(&self) -> HashMap<String, String> {
-    let mut map = HashMap::new();
-    // Convert each field to a String and insert it into the map
-    let default_instance_type = resolve_default_instance_type(&self.provider);
-    map.insert(
-      "instance_type".to_string(),
-      self
-        .instance_type
-        .as_ref()
-        .map_or(default_instance_type, |s| s.clone()),
-    );
-    let random_region = resolve_random_region(&self.provider);
-    map.insert(
-      "region".to_string(),
-      self.region.as_ref().map_or(random_region, |s| s.clone()),
-    );
-    let default_script_path = String::new();
-    map.insert(
-      "script_path".to_string(),
-      self
-        .script_path
-        .as_ref()
-        .map_or(default_script_path, |s| s.to_string_lossy().to_string()),
-    );
-    let defulat_inbound_rules = resolve_default_inbound_rule();
-    let final_inbound_rules = &self
-      .inbound_rules
-      .as_ref()
-      .map_or(defulat_inbound_rules, |s| s.clone());
-    let inbound_rules_json =
-      serde_json::to_string(final_inbound_rules).unwrap();
-    map.insert("inbound_rules".to_string(), inbound_rules_json);
-    let default_ssh_public_key_path = "none".to_string();
-    map.insert(
-      "ssh_public_key_path".to_string(),
-      self
-        .ssh_public_key_path
-        .as_ref()
-        .map_or(default_ssh_public_key_path, |s| s.clone()),
-    );
-    map
-  }
-}
-
-impl fmt::Debug for RunUndeployParams {
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    // Manually printing fields as key-value pairs
-    write!(f, "Undeploy params \n")?;
-    write!(f, "  debug: {},\n", self.debug)?;
-    let default_instance_type = resolve_default_instance_type(&self.provider);
-    write!(
-      f,
-      "  instance_type: {},\n",
-      self
-        .instance_type
-        .as_ref()
-        .map_or(default_instance_type, |s| format!("[{}]", s))
-    )?;
-    write!(f, "  provider: {:?},\n", self.provider)?;
-    write!(f, "  region: {},\n", self.region)?;
-    write!(f, "  template_path: {:?}\n", self.template_path)?;
-    write!(f, "")
-  }
-}
-
-impl RunUndeployParams {
-  fn to_atar_map(&self) -> HashMap<String, String> {
-    let mut map = HashMap::new();
-    // Convert each field to a String and insert it into the map
-    let default_instance_type = resolve_default_instance_type(&self.provider);
-    map.insert(
-      "instance_type".to_string(),
-      self
-        .instance_type
-        .as_ref()
-        .map_or(default_instance_type, |s| s.clone()),
-    );
-    map.insert("region".to_string(), self.region.clone());
-    map
+/// Give up on a --script-url download after this long.
+const SCRIPT_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest --script-url body fuoco accepts; user data limits are far lower.
+const SCRIPT_MAX_BYTES: u64 = 1024 * 1024;
+
+/// Merge --env-file and --env (the flag wins). The file holds one
+/// `KEY=VALUE` per line, dotenv style: blank lines and `#` comments are
+/// skipped, `export ` is allowed and one layer of matching quotes is removed.
+fn resolve_env(
+  env: Vec<EnvVar>,
+  env_file: Option<PathBuf>,
+) -> Result<Vec<EnvVar>> {
+  let mut merged: Vec<EnvVar> = Vec::new();
+  if let Some(path) = env_file {
+    let content = fs::read_to_string(&path)
+      .with_context(|| format!("Failed to read env file {:?}", path))?;
+    for (number, line) in content.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let line = line.strip_prefix("export ").unwrap_or(line);
+      let mut var: EnvVar = line.parse().map_err(|err| {
+        anyhow::anyhow!("{:?} line {}: {}", path, number + 1, err)
+      })?;
+      for quote in ['"', '\''] {
+        if let Some(unquoted) = var
+          .value
+          .strip_prefix(quote)
+          .and_then(|value| value.strip_suffix(quote))
+        {
+          var.value = unquoted.to_string();
+          break;
+        }
+      }
+      merged.retain(|existing| existing.key != var.key);
+      merged.push(var);
+    }
+  }
+  for var in env {
+    merged.retain(|existing| existing.key != var.key);
+    merged.push(var);
   }
+  Ok(merged)
 }
 
-/// Supported cloud providers.
-#[derive(ValueEnum, Clone, Debug)]
-enum Provider {
-  AWS,
-  GCP,
-  Hetzner,
+/// Add the rules in --inbound-rules-file to the --inbound-rule flags, the
+/// file's first, dropping duplicates. `None`, for the default SSH rule,
+/// when neither gives one.
+fn resolve_inbound_rules_file(
+  rules: Option<Vec<InboundRule>>,
+  file: Option<PathBuf>,
+) -> Result<Option<Vec<InboundRule>>> {
+  let Some(path) = file else {
+    return Ok(rules);
+  };
+  let content = fs::read_to_string(&path)
+    .with_context(|| format!("Failed to read inbound rules file {:?}", path))?;
+  let mut merged: Vec<InboundRule> = Vec::new();
+  for (number, line) in content.lines().enumerate() {
+    let line = line.split('#').next().unwrap_or("").trim();
+    if line.is_empty() {
+      continue;
+    }
+    let rule: InboundRule = line.parse().map_err(|err| {
+      anyhow::anyhow!("{:?} line {}: {}", path, number + 1, err)
+    })?;
+    merged.push(rule);
+  }
+  merged.extend(rules.unwrap_or_default());
+  let mut rules: Vec<InboundRule> = Vec::new();
+  for rule in merged {
+    if !rules.contains(&rule) {
+      rules.push(rule);
+    }
+  }
+  Ok(Some(rules).filter(|rules| !rules.is_empty()))
+}
+
+/// Merge --var-file and --var (the flag wins) and refuse to shadow fuoco's
+/// own variables unless explicitly allowed.
+fn resolve_extra_vars(
+  vars: Vec<TemplateVar>,
+  var_file: Option<PathBuf>,
+  allow_override: bool,
+) -> Result<Vec<TemplateVar>> {
+  let mut merged: Vec<TemplateVar> = Vec::new();
+  if let Some(path) = var_file {
+    let content = fs::read_to_string(&path)
+      .with_context(|| format!("Failed to read var file {:?}", path))?;
+    let object: serde_json::Map<String, serde_json::Value> =
+      serde_json::from_str(&content).with_context(|| {
+        format!("Var file {:?} must contain a JSON object", path)
+      })?;
+    for (key, value) in object {
+      // Strings go through as-is; anything else as its JSON text, which is
+      // what Terraform expects for numbers, bools, lists and maps.
+      let value = match value {
+        serde_json::Value::String(s) => s,
+        other => other.to_string(),
+      };
+      merged.push(TemplateVar { key, value });
+    }
+  }
+  for var in vars {
+    merged.retain(|existing| existing.key != var.key);
+    merged.push(var);
+  }
+  if !allow_override {
+    let shadowed: Vec<&str> = merged
+      .iter()
+      .map(|var| var.key.as_str())
+      .filter(|key| FUOCO_VARIABLES.contains(key))
+      .collect();
+    if !shadowed.is_empty() {
+      anyhow::bail!(
+        "Refusing to override fuoco-managed variables: {} \
+         (pass --var-override to allow it)",
+        shadowed.join(", ")
+      );
+    }
+  }
+  Ok(merged)
 }
 
-#[derive(Clone, Debug, Serialize)]
-struct InboundRule {
-  protocol: String,
-  port_number: u16,
+/// What `deploy` prints on stdout.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Format {
+  /// The outputs, as `name: value` lines.
+  Text,
+  /// One JSON object per lifecycle event, named by its `type` field.
+  JsonEvents,
 }
 
-impl std::str::FromStr for InboundRule {
-  type Err = String;
+/// How a region is chosen when the user does not pass one.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum RegionStrategy {
+  /// Uniformly at random from the provider's region list.
+  Random,
+  /// Lowest TCP round-trip time to a per-region endpoint.
+  #[value(alias = "latency")]
+  Closest,
+  /// Lowest hourly price for the instance type.
+  Cheapest,
+}
 
-  fn from_str(s: &str) -> Result<Self, Self::Err> {
-    let parts: Vec<&str> = s.split(':').collect();
-    if parts.len() != 2 {
-      return Err("Inbound rule must be in format protocol:port".into());
+/// Set the region `strategy` picks for `params`, and what was measured or
+/// priced to pick it.
+fn pick_region(
+  params: &mut DeployParams,
+  strategy: RegionStrategy,
+) -> Result<()> {
+  let (provider, filter, exclude) = (
+    &params.provider,
+    &params.region_filter,
+    &params.region_exclude,
+  );
+  let mut seed = None;
+  let (region, rtt, price) = match strategy {
+    RegionStrategy::Random => {
+      let drawn = params.region_seed.unwrap_or_else(rand::random);
+      seed = Some(drawn);
+      (
+        resolve_random_region(provider, filter, exclude, drawn)?,
+        None,
+        None,
+      )
     }
-    let protocol = parts[0].to_string();
-    let port_number = parts[1]
-      .parse::<u16>()
-      .map_err(|_| "Invalid port number".to_string())?;
-    Ok(InboundRule {
-      protocol,
-      port_number,
-    })
-  }
+    RegionStrategy::Closest => {
+      let (region, rtt) = resolve_closest_region(provider, filter, exclude)?;
+      (region, Some(rtt), None)
+    }
+    RegionStrategy::Cheapest => {
+      let instance_type = params.resolved_instance_type();
+      let (region, price) =
+        resolve_cheapest_region(provider, &instance_type, filter, exclude)?;
+      (region, None, price)
+    }
+  };
+  params.region = Some(region);
+  params.region_rtt = rtt;
+  params.region_seed = seed;
+  params.hourly_price = price;
+  Ok(())
 }
 
 fn main() {
-  run().unwrap_or_else(|err| {
-    eprintln!("Error: {}", err);
-    process::exit(1);
+  logging::init();
+  let result = run();
+  capture::cleanup();
+  result.unwrap_or_else(|err| {
+    log::error!("{}", err);
+    if let Some(log_file) = logging::file() {
+      eprintln!("See {} for details", log_file.display());
+    }
+    process::exit(exit::code(&err));
   });
 }
 
 fn run() -> Result<()> {
   let cli = Cli::parse();
+  style::init(cli.no_color);
+  if cli.quiet {
+    logging::set_quiet();
+  }
+  if let Some(log_file) = &cli.log_file {
+    logging::log_to_file(log_file)?;
+  }
+  if let Some(workdir) = &cli.workdir {
+    use_workdir(workdir)?;
+  }
+  use_plugin_cache();
+  use_hetzner_token();
+  if let Err(err) = capture::install() {
+    log::debug!("Not keeping Terraform's stderr for errors: {:#}", err);
+  }
 
   match cli.command {
     Commands::Deploy {
+      verbose,
       debug,
+      events,
+      format,
+      yes,
+      print_ip,
+      print_vars,
+      reuse,
+      fresh_workspace,
+      skip_credential_check,
+      wait_lock,
+      retries,
+      deploy_timeout,
+      destroy_timeout,
+      timeout,
+      timings,
+      params_format,
+      keep,
+      idle_timeout,
+      idle_check_interval,
+      watch_logs,
+      on_hangup,
+      name,
       instance_type,
+      strict_instance_type,
+      arch,
       provider,
+      template,
+      no_template_override,
       region,
+      region_strategy,
+      region_filter,
+      region_exclude,
+      region_seed,
       script_path,
+      cloud_config,
+      cloud_init,
+      files,
+      env,
+      env_file,
+      script_url,
+      script_sha256,
       inbound_rules,
+      inbound_rules_file,
       ssh_public_key_path,
+      key_name,
+      ssh_port,
+      compartment_id,
+      aws_profile,
+      vpc_id,
+      subnet_id,
+      availability_zone,
+      az_fallback,
+      no_public_ip,
+      static_ip,
+      spot,
+      spot_max_price,
+      disk_size,
+      extra_volume,
+      extra_volume_mount,
+      image,
+      pre_deploy_hook,
+      post_deploy_hook,
+      pre_destroy_hook,
+      teardown_script,
+      teardown_timeout,
+      notify_url,
+      notify_format,
+      callback_url,
+      sensitive_outputs,
+      tags,
+      metadata,
+      labels_from_git,
+      user,
+      hostname,
+      vars,
+      var_file,
+      var_override,
     } => {
-      let provider_str = match provider {
-        Provider::AWS => "aws",
-        Provider::GCP => "gcp",
-        Provider::Hetzner => "hetzner",
-      };
-      let template_path = template_path(provider_str)?;
-      let run_deploy_params = RunDeployParams {
-        debug,
-        instance_type,
-        provider,
-        region,
-        script_path,
-        template_path,
-        inbound_rules,
-        ssh_public_key_path,
-      };
-      run_deploy(run_deploy_params)?;
+      let checked = (|| -> Result<_> {
+        if print_ip || print_vars || format == Format::JsonEvents {
+          STDOUT_RESERVED.store(true, Ordering::Relaxed);
+        }
+        if format == Format::JsonEvents {
+          events::to_stdout();
+        }
+        let events = events || format == Format::JsonEvents;
+        let verbosity = Verbosity::new(verbose, debug);
+        logging::set_verbosity(verbosity);
+        let providers = dedup_providers(provider);
+        use_aws_profile(aws_profile, &providers)?;
+        if providers.len() > 1 {
+          // These only make sense for one provider
+          for (flag, set) in [
+            ("--region", region.is_some()),
+            ("--instance-type", instance_type.is_some()),
+            ("--image", image.is_some()),
+            ("--template", template.is_some()),
+            ("--print-ip", print_ip),
+            ("--print-vars", print_vars),
+          ] {
+            if set {
+              anyhow::bail!("{} cannot be used with several providers", flag);
+            }
+          }
+        }
+        let inbound_rules =
+          resolve_inbound_rules_file(inbound_rules, inbound_rules_file)?;
+        let metadata: BTreeMap<_, _> = metadata
+          .into_iter()
+          .map(|var| (var.key, var.value))
+          .collect();
+        if cloud_init.is_some()
+          && providers.iter().any(|p| matches!(p, Provider::AWS))
+          && ssh_public_key_path.len() > 1
+        {
+          log::warn!(
+            "only the first --ssh-public-key-path is authorized on AWS: \
+             --cloud-init replaces the boot script that adds the others"
+          );
+        }
+        let (script, script_sha256, stdin_bytes) = match &script_url {
+          Some(url) => {
+            let (script, digest) = fetch_script(url, script_sha256.as_deref())?;
+            (Some(script), Some(digest), None)
+          }
+          None => {
+            let (script, stdin_bytes) = read_scripts(&script_path)?;
+            (script, None, stdin_bytes)
+          }
+        };
+        let cloud_config_path = cloud_config;
+        let cloud_config = cloud_config_path
+          .as_deref()
+          .map(read_cloud_config)
+          .transpose()?;
+        let cloud_init_path = cloud_init;
+        let cloud_init = cloud_init_path
+          .as_deref()
+          .map(read_cloud_init)
+          .transpose()?;
+        if cloud_init.is_some() && extra_volume.is_some() {
+          log::warn!(
+            "--extra-volume is attached but not mounted: --cloud-init replaces \
+             the boot script that mounts it"
+          );
+        }
+        // The key pair stands in for the keys fuoco would find on disk
+        let ssh_public_key_path = if key_name.is_some() {
+          Vec::new()
+        } else {
+          resolve_ssh_public_key_paths(ssh_public_key_path)
+        };
+        let env = resolve_env(env, env_file)?;
+        if !env.is_empty() && script.is_none() {
+          log::warn!("--env has no effect without a startup script");
+        }
+        let cloud_config = userdata::add_write_files(cloud_config, &files)
+          .context("Failed to add --file entries to the cloud-config")?;
+        let cloud_config =
+          userdata::set_hostname(cloud_config, hostname.as_deref())
+            .context("Failed to add --hostname to the cloud-config")?;
+        let base_params = DeployParams {
+          verbosity,
+          events,
+          progress: None,
+          yes,
+          print_ip,
+          reuse,
+          fresh_workspace,
+          skip_credential_check,
+          wait_lock,
+          retries,
+          deploy_timeout,
+          destroy_timeout,
+          deadline: timeout.map(|timeout| Instant::now() + timeout),
+          timings,
+          params_format,
+          keep,
+          idle_timeout,
+          idle_check_interval,
+          watch_logs,
+          on_hangup,
+          workdir: cli.workdir.clone(),
+          name,
+          instance_type,
+          strict_instance_type,
+          arch,
+          provider: providers[0].clone(),
+          region: None,
+          region_filter,
+          region_exclude,
+          region_rtt: None,
+          region_seed,
+          hourly_price: None,
+          script_path,
+          stdin_bytes,
+          script_url,
+          script_sha256,
+          script,
+          cloud_config_path,
+          cloud_config,
+          cloud_init_path,
+          cloud_init,
+          files,
+          env,
+          template_path: PathBuf::new(),
+          inbound_rules,
+          ssh_public_keys: read_ssh_public_keys(&ssh_public_key_path)?,
+          ssh_public_key_path,
+          key_name,
+          ssh_port,
+          compartment_id,
+          vpc_id,
+          subnet_id,
+          availability_zone: availability_zone.clone(),
+          az_fallback,
+          no_public_ip,
+          static_ip,
+          spot,
+          spot_max_price,
+          disk_size,
+          extra_volume,
+          extra_volume_mount,
+          image,
+          user,
+          hostname,
+          tags: resolve_tags(tags, labels_from_git)?,
+          metadata,
+          pre_deploy_hook,
+          post_deploy_hook,
+          pre_destroy_hook,
+          teardown: read_teardown_script(teardown_script, teardown_timeout)?,
+          notifier: notify_url
+            .map(|url| notify::Notifier::new(url, notify_format))
+            .transpose()?,
+          callback_url,
+          sensitive_outputs,
+          extra_vars: resolve_extra_vars(vars, var_file, var_override)?,
+        };
+        let mut all_params = Vec::new();
+        for provider in providers {
+          let (template_path, template_source) =
+            resolve_template(&provider, &template, no_template_override)?;
+          status!("Using {} template {:?}", template_source, template_path);
+          let mut deploy_params = DeployParams {
+            provider,
+            region: region.clone(),
+            template_path,
+            ..base_params.clone()
+          };
+          deploy_params.validate()?;
+          if let Some(zone) = &availability_zone {
+            let zone_region = aws_zone_region(zone)?;
+            match resolve_region(&deploy_params.provider, region.as_deref()) {
+              Some((region, _)) if region != zone_region => anyhow::bail!(
+                "--availability-zone {} is not in the region {}",
+                zone,
+                region
+              ),
+              Some(_) => {}
+              None => deploy_params.region = Some(zone_region),
+            }
+          }
+          // Picked here rather than at apply time, so the params shown
+          // before deploying name the region
+          if resolve_region(
+            &deploy_params.provider,
+            deploy_params.region.as_deref(),
+          )
+          .is_none()
+          {
+            pick_region(&mut deploy_params, region_strategy)?;
+          }
+          // Settle the GCP zone now, so the params name the one deployed to
+          if let (Provider::GCP, Some(region)) =
+            (&deploy_params.provider, &deploy_params.region)
+          {
+            deploy_params.region = Some(resolve_gcp_zone(region));
+          }
+          if print_vars {
+            // Sorted, so two runs diff cleanly
+            let vars: BTreeMap<_, _> =
+              deploy_params.to_atar_map().into_iter().collect();
+            println!("{}", serde_json::to_string_pretty(&vars)?);
+            return Ok(None);
+          }
+          if template_source != TemplateSource::Builtin {
+            let mut vars = deploy_params.to_atar_map();
+            // --az-fallback sets it later
+            if az_fallback {
+              vars.entry("availability_zone".to_string()).or_default();
+            }
+            validate_template_variables(&deploy_params.template_path, &vars)?;
+          }
+          all_params.push(deploy_params);
+        }
+        Ok(Some(all_params))
+      })()
+      .exit_status(exit::INVALID)?;
+      if let Some(all_params) = checked {
+        run_deploy(all_params)?;
+      }
     }
     Commands::Undeploy {
+      verbose,
       debug,
+      yes,
+      force,
       instance_type,
+      arch,
       provider,
+      name,
+      template,
+      no_template_override,
       region,
+      compartment_id,
+      aws_profile,
+      teardown_script,
+      teardown_timeout,
+      ssh_public_key_path,
+      ssh_port,
+      retries,
+      destroy_timeout,
+      timings,
+      params_format,
+      wait_lock,
+      vars,
+      var_file,
+      var_override,
+    } => {
+      let checked = (|| -> Result<_> {
+        let verbosity = Verbosity::new(verbose, debug);
+        logging::set_verbosity(verbosity);
+        require_compartment_id(&provider, &compartment_id)?;
+        use_aws_profile(aws_profile, slice::from_ref(&provider))?;
+        let (region, _) = resolve_region(&provider, region.as_deref())
+          .with_context(|| {
+            let mut sources = vec!["--region"];
+            sources.extend(region_env_vars(&provider));
+            format!("undeploy needs the region: set {}", sources.join(" or "))
+          })?;
+        if let Provider::GCP = provider {
+          validate_gcp_location(&region, true)?;
+        }
+        let (template_path, template_source) =
+          resolve_template(&provider, &template, no_template_override)?;
+        status!("Using {} template {:?}", template_source, template_path);
+        let workspace_template = workspace::Identity {
+          template: &template_path,
+          provider: provider_str(&provider),
+          name: &name,
+          region: &region,
+        }
+        .find()?;
+        let undeploy_params = UndeployParams {
+          verbosity,
+          yes,
+          force,
+          instance_type,
+          arch,
+          provider,
+          name,
+          region,
+          template_path,
+          workspace_template,
+          compartment_id,
+          teardown: read_teardown_script(teardown_script, teardown_timeout)?,
+          ssh_public_key_path,
+          ssh_port,
+          retries,
+          destroy_timeout,
+          timings,
+          params_format,
+          wait_lock,
+          extra_vars: resolve_extra_vars(vars, var_file, var_override)?,
+        };
+        if template_source != TemplateSource::Builtin {
+          validate_template_variables(
+            &undeploy_params.template_path,
+            &undeploy_params.to_atar_map(),
+          )?;
+        }
+        Ok(undeploy_params)
+      })()
+      .exit_status(exit::INVALID)?;
+      fuoco::undeploy(&checked, |atar_map, outputs| {
+        confirm_undeploy(&checked, atar_map, outputs)
+      })?;
+    }
+    Commands::Status {
+      name,
+      provider,
+      region,
+      ssh_port,
+    } => status::run(&name, provider.as_ref(), region.as_deref(), ssh_port)?,
+    Commands::Clean { yes, older_than } => clean::run(yes, older_than)?,
+    Commands::Regions { provider, refresh } => list_regions(provider, refresh)?,
+    Commands::InstanceTypes { provider, arch } => {
+      list_instance_types(provider, arch)
+    }
+    Commands::Completions { shell } => {
+      clap_complete::generate(
+        shell,
+        &mut Cli::command(),
+        "fuoco",
+        &mut io::stdout(),
+      );
+    }
+    Commands::Templates {
+      no_template_override,
     } => {
-      let provider_str = match provider {
-        Provider::AWS => "aws",
-        Provider::GCP => "gcp",
-        Provider::Hetzner => "hetzner",
+      for provider in Provider::value_variants() {
+        let (path, source) =
+          resolve_template(provider, &None, no_template_override)?;
+        println!(
+          "{:<8} {:<8} {}",
+          provider_str(provider),
+          source,
+          path.display()
+        );
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Read every --script-path in order and join them into one startup script.
+/// Also returns how much was read from stdin, for the params dump.
+fn read_scripts(paths: &[PathBuf]) -> Result<(Option<String>, Option<usize>)> {
+  if paths.iter().filter(|path| is_stdin(path)).count() > 1 {
+    anyhow::bail!("--script-path - can only be given once");
+  }
+  let mut stdin_bytes = None;
+  let mut scripts = Vec::new();
+  for path in paths {
+    let script = read_script(path)?;
+    if is_stdin(path) {
+      stdin_bytes = Some(script.len());
+    }
+    let label = if is_stdin(path) {
+      "<stdin>".to_string()
+    } else {
+      path.display().to_string()
+    };
+    scripts.push((label, script));
+  }
+  let script = match scripts.len() {
+    0 => None,
+    1 => scripts.pop().map(|(_, script)| script),
+    _ => Some(userdata::join_scripts(&scripts)),
+  };
+  Ok((script, stdin_bytes))
+}
+
+/// Read the startup script from `path`, or from stdin when it is `-`.
+fn read_script(path: &Path) -> Result<String> {
+  if !is_stdin(path) {
+    let metadata = fs::metadata(path).map_err(|err| match err.kind() {
+      io::ErrorKind::NotFound => {
+        anyhow::anyhow!("Startup script {:?} does not exist", path)
+      }
+      _ => anyhow::anyhow!("Cannot access startup script {:?}: {}", path, err),
+    })?;
+    if metadata.is_dir() {
+      anyhow::bail!(
+        "Startup script {:?} is a directory; pass each script with its own \
+         --script-path, in the order they should run",
+        path
+      );
+    }
+    // `-s <(...)` hands over a pipe
+    if !metadata.is_file() && !metadata.file_type().is_fifo() {
+      anyhow::bail!("Startup script {:?} is not a regular file", path);
+    }
+    let script = fs::read_to_string(path).with_context(|| {
+      format!("Startup script {:?} exists but cannot be read", path)
+    })?;
+    warn_on_missing_shebang(&path.display().to_string(), &script);
+    return Ok(script);
+  }
+  let stdin = io::stdin();
+  if stdin.is_terminal() {
+    anyhow::bail!("--script-path - reads the script from stdin; pipe it in");
+  }
+  let mut script = String::new();
+  stdin
+    .lock()
+    .read_to_string(&mut script)
+    .context("Failed to read startup script from stdin")?;
+  if script.trim().is_empty() {
+    anyhow::bail!("Startup script read from stdin is empty");
+  }
+  warn_on_missing_shebang("<stdin>", &script);
+  Ok(script)
+}
+
+/// fuoco's boot script runs each startup script as a file of its own, so
+/// the first line picks the interpreter.
+fn warn_on_missing_shebang(label: &str, script: &str) {
+  if !script.starts_with("#!") {
+    log::warn!(
+      "startup script {} does not start with a shebang (e.g. #!/bin/bash); \
+       it will run under bash, whatever it was written for",
+      label
+    );
+  }
+}
+
+/// Read a cloud-config file and check it is something cloud-init will act
+/// on: the `#cloud-config` header and a YAML mapping.
+fn read_cloud_config(path: &Path) -> Result<String> {
+  let cloud_config = fs::read_to_string(path)
+    .with_context(|| format!("Failed to read cloud-config {:?}", path))?;
+  if cloud_config.lines().next().map(str::trim_end) != Some("#cloud-config") {
+    anyhow::bail!("cloud-config {:?} must start with #cloud-config", path);
+  }
+  let value: serde_yaml::Value = serde_yaml::from_str(&cloud_config)
+    .with_context(|| format!("cloud-config {:?} is not valid YAML", path))?;
+  if !value.is_mapping() {
+    anyhow::bail!("cloud-config {:?} must be a YAML mapping", path);
+  }
+  Ok(cloud_config)
+}
+
+/// Read --cloud-init user data, which is passed on untouched: it only has
+/// to be YAML, and cloud-init skips a cloud-config without its header.
+fn read_cloud_init(path: &Path) -> Result<String> {
+  let user_data = fs::read_to_string(path)
+    .with_context(|| format!("Failed to read cloud-init {:?}", path))?;
+  serde_yaml::from_str::<serde_yaml::Value>(&user_data)
+    .with_context(|| format!("cloud-init {:?} is not valid YAML", path))?;
+  if user_data.lines().next().map(str::trim_end) != Some("#cloud-config") {
+    log::warn!(
+      "cloud-init {:?} does not start with #cloud-config, so cloud-init may \
+       not read it as a cloud-config",
+      path
+    );
+  }
+  Ok(user_data)
+}
+
+/// Download the startup script, refusing anything slow, large, non-2xx or
+/// not matching `expected_sha256`. Returns the script and its digest.
+fn fetch_script(
+  url: &str,
+  expected_sha256: Option<&str>,
+) -> Result<(String, String)> {
+  let agent: ureq::Agent = ureq::Agent::config_builder()
+    .timeout_global(Some(SCRIPT_DOWNLOAD_TIMEOUT))
+    .build()
+    .into();
+  let mut response = agent
+    .get(url)
+    .call()
+    .with_context(|| format!("Failed to download startup script {}", url))?;
+  let script = response
+    .body_mut()
+    .with_config()
+    .limit(SCRIPT_MAX_BYTES)
+    .read_to_string()
+    .with_context(|| format!("Failed to read startup script {}", url))?;
+  let digest = format!("{:x}", Sha256::digest(script.as_bytes()));
+  if let Some(expected) = expected_sha256 {
+    if !digest.eq_ignore_ascii_case(expected.trim()) {
+      anyhow::bail!(
+        "Startup script {} has SHA-256 {}, expected {}",
+        url,
+        digest,
+        expected
+      );
+    }
+  }
+  warn_on_missing_shebang(url, &script);
+  Ok((script, digest))
+}
+
+/// Combine the git labels (when asked for) with --tag, which wins, and
+/// hold them to the strictest labelling rules (GCP's) so every provider
+/// accepts them.
+fn resolve_tags(
+  tags: Vec<TemplateVar>,
+  labels_from_git: bool,
+) -> Result<BTreeMap<String, String>> {
+  let mut resolved = BTreeMap::new();
+  if labels_from_git {
+    resolved.extend(git_labels());
+  }
+  for tag in tags {
+    if !tag.key.starts_with(|c: char| c.is_ascii_lowercase())
+      || label_value(&tag.key) != tag.key
+      || label_value(&tag.value) != tag.value
+    {
+      anyhow::bail!(
+        "--tag {}={} must use only lowercase letters, digits, '_' and '-' \
+         (at most 63 characters each, key starting with a letter)",
+        tag.key,
+        tag.value
+      );
+    }
+    resolved.insert(tag.key, tag.value);
+  }
+  Ok(resolved)
+}
+
+/// `git_repo`, `git_branch` and `git_commit` for the working directory,
+/// made label-safe. Outside a git repo (or without git) this is empty, as
+/// labels are never worth failing a deploy over.
+fn git_labels() -> Vec<(String, String)> {
+  let git = |args: &[&str]| {
+    process::Command::new("git")
+      .args(args)
+      .stderr(process::Stdio::null())
+      .output()
+      .ok()
+      .filter(|output| output.status.success())
+      .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+  };
+  let Some(toplevel) = git(&["rev-parse", "--show-toplevel"]) else {
+    log::warn!("not inside a git repository; skipping git labels");
+    return Vec::new();
+  };
+  let repo = Path::new(&toplevel)
+    .file_name()
+    .map(|name| name.to_string_lossy().to_string());
+  let labels = [
+    ("git_repo", repo),
+    ("git_branch", git(&["rev-parse", "--abbrev-ref", "HEAD"])),
+    ("git_commit", git(&["rev-parse", "HEAD"])),
+  ];
+  labels
+    .into_iter()
+    .filter_map(|(key, value)| Some((key.to_string(), label_value(&value?))))
+    .collect()
+}
+
+/// Lowercase `value` and replace anything a GCP label value cannot hold
+/// (e.g. the `/` in `feature/x`) with `-`.
+fn label_value(value: &str) -> String {
+  value
+    .to_lowercase()
+    .chars()
+    .map(|c| match c {
+      'a'..='z' | '0'..='9' | '_' | '-' => c,
+      _ => '-',
+    })
+    .take(63)
+    .collect()
+}
+
+/// `90`, `90s`, `10m`, `1h` or `7d`; a bare number is seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+  let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+    Some(i) => value.split_at(i),
+    None => (value, "s"),
+  };
+  let number: u64 = number.parse().map_err(|_| {
+    format!("expected a duration like 90s, 10m or 1h, got {:?}", value)
+  })?;
+  let secs = match unit {
+    "s" => number,
+    "m" => number * 60,
+    "h" => number * 3600,
+    "d" => number * 86400,
+    _ => {
+      return Err(format!(
+        "unknown duration unit {:?} (use s, m, h or d)",
+        unit
+      ))
+    }
+  };
+  Ok(Duration::from_secs(secs))
+}
+
+/// An RFC 1123 host label: what every provider and image accepts as a
+/// hostname. Used as a clap value parser.
+fn parse_hostname(hostname: &str) -> Result<String, String> {
+  let valid = (1..=63).contains(&hostname.len())
+    && hostname
+      .chars()
+      .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    && !hostname.starts_with('-')
+    && !hostname.ends_with('-');
+  if !valid {
+    return Err(
+      "must be 1-63 lowercase letters, digits and '-', not starting or \
+       ending with '-'"
+        .to_string(),
+    );
+  }
+  Ok(hostname.to_string())
+}
+
+/// --aws-profile, through the variable that the AWS CLI of the credential
+/// check and Terraform's AWS provider both read.
+fn use_aws_profile(
+  profile: Option<String>,
+  providers: &[Provider],
+) -> Result<()> {
+  let Some(profile) = profile else {
+    return Ok(());
+  };
+  if !providers.iter().any(|p| matches!(p, Provider::AWS)) {
+    anyhow::bail!("--aws-profile is only supported by the aws provider");
+  }
+  env::set_var("AWS_PROFILE", profile);
+  Ok(())
+}
+
+/// The region an AWS availability zone is in: `us-east-1` for `us-east-1a`
+/// and for the Local Zone `us-east-1-bos-1a`.
+fn aws_zone_region(zone: &str) -> Result<String> {
+  let parts: Vec<&str> = zone.split('-').collect();
+  let lowercase = |part: &&str| {
+    !part.is_empty() && part.chars().all(|c| c.is_ascii_lowercase())
+  };
+  let number = parts.get(2).map_or("", |part| {
+    part.trim_end_matches(|c: char| c.is_ascii_lowercase())
+  });
+  let letters = parts.get(2).map_or(0, |part| part.len() - number.len());
+  let shaped = parts.len() >= 3
+    && parts[..2].iter().all(lowercase)
+    && !number.is_empty()
+    && number.chars().all(|c| c.is_ascii_digit())
+    && zone.ends_with(|c: char| c.is_ascii_lowercase())
+    // us-east-1a, or a Local Zone's us-east-1-bos-1a
+    && (parts.len() == 3 && letters == 1 || parts.len() > 3 && letters == 0);
+  if !shaped {
+    anyhow::bail!(
+      "{:?} is not an AWS availability zone (e.g. us-east-1a)",
+      zone
+    );
+  }
+  Ok(format!("{}-{}-{}", parts[0], parts[1], number))
+}
+
+/// --provider values: every provider, plus `all` for all of them.
+fn provider_selection_parser() -> impl TypedValueParser<Value = Vec<Provider>> {
+  let values = Provider::value_variants()
+    .iter()
+    .filter_map(ValueEnum::to_possible_value)
+    .chain([clap::builder::PossibleValue::new("all").help("every provider")]);
+  clap::builder::PossibleValuesParser::new(values).map(|value| {
+    if value == "all" {
+      Provider::value_variants().to_vec()
+    } else {
+      vec![<Provider as ValueEnum>::from_str(&value, true)
+        .expect("checked by PossibleValuesParser")]
+    }
+  })
+}
+
+/// The selected providers in order, each once.
+fn dedup_providers(selection: Vec<Vec<Provider>>) -> Vec<Provider> {
+  let mut providers: Vec<Provider> = Vec::new();
+  for provider in selection.into_iter().flatten() {
+    if !providers
+      .iter()
+      .any(|p| provider_str(p) == provider_str(&provider))
+    {
+      providers.push(provider);
+    }
+  }
+  providers
+}
+
+/// `fuoco regions`: each provider's region list and its source, fetched
+/// anew first with `refresh`. A failed fetch leaves the cache as it was.
+fn list_regions(provider: Option<Provider>, refresh: bool) -> Result<()> {
+  let providers = match provider {
+    Some(provider) => vec![provider],
+    None => Provider::value_variants().to_vec(),
+  };
+  if refresh {
+    let mut failed = 0;
+    let mut refreshable = 0;
+    for provider in &providers {
+      if !regions::refreshable(provider) {
+        continue;
+      }
+      refreshable += 1;
+      status!("Fetching {:?} regions...", provider);
+      if let Err(err) = regions::refresh(provider) {
+        log::warn!("could not fetch the {:?} regions: {:#}", provider, err);
+        failed += 1;
+      }
+    }
+    if refreshable == 0 {
+      anyhow::bail!(
+        "--refresh: fuoco fetches live regions for AWS, GCP and Hetzner only"
+      );
+    }
+    if failed == refreshable {
+      anyhow::bail!("could not fetch any region list");
+    }
+  }
+  for provider in &providers {
+    let (list, source) = regions::available(provider);
+    println!(
+      "{:<8} {:<8} {}",
+      provider_str(provider),
+      source,
+      list.join(", ")
+    );
+  }
+  Ok(())
+}
+
+/// `fuoco instance-types`: the types --strict-instance-type accepts, one
+/// per line, marking the ones deployed when --instance-type is left out.
+fn list_instance_types(provider: Option<Provider>, arch: Option<Arch>) {
+  let providers = match provider {
+    Some(provider) => vec![provider],
+    None => Provider::value_variants().to_vec(),
+  };
+  for provider in &providers {
+    for instance_type in known_instance_types(provider) {
+      let Some(type_arch) = instance_type_arch(provider, instance_type) else {
+        continue;
       };
-      let template_path = template_path(provider_str)?;
-      let run_undeploy_params = RunUndeployParams {
-        debug,
+      if arch.is_some_and(|arch| arch != type_arch) {
+        continue;
+      }
+      let default =
+        resolve_default_instance_type(provider, type_arch) == *instance_type;
+      let price = pricing::hourly_usd(provider_str(provider), instance_type);
+      println!(
+        "{:<8} {:<24} {:<7} {}{}",
+        provider_str(provider),
         instance_type,
-        provider,
-        region,
-        template_path,
-      };
-      run_undeploy(run_undeploy_params)?;
+        type_arch,
+        pricing::describe(price),
+        if default { "  (default)" } else { "" }
+      );
     }
   }
-  Ok(())
 }
 
-/// Determine the path to the Terraform template for the given provider.
-fn template_path(provider_str: &str) -> Result<PathBuf> {
-  let manifest =
-    env::var("CARGO_MANIFEST_DIR").context("CARGO_MANIFEST_DIR is not set")?;
-  let mut path = PathBuf::from(manifest);
-  path.push("templates");
-  path.push(provider_str);
-  path.push("main.tf");
-  Ok(path)
+/// Share downloaded Terraform providers between workspaces, so a fresh one
+/// does not fetch them again. An existing TF_PLUGIN_CACHE_DIR wins.
+fn use_plugin_cache() {
+  if env::var_os("TF_PLUGIN_CACHE_DIR").is_some() {
+    return;
+  }
+  let Some(dir) = cache_dir().map(|dir| dir.join("plugins")) else {
+    return;
+  };
+  // Terraform ignores a cache directory that does not exist
+  match fs::create_dir_all(&dir) {
+    Ok(()) => env::set_var("TF_PLUGIN_CACHE_DIR", dir),
+    Err(err) => log::warn!("no Terraform plugin cache at {:?}: {}", dir, err),
+  }
 }
 
-fn run_deploy(params: RunDeployParams) -> Result<()> {
-  println!("{:?}", params);
-  // Remove any existing cached Terraform workspace so changes to templates are picked up
+/// Terraform rejects values for undeclared variables, so check up front that
+/// a custom template declares everything fuoco is about to pass, in any of
+/// the `.tf` files of its directory.
+fn validate_template_variables(
+  template_path: &Path,
+  atar_map: &HashMap<String, String>,
+) -> Result<()> {
+  let dir = template_path
+    .parent()
+    .filter(|dir| !dir.as_os_str().is_empty())
+    .unwrap_or(Path::new("."));
+  let mut content = String::new();
+  for entry in fs::read_dir(dir)
+    .with_context(|| format!("Failed to read template directory {:?}", dir))?
   {
-    let template_dir = params
-      .template_path
-      .parent()
-      .context("Cannot determine Terraform directory")?;
-    let mut hasher = Sha256::new();
-    hasher.update(template_dir.to_string_lossy().as_bytes());
-    let hash = format!("{:x}", hasher.finalize());
-    let work = env::temp_dir().join("atar").join(hash);
-    if work.exists() {
-      fs::remove_dir_all(&work)
-        .context("Failed to remove stale Terraform workspace")?;
-    }
-  }
-  let hash_map = params.to_atar_map();
-  let outputs = lib_deploy(&params.template_path, &hash_map, params.debug)?;
-  if !outputs.is_empty() {
-    println!("*************************** Outputs **************************");
-    for (k, v) in outputs {
-      println!("{}: {}", k, v);
+    let path = entry?.path();
+    if path.extension().is_some_and(|extension| extension == "tf") {
+      content += &fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read template {:?}", path))?;
+      content.push('\n');
     }
-    println!("**************************************************************");
   }
+  let declared: Vec<&str> = content
+    .lines()
+    .filter_map(|line| line.trim_start().strip_prefix("variable"))
+    .filter_map(|rest| rest.trim_start().strip_prefix('"'))
+    .filter_map(|rest| rest.split('"').next())
+    .collect();
+  let mut missing: Vec<&str> = atar_map
+    .keys()
+    .map(|key| key.as_str())
+    .filter(|key| !declared.contains(key))
+    .collect();
+  if !missing.is_empty() {
+    missing.sort();
+    anyhow::bail!(
+      "Template {:?} does not declare the variables: {}",
+      dir,
+      missing.join(", ")
+    );
+  }
+  Ok(())
+}
 
-  let guard = DestroyGuard {
-    params: params.clone(),
-  };
+fn run_deploy(all_params: Vec<DeployParams>) -> Result<()> {
+  let deployments: Vec<_> =
+    all_params.into_iter().map(Deployment::new).collect();
+  for deployment in &deployments {
+    let params = deployment.params();
+    match params.params_format {
+      ParamsFormat::Text => status!("{}", params),
+      ParamsFormat::Json => status!(
+        "{}",
+        serde_json::to_string(&resolved::deploy(
+          params,
+          deployment.variables()
+        ))?
+      ),
+    }
+  }
+  if deployments
+    .iter()
+    .any(|deployment| deployment.params().spot)
+  {
+    // A reclaimed instance is dropped from state on refresh, so the
+    // handle still cleans up the remaining resources.
+    log::warn!(
+      "spot instances can be reclaimed by the provider at any \
+       time; the VM may disappear before you stop fuoco."
+    );
+  }
+  // Before the confirmation, and before Terraform gets to fail on them
+  for deployment in &deployments {
+    deployment.check_credentials()?;
+  }
+  // Everything but the provider comes from the same flags
+  let first = deployments[0].params();
+  let (yes, print_ip, keep, on_hangup, timings_format, deadline) = (
+    first.yes,
+    first.print_ip,
+    first.keep,
+    first.on_hangup,
+    first.timings,
+    first.deadline,
+  );
+  if !yes && !confirm_deploy(&deployments)? {
+    anyhow::bail!("Deploy cancelled");
+  }
+  let handles = deploy_all(deployments)?;
+  let multi = handles.len() > 1;
+  let timings: Vec<_> = handles
+    .iter()
+    .map(|h| {
+      let template = h.params().identity(h.variables()).template();
+      (provider_str(&h.params().provider), template, h.timings())
+    })
+    .collect();
+  for handle in &handles {
+    if multi {
+      status!("\n[{}]", provider_str(&handle.params().provider));
+    }
+    print_outputs(handle.outputs());
+    status!("Estimated cost: {}", handle.params().estimated_cost());
+    // --keep prints it in its warning below
+    if !keep {
+      status!(
+        "Undeploy command, should fuoco be killed before it destroys:\n  {}",
+        undeploy_command(handle.params(), handle.variables())
+      );
+    }
+  }
+
+  // A panic runs the hook and then unwinds through the handles; only the
+  // first of them destroys
+  let destroyers: Vec<_> = handles.iter().map(|h| h.destroyer()).collect();
   {
     let previous = panic::take_hook();
+    let destroyers = destroyers.clone();
     panic::set_hook(Box::new(move |info| {
-      eprintln!("panic: {:?}, cleaning up Terraform...", info);
-      if let Err(err) =
-        lib_undeploy(&params.template_path, &hash_map, params.debug)
-      {
-        eprintln!("cleanup after panic failed: {}", err);
+      log::error!("panic: {:?}, cleaning up Terraform...", info);
+      for destroyer in &destroyers {
+        if let Err(err) = destroyer.destroy() {
+          log::error!("Failed to destroy Terraform resources: {}", err);
+        }
       }
       previous(info);
     }));
   }
 
   let (tx, rx) = mpsc::channel();
-  let mut signals =
-    Signals::new(&[SIGINT, SIGTERM]).context("Failed to set signal handler")?;
+  if let Some(deadline) = deadline {
+    let tx = tx.clone();
+    thread::spawn(move || {
+      thread::sleep(deadline.saturating_duration_since(Instant::now()));
+      let _ = tx.send(WakeReason::Timeout);
+    });
+  }
+  for handle in &handles {
+    let params = handle.params();
+    if params.spot {
+      if let Some(addr) = spot_probe_address(params, handle.outputs()) {
+        watch_spot_instance(addr, tx.clone());
+      }
+    }
+    if let Some(idle_timeout) = params.idle_timeout {
+      let Some(target) = ssh_target(
+        &params.provider,
+        handle.outputs(),
+        params.ssh_public_key_path.first().map(String::as_str),
+        params.ssh_port,
+      ) else {
+        log::warn!("--idle-timeout: no IP to check the instance on");
+        continue;
+      };
+      let tx = tx.clone();
+      idle::watch(
+        target,
+        idle_timeout,
+        params.idle_check_interval,
+        move || {
+          let _ = tx.send(WakeReason::Idle(idle_timeout));
+        },
+      );
+    }
+  }
+  let log_watches: Vec<_> = handles
+    .iter()
+    .filter(|h| h.params().watch_logs)
+    .filter_map(|h| {
+      let params = h.params();
+      let Some(target) = ssh_target(
+        &params.provider,
+        h.outputs(),
+        params.ssh_public_key_path.first().map(String::as_str),
+        params.ssh_port,
+      ) else {
+        log::warn!("--watch-logs: no IP to reach the instance on");
+        return None;
+      };
+      let prefix = if multi {
+        format!("[{}] ", provider_str(&params.provider))
+      } else {
+        String::new()
+      };
+      Some(bootlog::watch(target, prefix))
+    })
+    .collect();
+  let mut signals = Signals::new(&[SIGINT, SIGTERM, SIGHUP, SIGUSR1])
+    .context("Failed to set signal handler")?;
+  // Keep listening once the destroy has started, so a hung destroy can
+  // still be abandoned without resorting to kill -9
+  let undeploy_hints: Vec<_> = handles
+    .iter()
+    .map(|h| undeploy_command(h.params(), h.variables()))
+    .collect();
   thread::spawn(move || {
-    for _ in signals.forever() {
-      let _ = tx.send(());
-      break;
+    let mut abandon_until: Option<Instant> = None;
+    for signal in signals.forever() {
+      if !destroyers.iter().any(|d| d.started()) {
+        let _ = tx.send(wake_reason(signal, on_hangup));
+        continue;
+      }
+      // Nobody is at the terminal to have asked for these
+      if signal == SIGHUP || signal == SIGUSR1 {
+        continue;
+      }
+      match abandon_until {
+        Some(until) if Instant::now() <= until => {
+          abandon_destroy(&undeploy_hints)
+        }
+        _ => {
+          let warning = format!(
+            "Destroy in progress, press Ctrl+C again within {}s to abandon \
+             (resources will be LEAKED)",
+            ABANDON_WINDOW.as_secs()
+          );
+          eprintln!("\n{}", style::alert(warning, Stream::Stderr));
+          abandon_until = Some(Instant::now() + ABANDON_WINDOW);
+        }
+      }
     }
   });
-  println!(
+  if print_ip {
+    // Tearing down beats handing a script a deployment it cannot reach
+    let handle = &handles[0];
+    let provider = &handle.params().provider;
+    let ip = instance_ip(provider, handle.outputs()).with_context(|| {
+      format!(
+        "--print-ip: the template has none of the outputs {}, private_ip",
+        public_ip_output_keys(provider).join(", ")
+      )
+    })?;
+    println!("{}", ip);
+  }
+  if keep {
+    keep_deployments(handles);
+    report_timings(&timings, timings_format);
+    return Ok(());
+  }
+  status!(
     "Resources deployed.\n\nPress Ctrl+C or send SIGTERM to destroy and exit."
   );
-  let _ = rx.recv();
-  println!("\nSignal received: starting Terraform destroy...");
-  drop(guard);
+  emit_all(&handles, || Event::WaitingForSignal);
+  let reason = loop {
+    match rx.recv() {
+      Ok(WakeReason::Info) => {
+        for handle in &handles {
+          status!("{}", handle.params());
+          print_outputs(handle.outputs());
+        }
+        status!("Up for {}", format_elapsed(handles[0].uptime()));
+      }
+      reason => break reason,
+    }
+  };
+  for watch in &log_watches {
+    watch.stop();
+  }
+  match reason {
+    Ok(WakeReason::InstanceLost) => {
+      // With several providers the comparison is spoiled anyway, so all
+      // of them go
+      emit_all(&handles, || Event::InstanceLost);
+      status!(
+        "\nA spot instance stopped responding and was probably reclaimed: \
+         destroying the remaining resources..."
+      );
+    }
+    Ok(WakeReason::Idle(idle_timeout)) => {
+      emit_all(&handles, || Event::IdleTimeout);
+      status!(
+        "\nIdle for {}: destroying the resources...",
+        format_elapsed(idle_timeout)
+      );
+    }
+    Ok(WakeReason::Timeout) => {
+      emit_all(&handles, || Event::Timeout);
+      status!("\n--timeout expired: destroying the resources...");
+    }
+    Ok(WakeReason::Interrupt)
+      if !yes && io::stdin().is_terminal() && !confirm_destroy()? =>
+    {
+      keep_deployments(handles);
+      report_timings(&timings, timings_format);
+      return Ok(());
+    }
+    Ok(WakeReason::Detach) => {
+      // The terminal is gone, so there is nobody to print for. The
+      // workspaces keep the state for `fuoco undeploy`
+      for handle in handles {
+        handle.keep();
+      }
+      return Ok(());
+    }
+    _ => {
+      emit_all(&handles, || Event::SignalReceived);
+      status!("\nSignal received: starting Terraform destroy...");
+    }
+  }
+  let lifetimes: Vec<_> = handles
+    .iter()
+    .map(|h| {
+      let params = h.params();
+      (
+        provider_str(&params.provider),
+        h.deployed_at(),
+        params.hourly_usd(),
+      )
+    })
+    .collect();
+  let timed_out = matches!(reason, Ok(WakeReason::Timeout));
+  let result = destroy_all(handles);
+  if result.is_ok() {
+    report_lifetimes(&lifetimes);
+  }
+  report_timings(&timings, timings_format);
+  result?;
+  if timed_out {
+    return Err(anyhow::anyhow!("--timeout expired"))
+      .exit_status(exit::TIMEOUT);
+  }
   Ok(())
 }
 
-fn run_undeploy(params: RunUndeployParams) -> Result<()> {
-  println!("{:?}", params);
-  let hash_map = params.to_atar_map();
-  lib_undeploy(&params.template_path, &hash_map, params.debug)?;
+/// Apply every deployment, in parallel when there are several. A failed
+/// one has already cleaned up after itself; the others carry on unless
+/// none is left.
+fn deploy_all(mut deployments: Vec<Deployment>) -> Result<Vec<DeployHandle>> {
+  if deployments.len() == 1 {
+    return Ok(vec![deployments.remove(0).deploy()?]);
+  }
+  let results: Vec<_> = thread::scope(|scope| {
+    let workers: Vec<_> = deployments
+      .into_iter()
+      .map(|deployment| {
+        let provider = provider_str(&deployment.params().provider);
+        (provider, scope.spawn(move || deployment.deploy()))
+      })
+      .collect();
+    workers
+      .into_iter()
+      .map(|(provider, worker)| {
+        let result = worker
+          .join()
+          .unwrap_or_else(|_| Err(anyhow::anyhow!("deploy thread panicked")));
+        (provider, result)
+      })
+      .collect()
+  });
+  let mut handles = Vec::new();
+  // The worst of the failures: a leak outranks a clean failure
+  let mut code = 1;
+  for (provider, result) in results {
+    match result {
+      Ok(handle) => handles.push(handle),
+      Err(err) => {
+        log::warn!("{} deploy failed: {:#}", provider, err);
+        code = code.max(exit::code(&err));
+      }
+    }
+  }
+  if handles.is_empty() {
+    return Err(anyhow::anyhow!("every deploy failed")).exit_status(code);
+  }
+  Ok(handles)
+}
+
+/// Destroy every deployment, in parallel, and fail if any of them did.
+fn destroy_all(mut handles: Vec<DeployHandle>) -> Result<()> {
+  if handles.len() == 1 {
+    return handles.remove(0).destroy();
+  }
+  let failed: Vec<_> = thread::scope(|scope| {
+    let workers: Vec<_> = handles
+      .into_iter()
+      .map(|handle| {
+        let provider = provider_str(&handle.params().provider);
+        (provider, scope.spawn(move || handle.destroy()))
+      })
+      .collect();
+    workers
+      .into_iter()
+      .filter_map(|(provider, worker)| match worker.join() {
+        Ok(Ok(())) => None,
+        _ => Some(provider),
+      })
+      .collect()
+  });
+  if !failed.is_empty() {
+    return Err(anyhow::anyhow!("destroying {} failed", failed.join(", ")))
+      .exit_status(exit::DESTROY_FAILED);
+  }
   Ok(())
 }
 
-struct DestroyGuard {
-  params: RunDeployParams,
-}
-
-impl Drop for DestroyGuard {
-  fn drop(&mut self) {
-    let hash_map = self.params.to_atar_map();
-    lib_undeploy(&self.params.template_path, &hash_map, self.params.debug)
-      .unwrap_or_else(|err| {
-        eprintln!("Failed to destroy Terraform resources: {}", err);
-      });
-  }
-}
-fn resolve_random_region(provider: &Provider) -> String {
-  let aws_regions = vec![
-    "us-east-1",
-    "us-east-2",
-    "us-west-1",
-    "us-west-2",
-    "ap-south-1",
-    "ap-northeast-3",
-    "ap-northeast-2",
-    "ap-southeast-1",
-    "ap-southeast-2",
-    "ap-northeast-1",
-    "ca-central-1",
-    "eu-central-1",
-    "eu-west-1",
-    "eu-west-2",
-    "eu-west-3",
-    "eu-north-1",
-    "sa-east-1",
-  ];
-  let gcp_regions = vec![
-    "us-central1",
-    "us-east1",
-    "us-east4",
-    "us-west1",
-    "us-west2",
-    "us-west3",
-    "us-west4",
-    "northamerica-northeast1",
-    "southamerica-east1",
-    "europe-west1",
-    "europe-west2",
-    "europe-west3",
-    "europe-west4",
-    "europe-west6",
-    "europe-west8",
-    "europe-west9",
-    "europe-north1",
-    "europe-southwest1",
-    "asia-east1",
-    "asia-east2",
-    "asia-northeast1",
-    "asia-northeast2",
-    "asia-northeast3",
-    "asia-south1",
-    "asia-south2",
-    "asia-southeast1",
-    "asia-southeast2",
-    "australia-southeast1",
-    "australia-southeast2",
-    "me-central1",
-    "me-west1",
-  ];
-  let hetzner_regions = vec!["fsn1", "nbg1", "hel1", "ash", "hil"];
-  match provider {
-    Provider::AWS => aws_regions
-      .choose(&mut rand::thread_rng())
-      .expect("Cannot resolve random region for AWS")
-      .to_string(),
-    Provider::GCP => gcp_regions
-      .choose(&mut rand::thread_rng())
-      .expect("Cannot resolve random region for GCP")
-      .to_string(),
-    Provider::Hetzner => hetzner_regions
-      .choose(&mut rand::thread_rng())
-      .expect("Cannot resolve random region for Hetzner")
-      .to_string(),
-  }
-}
-
-fn resolve_default_inbound_rule() -> Vec<InboundRule> {
-  return vec![InboundRule {
-    protocol: "tcp".to_string(),
-    port_number: 22,
-  }];
-}
-
-fn resolve_default_instance_type(provider: &Provider) -> String {
-  match provider {
-    Provider::AWS => "t3.micro".to_string(),
-    Provider::GCP => "f1-micro".to_string(),
-    Provider::Hetzner => "cx11".to_string(),
+/// Print how long each destroyed deployment was up, and roughly what that
+/// cost when the instance type is in the price list.
+fn report_lifetimes(lifetimes: &[(&str, Instant, Option<f64>)]) {
+  let multi = lifetimes.len() > 1;
+  for (provider, deployed_at, hourly) in lifetimes {
+    let elapsed = deployed_at.elapsed();
+    let mut line = format!("Up for {}", format_elapsed(elapsed));
+    if let Some(hourly) = hourly {
+      line = format!("{}, {}", line, pricing::accrued(*hourly, elapsed));
+    }
+    if multi {
+      status!("[{}] {}", provider, line);
+    } else {
+      status!("{}", line);
+    }
+  }
+}
+
+/// Print how long each phase of each deployment took, and record it with
+/// the deployment.
+fn report_timings(
+  timings: &[(&str, PathBuf, Arc<Timings>)],
+  format: TimingsFormat,
+) {
+  let multi = timings.len() > 1;
+  for (provider, template, timings) in timings {
+    timings.print(format, multi.then_some(*provider));
+    if let Err(err) =
+      workspace::record_timings(template, "deploy", timings.to_json())
+    {
+      log::warn!("could not record the timings: {:#}", err);
+    }
+  }
+}
+
+/// Send an event that concerns every deployment, e.g. the shared signal.
+fn emit_all<'a>(handles: &[DeployHandle], event: impl Fn() -> Event<'a>) {
+  for handle in handles {
+    handle.params().emit(event());
+  }
+}
+
+/// Leave the deployments running: no destroy now or on exit.
+fn keep_deployments(handles: Vec<DeployHandle>) {
+  let undeploy_hints: Vec<_> = handles
+    .iter()
+    .map(|h| undeploy_command(h.params(), h.variables()))
+    .collect();
+  eprintln!(
+    "\n****************************************************************\n\
+     WARNING: the resources will NOT be auto-destroyed and keep costing\n\
+     money. Destroy them later with:\n  {}\n\
+     ****************************************************************",
+    undeploy_hints.join("\n  ")
+  );
+  for handle in handles {
+    handle.keep();
+  }
+}
+
+/// The outputs on stdout for scripts to read, framed on stderr.
+fn print_outputs(outputs: &HashMap<String, String>) {
+  if !outputs.is_empty() {
+    status!("*************************** Outputs **************************");
+    for (k, v) in outputs {
+      if STDOUT_RESERVED.load(Ordering::Relaxed) {
+        status!("{}: {}", k, v);
+      } else {
+        println!("{}: {}", style::bold(k, Stream::Stdout), v);
+      }
+    }
+    status!("**************************************************************");
+  }
+}
+
+fn read_teardown_script(
+  path: Option<PathBuf>,
+  timeout_secs: u64,
+) -> Result<Option<teardown::Script>> {
+  path
+    .map(|path| teardown::Script::read(path, Duration::from_secs(timeout_secs)))
+    .transpose()
+}
+
+/// How long a second Ctrl+C during the destroy waits for the third.
+const ABANDON_WINDOW: Duration = Duration::from_secs(5);
+
+/// Give up on the destroy: stop Terraform, say how to finish the job later
+/// and exit. The workspace and its state stay behind for `fuoco undeploy`.
+fn abandon_destroy(undeploy_hints: &[String]) -> ! {
+  watchdog::stop_terraform();
+  eprintln!();
+  log::error!(
+    "Destroy abandoned: the resources were LEAKED. Destroy them later \
+     with:\n  {}",
+    undeploy_hints.join("\n  ")
+  );
+  capture::cleanup();
+  process::exit(exit::DESTROY_ABANDONED);
+}
+
+/// What a signal received while the deployment is up asks for.
+fn wake_reason(signal: i32, on_hangup: HangupAction) -> WakeReason {
+  match signal {
+    SIGINT => WakeReason::Interrupt,
+    SIGUSR1 => WakeReason::Info,
+    SIGHUP if on_hangup == HangupAction::Keep => WakeReason::Detach,
+    _ => WakeReason::Signal,
+  }
+}
+
+/// Why the deploy wait loop woke up.
+#[derive(Debug, PartialEq)]
+enum WakeReason {
+  /// SIGINT, usually Ctrl+C at the terminal.
+  Interrupt,
+  Signal,
+  InstanceLost,
+  /// SIGHUP with `--on-hangup keep`: leave without destroying.
+  Detach,
+  /// SIGUSR1: reprint the status, then keep waiting.
+  Info,
+  /// The instance was idle for the --idle-timeout.
+  Idle(Duration),
+  /// --timeout expired.
+  Timeout,
+}
+
+const SPOT_PROBE_INTERVAL: Duration = Duration::from_secs(30);
+const SPOT_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+const SPOT_PROBE_FAILURES: u32 = 3;
+
+/// Ask before creating anything when a person is at the keyboard. Scripts
+/// and CI runs (stdin not a terminal) are never prompted.
+fn confirm_deploy(deployments: &[Deployment]) -> Result<bool> {
+  let stdin = io::stdin();
+  if !stdin.is_terminal() {
+    return Ok(true);
+  }
+  let targets: Vec<_> = deployments
+    .iter()
+    .map(|deployment| {
+      let (params, atar_map) = (deployment.params(), deployment.variables());
+      let lifecycle = if params.spot { "spot" } else { "on-demand" };
+      format!(
+        "{} {} ({}, {}) in {}",
+        provider_str(&params.provider),
+        atar_map["instance_type"],
+        lifecycle,
+        params.estimated_cost(),
+        location(atar_map),
+      )
+    })
+    .collect();
+  let answer = prompt(&format!(
+    "Deploy {} as {:?}? [y/N] ",
+    targets.join(", "),
+    deployments[0].params().name,
+  ))?;
+  Ok(matches!(answer.as_str(), "y" | "yes"))
+}
+
+/// On Ctrl+C at a terminal, check the user really wants the VM gone.
+/// Anything but an explicit no destroys.
+fn confirm_destroy() -> Result<bool> {
+  let answer = prompt("\nDestroy the resources now? [Y/n] ")?;
+  Ok(!matches!(answer.as_str(), "n" | "no"))
+}
+
+/// Ask `question` on the terminal and return the lowercased answer.
+fn prompt(question: &str) -> Result<String> {
+  let mut out = io::stderr();
+  write!(out, "{}", question)
+    .and_then(|()| out.flush())
+    .context("Failed to write prompt")?;
+  let mut answer = String::new();
+  io::stdin()
+    .read_line(&mut answer)
+    .context("Failed to read answer")?;
+  Ok(answer.trim().to_lowercase())
+}
+
+/// The public IP and first open TCP port of a spot deployment, if both exist.
+fn spot_probe_address(
+  params: &DeployParams,
+  outputs: &HashMap<String, String>,
+) -> Option<SocketAddr> {
+  let ip = instance_ip(&params.provider, outputs)?.parse().ok()?;
+  let port =
+    resolve_inbound_rules(params.inbound_rules.as_ref(), params.ssh_port)
+      .into_iter()
+      .find(|rule| rule.protocol == "tcp")?
+      .port_number;
+  Some(SocketAddr::new(ip, port))
+}
+
+/// Probe a spot instance until it stops answering after having answered at
+/// least once, which is how a reclaimed instance looks from the outside.
+fn watch_spot_instance(addr: SocketAddr, tx: mpsc::Sender<WakeReason>) {
+  thread::spawn(move || {
+    let mut reachable = false;
+    let mut failures = 0;
+    loop {
+      thread::sleep(SPOT_PROBE_INTERVAL);
+      match TcpStream::connect_timeout(&addr, SPOT_PROBE_TIMEOUT) {
+        Ok(_) => {
+          reachable = true;
+          failures = 0;
+        }
+        Err(_) if reachable => {
+          failures += 1;
+          if failures >= SPOT_PROBE_FAILURES {
+            let _ = tx.send(WakeReason::InstanceLost);
+            break;
+          }
+        }
+        Err(_) => {}
+      }
+    }
+  });
+}
+
+/// The workspace is shared by everyone using the same template on this
+/// machine, so show what is about to go and ask first. Without a terminal
+/// there is nobody to ask: that takes --yes.
+fn confirm_undeploy(
+  params: &UndeployParams,
+  atar_map: &HashMap<String, String>,
+  outputs: &Result<HashMap<String, String>>,
+) -> Result<bool> {
+  if !io::stdin().is_terminal() {
+    anyhow::bail!(
+      "undeploy destroys without a way back: pass --yes to confirm when \
+       stdin is not a terminal"
+    );
+  }
+  let found = match outputs {
+    Ok(outputs) if !outputs.is_empty() => format!(
+      "a deployment at {}",
+      instance_ip(&params.provider, outputs).unwrap_or("no IP")
+    ),
+    Ok(_) => {
+      "no recorded resources; destroy will likely do nothing".to_string()
+    }
+    Err(_) => {
+      "no deployment state; destroy runs against an empty workspace".to_string()
+    }
+  };
+  let answer = prompt(&format!(
+    "{} {} {} in {} ({})? [y/N] ",
+    if params.force {
+      "Force-destroy (may leave resources behind)"
+    } else {
+      "Destroy"
+    },
+    provider_str(&params.provider),
+    atar_map["instance_type"],
+    location(atar_map),
+    found,
+  ))?;
+  Ok(matches!(answer.as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use signal_hook::low_level::raise;
+
+  /// Each signal, delivered to the process, and the path the wait loop
+  /// takes for it.
+  #[test]
+  fn signals_pick_their_path() {
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP, SIGUSR1]).unwrap();
+    let mut deliver = |signal| {
+      raise(signal).unwrap();
+      signals.forever().next().unwrap()
+    };
+    let cases = [
+      (SIGINT, HangupAction::Destroy, WakeReason::Interrupt),
+      (SIGTERM, HangupAction::Destroy, WakeReason::Signal),
+      (SIGUSR1, HangupAction::Destroy, WakeReason::Info),
+      (SIGHUP, HangupAction::Destroy, WakeReason::Signal),
+      (SIGHUP, HangupAction::Keep, WakeReason::Detach),
+      (SIGTERM, HangupAction::Keep, WakeReason::Signal),
+    ];
+    for (signal, on_hangup, expected) in cases {
+      let received = deliver(signal);
+      assert_eq!(received, signal);
+      assert_eq!(
+        wake_reason(received, on_hangup),
+        expected,
+        "signal {} with --on-hangup {:?}",
+        signal,
+        on_hangup
+      );
+    }
+  }
+
+  /// Variables may be declared in any file beside main.tf.
+  #[test]
+  fn template_variables_are_found_in_every_file() {
+    let dir = env::temp_dir().join(format!("fuoco-split-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+      dir.join("main.tf"),
+      "resource \"null_resource\" \"vm\" {}\n",
+    )
+    .unwrap();
+    fs::write(
+      dir.join("variables.tf"),
+      "variable \"region\" {}\nvariable \"name\" {}\n",
+    )
+    .unwrap();
+    let template = dir.join("main.tf");
+    let mut vars = HashMap::from([
+      ("region".to_string(), "fsn1".to_string()),
+      ("name".to_string(), "vm".to_string()),
+    ]);
+    validate_template_variables(&template, &vars).unwrap();
+    vars.insert("image".to_string(), "debian-12".to_string());
+    let err = validate_template_variables(&template, &vars).unwrap_err();
+    assert!(err.to_string().ends_with("variables: image"), "{}", err);
+    fs::remove_dir_all(dir).unwrap();
   }
 }
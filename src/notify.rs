@@ -0,0 +1,169 @@
+//! Webhook notifications (e.g. Slack) when a deployment comes up or goes
+//! away, and the --callback-url POST of the outputs.
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{
+  env,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A webhook should never hold up a deploy, let alone a destroy.
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Shape of the POSTed JSON.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum NotifyFormat {
+  /// `{"text": ...}`, as Slack incoming webhooks expect.
+  Slack,
+  /// The raw event object.
+  Generic,
+}
+
+/// What happened, as reported to the webhook.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Notification<'a> {
+  DeploySucceeded {
+    provider: &'a str,
+    region: &'a str,
+    instance_type: &'a str,
+    public_ip: Option<&'a str>,
+  },
+  DestroyStarted,
+  DestroyFinished {
+    success: bool,
+    error: Option<&'a str>,
+  },
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+  /// Seconds since the Unix epoch.
+  timestamp: u64,
+  deployment: &'a str,
+  /// Who ran fuoco.
+  user: &'a str,
+  #[serde(flatten)]
+  notification: &'a Notification<'a>,
+}
+
+#[derive(Clone)]
+pub struct Notifier {
+  url: String,
+  format: NotifyFormat,
+}
+
+impl Notifier {
+  pub fn new(url: String, format: NotifyFormat) -> Result<Self> {
+    validate_url(&url, "--notify-url")?;
+    Ok(Notifier { url, format })
+  }
+
+  pub fn redacted_url(&self) -> String {
+    redact_url(&self.url)
+  }
+
+  pub fn format(&self) -> NotifyFormat {
+    self.format
+  }
+
+  /// POST `notification`; failures only warn.
+  pub fn send(&self, deployment: &str, notification: &Notification) {
+    if let Err(err) = self.post(deployment, notification) {
+      log::warn!("notification to {} failed: {:#}", self.redacted_url(), err);
+    }
+  }
+
+  fn post(&self, deployment: &str, notification: &Notification) -> Result<()> {
+    let user = env::var("USER")
+      .or_else(|_| env::var("LOGNAME"))
+      .unwrap_or_else(|_| "unknown".to_string());
+    let body = match self.format {
+      NotifyFormat::Slack => {
+        json!({ "text": slack_text(deployment, &user, notification) })
+      }
+      NotifyFormat::Generic => serde_json::to_value(Envelope {
+        timestamp: SystemTime::now()
+          .duration_since(UNIX_EPOCH)
+          .map_or(0, |d| d.as_secs()),
+        deployment,
+        user: &user,
+        notification,
+      })?,
+    };
+    post_json(&self.url, &body)
+  }
+}
+
+pub fn validate_url(url: &str, flag: &str) -> Result<()> {
+  if !url.starts_with("https://") && !url.starts_with("http://") {
+    anyhow::bail!("{} must be an http(s) URL", flag);
+  }
+  Ok(())
+}
+
+/// The URL without path, query or credentials: webhook URLs embed their
+/// secret, so this is all that may be printed.
+pub fn redact_url(url: &str) -> String {
+  let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+  let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+  let host = authority.rsplit('@').next().unwrap_or(authority);
+  format!("{}://{}/[redacted]", scheme, host)
+}
+
+/// POST `body`, giving up after `NOTIFY_TIMEOUT`.
+pub fn post_json(url: &str, body: &Value) -> Result<()> {
+  let agent: ureq::Agent = ureq::Agent::config_builder()
+    .timeout_global(Some(NOTIFY_TIMEOUT))
+    .build()
+    .into();
+  agent
+    .post(url)
+    .header("Content-Type", "application/json")
+    .send(body.to_string())
+    // The error can quote the URL, secret included
+    .map_err(|err| anyhow::anyhow!("{}", redact_error(&err, url)))
+    .context("POST failed")?;
+  Ok(())
+}
+
+fn redact_error(err: &ureq::Error, url: &str) -> String {
+  err.to_string().replace(url, "[redacted]")
+}
+
+fn slack_text(
+  deployment: &str,
+  user: &str,
+  notification: &Notification,
+) -> String {
+  match notification {
+    Notification::DeploySucceeded {
+      provider,
+      region,
+      instance_type,
+      public_ip,
+    } => format!(
+      ":fire: {} deployed `{}`: {} {} in {} at {}",
+      user,
+      deployment,
+      provider,
+      instance_type,
+      region,
+      public_ip.unwrap_or("no public IP")
+    ),
+    Notification::DestroyStarted => {
+      format!(":hourglass: {} is destroying `{}`", user, deployment)
+    }
+    Notification::DestroyFinished { success: true, .. } => {
+      format!(":white_check_mark: `{}` destroyed", deployment)
+    }
+    Notification::DestroyFinished { error, .. } => format!(
+      ":warning: destroying `{}` failed, resources may still exist: {}",
+      deployment,
+      error.unwrap_or("unknown error")
+    ),
+  }
+}
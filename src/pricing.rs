@@ -0,0 +1,274 @@
+//! Rough on-demand prices for the instance types people deploy most often.
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::{collections::HashMap, time::Duration};
+
+/// Hours in an average month, as the providers bill them.
+const HOURS_PER_MONTH: f64 = 730.0;
+
+/// Approximate on-demand USD/hour in each provider's cheapest common region
+/// (us-east-1, us-central1, Hetzner's EU locations, OCI, Linode and
+/// Scaleway list prices). Good enough to tell a cent-per-hour box from a
+/// dollar-per-hour one.
+const PRICES: &[(&str, &str, f64)] = &[
+  ("aws", "t2.nano", 0.0058),
+  ("aws", "t2.micro", 0.0116),
+  ("aws", "t2.small", 0.023),
+  ("aws", "t2.medium", 0.0464),
+  ("aws", "t3.nano", 0.0052),
+  ("aws", "t3.micro", 0.0104),
+  ("aws", "t3.small", 0.0208),
+  ("aws", "t3.medium", 0.0416),
+  ("aws", "t3.large", 0.0832),
+  ("aws", "t3.xlarge", 0.1664),
+  ("aws", "t3a.nano", 0.0047),
+  ("aws", "t3a.micro", 0.0094),
+  ("aws", "t3a.small", 0.0188),
+  ("aws", "t3a.medium", 0.0376),
+  ("aws", "t4g.nano", 0.0042),
+  ("aws", "t4g.micro", 0.0084),
+  ("aws", "t4g.small", 0.0168),
+  ("aws", "t4g.medium", 0.0336),
+  ("aws", "t4g.large", 0.0672),
+  ("aws", "m5.large", 0.096),
+  ("aws", "m5.xlarge", 0.192),
+  ("aws", "m6i.large", 0.096),
+  ("aws", "m6g.medium", 0.0385),
+  ("aws", "m6g.large", 0.077),
+  ("aws", "m7g.medium", 0.0408),
+  ("aws", "m7g.large", 0.0816),
+  ("aws", "c5.large", 0.085),
+  ("aws", "c5.xlarge", 0.17),
+  ("aws", "c6i.large", 0.085),
+  ("aws", "c6g.medium", 0.034),
+  ("aws", "c6g.large", 0.068),
+  ("aws", "c7g.medium", 0.0363),
+  ("aws", "c7g.large", 0.0725),
+  ("aws", "r5.large", 0.126),
+  ("aws", "r6i.large", 0.126),
+  ("aws", "r6g.large", 0.1008),
+  ("gcp", "f1-micro", 0.0076),
+  ("gcp", "g1-small", 0.0257),
+  ("gcp", "e2-micro", 0.0084),
+  ("gcp", "e2-small", 0.0168),
+  ("gcp", "e2-medium", 0.0335),
+  ("gcp", "e2-standard-2", 0.067),
+  ("gcp", "e2-standard-4", 0.134),
+  ("gcp", "e2-standard-8", 0.268),
+  ("gcp", "n1-standard-1", 0.0475),
+  ("gcp", "n1-standard-2", 0.095),
+  ("gcp", "n2-standard-2", 0.0971),
+  ("gcp", "n2-standard-4", 0.1942),
+  ("gcp", "n2d-standard-2", 0.0845),
+  ("gcp", "t2d-standard-1", 0.0422),
+  ("gcp", "t2a-standard-1", 0.0385),
+  ("gcp", "t2a-standard-2", 0.077),
+  ("hetzner", "cx11", 0.0066),
+  ("hetzner", "cx21", 0.0095),
+  ("hetzner", "cx22", 0.0066),
+  ("hetzner", "cx31", 0.0168),
+  ("hetzner", "cx32", 0.0113),
+  ("hetzner", "cx42", 0.0273),
+  ("hetzner", "cx52", 0.054),
+  ("hetzner", "cpx11", 0.008),
+  ("hetzner", "cpx21", 0.0136),
+  ("hetzner", "cpx31", 0.0248),
+  ("hetzner", "cax11", 0.0063),
+  ("hetzner", "cax21", 0.011),
+  ("hetzner", "cax31", 0.0218),
+  ("hetzner", "cax41", 0.0431),
+  // Always Free shape
+  ("oracle", "VM.Standard.E2.1.Micro", 0.0),
+  // The template sizes flex shapes at 1 OCPU and 6 GB
+  ("oracle", "VM.Standard.A1.Flex", 0.019),
+  ("oracle", "VM.Standard.E4.Flex", 0.034),
+  ("oracle", "VM.Standard.E5.Flex", 0.039),
+  ("linode", "g6-nanode-1", 0.0075),
+  ("linode", "g6-standard-1", 0.018),
+  ("linode", "g6-standard-2", 0.036),
+  ("linode", "g6-standard-4", 0.072),
+  ("linode", "g6-dedicated-2", 0.054),
+  ("linode", "g6-dedicated-4", 0.108),
+  ("scaleway", "DEV1-S", 0.0095),
+  ("scaleway", "DEV1-M", 0.021),
+  ("scaleway", "DEV1-L", 0.045),
+  ("scaleway", "PLAY2-NANO", 0.03),
+  ("scaleway", "COPARM1-2C-8G", 0.045),
+];
+
+/// How much more than in the regions `PRICES` is for the same instance
+/// costs elsewhere, roughly; 1 for regions not listed. Oracle and Linode
+/// (in the regions fuoco knows) and Scaleway charge the same everywhere.
+const REGION_FACTORS: &[(&str, &str, f64)] = &[
+  ("aws", "us-west-1", 1.2),
+  ("aws", "ca-central-1", 1.1),
+  ("aws", "eu-west-1", 1.1),
+  ("aws", "eu-west-2", 1.15),
+  ("aws", "eu-west-3", 1.16),
+  ("aws", "eu-central-1", 1.15),
+  ("aws", "eu-north-1", 1.05),
+  ("aws", "ap-south-1", 1.05),
+  ("aws", "ap-northeast-1", 1.3),
+  ("aws", "ap-northeast-2", 1.25),
+  ("aws", "ap-northeast-3", 1.3),
+  ("aws", "ap-southeast-1", 1.25),
+  ("aws", "ap-southeast-2", 1.25),
+  ("aws", "sa-east-1", 1.6),
+  ("gcp", "us-west2", 1.2),
+  ("gcp", "us-west3", 1.2),
+  ("gcp", "us-west4", 1.13),
+  ("gcp", "northamerica-northeast1", 1.1),
+  ("gcp", "southamerica-east1", 1.59),
+  ("gcp", "europe-west1", 1.1),
+  ("gcp", "europe-west2", 1.21),
+  ("gcp", "europe-west3", 1.21),
+  ("gcp", "europe-west4", 1.1),
+  ("gcp", "europe-west6", 1.33),
+  ("gcp", "europe-west8", 1.15),
+  ("gcp", "europe-west9", 1.15),
+  ("gcp", "europe-north1", 1.1),
+  ("gcp", "europe-southwest1", 1.18),
+  ("gcp", "asia-east1", 1.16),
+  ("gcp", "asia-east2", 1.4),
+  ("gcp", "asia-northeast1", 1.28),
+  ("gcp", "asia-northeast2", 1.28),
+  ("gcp", "asia-northeast3", 1.28),
+  ("gcp", "asia-south1", 1.2),
+  ("gcp", "asia-south2", 1.2),
+  ("gcp", "asia-southeast1", 1.23),
+  ("gcp", "asia-southeast2", 1.3),
+  ("gcp", "australia-southeast1", 1.42),
+  ("gcp", "australia-southeast2", 1.42),
+  ("gcp", "me-central1", 1.3),
+  ("gcp", "me-west1", 1.3),
+  ("hetzner", "ash", 1.1),
+  ("hetzner", "hil", 1.1),
+];
+
+/// Hetzner bills in euros.
+const EUR_TO_USD: f64 = 1.1;
+
+const HETZNER_PRICING_URL: &str = "https://api.hetzner.cloud/v1/pricing";
+const LIVE_PRICING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Approximate on-demand USD/hour for `instance_type`, if it is cataloged.
+pub fn hourly_usd(provider: &str, instance_type: &str) -> Option<f64> {
+  PRICES
+    .iter()
+    .find(|(p, t, _)| *p == provider && *t == instance_type)
+    .map(|(_, _, price)| *price)
+}
+
+/// `hourly_usd` adjusted for `region` (a GCP zone counts as its region).
+pub fn regional_hourly_usd(
+  provider: &str,
+  instance_type: &str,
+  region: &str,
+) -> Option<f64> {
+  let region = match provider {
+    "gcp" => crate::split_gcp_location(region).0,
+    _ => region,
+  };
+  let factor = REGION_FACTORS
+    .iter()
+    .find(|(p, r, _)| *p == provider && *r == region)
+    .map_or(1.0, |(_, _, factor)| *factor);
+  Some(hourly_usd(provider, instance_type)? * factor)
+}
+
+/// The USD/hour of `instance_type` in each of `regions` that has a price:
+/// the provider's live prices where fuoco can fetch them (Hetzner, with
+/// HCLOUD_TOKEN), the bundled estimates otherwise. Failing to fetch them
+/// only warns.
+pub fn compare_regions(
+  provider: &str,
+  instance_type: &str,
+  regions: &[&str],
+) -> Vec<(String, f64)> {
+  let live = match provider {
+    "hetzner" => match hetzner_prices(instance_type) {
+      Ok(prices) => Some(prices),
+      Err(err) => {
+        log::warn!(
+          "could not fetch Hetzner's prices ({:#}); using fuoco's bundled \
+           estimates",
+          err
+        );
+        None
+      }
+    },
+    _ => None,
+  };
+  regions
+    .iter()
+    .filter_map(|region| {
+      let price = match &live {
+        Some(live) => live.get(*region).copied(),
+        None => regional_hourly_usd(provider, instance_type, region),
+      };
+      Some((region.to_string(), price?))
+    })
+    .collect()
+}
+
+/// Hourly gross price of `server_type` in each location, from the Hetzner
+/// Cloud API.
+fn hetzner_prices(server_type: &str) -> Result<HashMap<String, f64>> {
+  let token = crate::hetzner_token()?;
+  let agent: ureq::Agent = ureq::Agent::config_builder()
+    .timeout_global(Some(LIVE_PRICING_TIMEOUT))
+    .build()
+    .into();
+  let body = agent
+    .get(HETZNER_PRICING_URL)
+    .header("Authorization", format!("Bearer {}", token))
+    .call()
+    .context("request failed")?
+    .body_mut()
+    .read_to_string()
+    .context("failed to read the response")?;
+  let pricing: Value =
+    serde_json::from_str(&body).context("unexpected response")?;
+  let prices = pricing["pricing"]["server_types"]
+    .as_array()
+    .context("unexpected response")?
+    .iter()
+    .find(|server| server["name"] == server_type)
+    .with_context(|| format!("no price for server type {}", server_type))?
+    ["prices"]
+    .as_array()
+    .context("unexpected response")?
+    .iter()
+    .filter_map(|price| {
+      let location = price["location"].as_str()?;
+      let hourly: f64 =
+        price["price_hourly"]["gross"].as_str()?.parse().ok()?;
+      Some((location.to_string(), hourly * EUR_TO_USD))
+    })
+    .collect();
+  Ok(prices)
+}
+
+/// Render an estimate as `~$X/hr (~$Y/month)`, or `unknown`.
+pub fn describe(hourly: Option<f64>) -> String {
+  match hourly {
+    Some(hourly) => format!(
+      "~${:.4}/hr (~${:.2}/month)",
+      hourly,
+      hourly * HOURS_PER_MONTH
+    ),
+    None => "unknown".to_string(),
+  }
+}
+
+/// What `elapsed` at `hourly` comes to, as `~$X`. Providers bill by the
+/// second or the hour depending on the instance, so it is only a hint.
+pub fn accrued(hourly: f64, elapsed: Duration) -> String {
+  let cost = hourly * elapsed.as_secs_f64() / 3600.0;
+  if cost < 0.01 {
+    "under $0.01".to_string()
+  } else {
+    format!("~${:.2}", cost)
+  }
+}
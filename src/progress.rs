@@ -0,0 +1,136 @@
+//! Shows that Terraform is still working while fuoco has nothing else to
+//! say: a spinner with the elapsed time when stderr is a terminal, and a
+//! line every `LOG_INTERVAL` otherwise, so CI logs show the run is alive.
+//! atar does not pass on Terraform's own output, so progress is per phase,
+//! not per resource.
+
+use std::{
+  io::{self, IsTerminal, Write},
+  sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Mutex, Once,
+  },
+  thread,
+  time::{Duration, Instant},
+};
+
+const SPIN_INTERVAL: Duration = Duration::from_millis(100);
+
+const LOG_INTERVAL: Duration = Duration::from_secs(15);
+
+const SPINNER: &[char] = &['|', '/', '-', '\\'];
+
+/// Cleared by `--debug`, which shows Terraform's own output instead.
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+static PHASES: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+
+/// Whether the spinner line is on screen.
+static DRAWN: AtomicBool = AtomicBool::new(false);
+
+static TICKER: Once = Once::new();
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+struct Entry {
+  id: u64,
+  label: &'static str,
+  started: Instant,
+  reported: Instant,
+}
+
+/// Shows progress for its phase until dropped.
+pub struct Phase {
+  id: u64,
+}
+
+impl Drop for Phase {
+  fn drop(&mut self) {
+    let mut phases = PHASES.lock().unwrap_or_else(|e| e.into_inner());
+    phases.retain(|entry| entry.id != self.id);
+    if phases.is_empty() {
+      clear();
+    }
+  }
+}
+
+pub fn disable() {
+  ENABLED.store(false, Ordering::Relaxed);
+}
+
+/// Show progress for `label` (e.g. `terraform apply`) while the returned
+/// guard lives. Several phases at once share the line.
+pub fn phase(label: &'static str) -> Option<Phase> {
+  if !ENABLED.load(Ordering::Relaxed) {
+    return None;
+  }
+  let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+  let now = Instant::now();
+  PHASES
+    .lock()
+    .unwrap_or_else(|e| e.into_inner())
+    .push(Entry {
+      id,
+      label,
+      started: now,
+      reported: now,
+    });
+  TICKER.call_once(|| {
+    thread::spawn(tick);
+  });
+  Some(Phase { id })
+}
+
+/// Take the spinner off the screen so a message can be printed; the next
+/// tick draws it again below.
+pub fn clear() {
+  if DRAWN.swap(false, Ordering::Relaxed) {
+    eprint!("\r\x1b[2K");
+    let _ = io::stderr().flush();
+  }
+}
+
+fn tick() {
+  let terminal = io::stderr().is_terminal();
+  let mut frame = 0;
+  loop {
+    thread::sleep(if terminal {
+      SPIN_INTERVAL
+    } else {
+      Duration::from_secs(1)
+    });
+    let mut phases = PHASES.lock().unwrap_or_else(|e| e.into_inner());
+    if phases.is_empty() {
+      continue;
+    }
+    if terminal {
+      let line: Vec<_> = phases
+        .iter()
+        .map(|entry| {
+          format!("{}... {}", entry.label, minutes(entry.started.elapsed()))
+        })
+        .collect();
+      eprint!("\r\x1b[2K{} {}", SPINNER[frame], line.join(", "));
+      let _ = io::stderr().flush();
+      DRAWN.store(true, Ordering::Relaxed);
+      frame = (frame + 1) % SPINNER.len();
+      continue;
+    }
+    for entry in phases.iter_mut() {
+      if entry.reported.elapsed() >= LOG_INTERVAL {
+        status!(
+          "Still waiting for {}... ({} elapsed)",
+          entry.label,
+          crate::format_elapsed(entry.started.elapsed())
+        );
+        entry.reported = Instant::now();
+      }
+    }
+  }
+}
+
+/// `01:42`, hours folded into the minutes.
+fn minutes(elapsed: Duration) -> String {
+  let secs = elapsed.as_secs();
+  format!("{:02}:{:02}", secs / 60, secs % 60)
+}
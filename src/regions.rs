@@ -0,0 +1,347 @@
+//! The regions fuoco picks from and checks against: the list built into
+//! fuoco (`known_regions`), or a live one `fuoco regions --refresh` fetched
+//! from the provider and cached, which wins while it is fresh.
+
+use crate::{cache_dir, known_regions, provider_str, Provider};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+  collections::HashMap,
+  env, fs,
+  path::{Path, PathBuf},
+  process::{Command, Stdio},
+  sync::{Mutex, OnceLock},
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How long a fetched list is used before fuoco goes back to its own.
+pub const CACHE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+const HETZNER_LOCATIONS_URL: &str = "https://api.hetzner.cloud/v1/locations";
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where a provider's region list came from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+  BuiltIn,
+  Cached,
+}
+
+impl std::fmt::Display for Source {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    f.write_str(match self {
+      Source::BuiltIn => "built-in",
+      Source::Cached => "cached",
+    })
+  }
+}
+
+/// Each provider's merged list, or `None` for the built-in one.
+type Lists = HashMap<String, Option<&'static [&'static str]>>;
+
+#[derive(Serialize, Deserialize)]
+struct Cache {
+  /// Seconds since the Unix epoch.
+  fetched_at: u64,
+  regions: Vec<String>,
+}
+
+/// The regions to pick from for `provider`, and where they came from. Read
+/// once per run; the cache only changes through `refresh`.
+pub fn available(provider: &Provider) -> (&'static [&'static str], Source) {
+  static LISTS: OnceLock<Mutex<Lists>> = OnceLock::new();
+  let built_in = known_regions(provider);
+  let mut lists = LISTS
+    .get_or_init(Default::default)
+    .lock()
+    .unwrap_or_else(|e| e.into_inner());
+  let list = *lists
+    .entry(provider_str(provider).to_string())
+    .or_insert_with(|| {
+      // Leaked once per provider and run, to hand out like the built-in
+      let merged: Vec<&'static str> = merge(built_in, &cached(provider)?)
+        .into_iter()
+        .map(|region| &*Box::leak(region.into_boxed_str()))
+        .collect();
+      Some(Box::leak(merged.into_boxed_slice()))
+    });
+  match list {
+    Some(list) => (list, Source::Cached),
+    None => (built_in, Source::BuiltIn),
+  }
+}
+
+/// Combine the built-in list with a live one. The live list decides which
+/// regions there are, since it knows of new regions and leaves out those
+/// the account cannot use; the built-in order comes first, with new
+/// regions after it in name order, so a seed keeps picking the same region
+/// as long as the list does not change.
+pub fn merge(built_in: &[&str], live: &[String]) -> Vec<String> {
+  let mut merged: Vec<String> = built_in
+    .iter()
+    .filter(|region| live.iter().any(|live| live == *region))
+    .map(|region| region.to_string())
+    .collect();
+  let mut added: Vec<String> = live
+    .iter()
+    .filter(|region| !built_in.contains(&region.as_str()))
+    .cloned()
+    .collect();
+  added.sort();
+  added.dedup();
+  merged.extend(added);
+  merged
+}
+
+/// Fetch the regions of `provider` from its API with the credentials
+/// already set up, and cache them for `available`.
+pub fn refresh(provider: &Provider) -> Result<Vec<String>> {
+  let regions = fetch(provider)?;
+  if regions.is_empty() {
+    anyhow::bail!("the provider listed no regions");
+  }
+  let path = cache_path(provider).context("no cache directory (HOME unset)")?;
+  let cache = Cache {
+    fetched_at: now(),
+    regions: regions.clone(),
+  };
+  if let Some(dir) = path.parent() {
+    fs::create_dir_all(dir)
+      .with_context(|| format!("Failed to create {:?}", dir))?;
+  }
+  fs::write(&path, serde_json::to_string_pretty(&cache)?)
+    .with_context(|| format!("Failed to write {:?}", path))?;
+  Ok(regions)
+}
+
+/// Whether `refresh` can fetch live regions for `provider`.
+pub fn refreshable(provider: &Provider) -> bool {
+  matches!(provider, Provider::AWS | Provider::GCP | Provider::Hetzner)
+}
+
+/// The availability zones of the AWS `region` open to the account, in
+/// name order, through the AWS CLI. Local and Wavelength Zones are left
+/// out.
+pub fn aws_availability_zones(region: &str) -> Result<Vec<String>> {
+  let mut command = Command::new("aws");
+  command.args([
+    "ec2",
+    "describe-availability-zones",
+    "--region",
+    region,
+    "--filters",
+    "Name=zone-type,Values=availability-zone",
+    "Name=state,Values=available",
+    "--query",
+    "AvailabilityZones[].ZoneName",
+    "--output",
+    "text",
+  ]);
+  let mut zones = run(command)?;
+  zones.sort();
+  Ok(zones)
+}
+
+/// `$XDG_CACHE_HOME/fuoco/regions-<provider>.json`.
+fn cache_path(provider: &Provider) -> Option<PathBuf> {
+  let name = format!("regions-{}.json", provider_str(provider));
+  cache_dir().map(|dir| dir.join(name))
+}
+
+/// The cached live list, unless it is missing, unreadable or stale.
+fn cached(provider: &Provider) -> Option<Vec<String>> {
+  read_cache(&cache_path(provider)?, now())
+}
+
+/// The live list cached at `path`, unless it is missing, unreadable or was
+/// fetched more than `CACHE_TTL` before `now`.
+fn read_cache(path: &Path, now: u64) -> Option<Vec<String>> {
+  let content = fs::read_to_string(path).ok()?;
+  let cache: Cache = match serde_json::from_str(&content) {
+    Ok(cache) => cache,
+    Err(err) => {
+      log::warn!("ignoring {:?}: {}", path, err);
+      return None;
+    }
+  };
+  let age = Duration::from_secs(now.saturating_sub(cache.fetched_at));
+  if age > CACHE_TTL {
+    log::debug!(
+      "{:?} is older than {} days; using the built-in regions",
+      path,
+      CACHE_TTL.as_secs() / 86400
+    );
+    return None;
+  }
+  Some(cache.regions).filter(|regions| !regions.is_empty())
+}
+
+fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |since| since.as_secs())
+}
+
+fn fetch(provider: &Provider) -> Result<Vec<String>> {
+  match provider {
+    Provider::AWS => {
+      let mut command = Command::new("aws");
+      command.args([
+        "ec2",
+        "describe-regions",
+        "--query",
+        "Regions[].RegionName",
+        "--output",
+        "text",
+      ]);
+      // Any region answers; the CLI only needs to be told one
+      if env::var_os("AWS_REGION").is_none()
+        && env::var_os("AWS_DEFAULT_REGION").is_none()
+      {
+        command.args(["--region", "us-east-1"]);
+      }
+      run(command)
+    }
+    Provider::GCP => {
+      let mut command = Command::new("gcloud");
+      command.args(["compute", "regions", "list", "--format", "value(name)"]);
+      run(command)
+    }
+    Provider::Hetzner => hetzner_locations(),
+    _ => anyhow::bail!(
+      "no live region list for {:?}; fuoco uses its built-in one",
+      provider
+    ),
+  }
+}
+
+/// The whitespace-separated words `command` prints.
+fn run(mut command: Command) -> Result<Vec<String>> {
+  let program = command.get_program().to_string_lossy().into_owned();
+  let output = command
+    .stdin(Stdio::null())
+    .output()
+    .with_context(|| format!("could not run {} (is it installed?)", program))?;
+  if !output.status.success() {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    anyhow::bail!(
+      "{} failed: {}",
+      program,
+      stderr.lines().find(|l| !l.trim().is_empty()).unwrap_or("")
+    );
+  }
+  Ok(
+    String::from_utf8_lossy(&output.stdout)
+      .split_whitespace()
+      .map(str::to_string)
+      .collect(),
+  )
+}
+
+fn hetzner_locations() -> Result<Vec<String>> {
+  let token = crate::hetzner_token()?;
+  let agent: ureq::Agent = ureq::Agent::config_builder()
+    .timeout_global(Some(FETCH_TIMEOUT))
+    .build()
+    .into();
+  let body = agent
+    .get(HETZNER_LOCATIONS_URL)
+    .header("Authorization", format!("Bearer {}", token))
+    .call()
+    .context("request failed")?
+    .body_mut()
+    .read_to_string()
+    .context("failed to read the response")?;
+  let locations: Value =
+    serde_json::from_str(&body).context("unexpected response")?;
+  Ok(
+    locations["locations"]
+      .as_array()
+      .context("unexpected response")?
+      .iter()
+      .filter_map(|location| Some(location["name"].as_str()?.to_string()))
+      .collect(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn strings(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+  }
+
+  #[test]
+  fn merge_keeps_the_built_in_order() {
+    let built_in = ["b-1", "a-1", "c-1"];
+    let live = strings(&["c-1", "a-1", "b-1"]);
+    assert_eq!(merge(&built_in, &live), strings(&["b-1", "a-1", "c-1"]));
+  }
+
+  #[test]
+  fn merge_follows_the_live_list() {
+    let built_in = ["b-1", "gone-1", "a-1"];
+    let live = strings(&["z-new", "a-1", "m-new", "b-1", "m-new"]);
+    assert_eq!(
+      merge(&built_in, &live),
+      strings(&["b-1", "a-1", "m-new", "z-new"])
+    );
+    assert!(merge(&built_in, &[]).is_empty());
+    assert_eq!(
+      merge(&[], &live),
+      strings(&["a-1", "b-1", "m-new", "z-new"])
+    );
+  }
+
+  /// `content` as a cache file, in a directory of its own.
+  fn cache_file(name: &str, content: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!(
+      "fuoco-regions-{}-{}",
+      name,
+      std::process::id()
+    ));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("regions-aws.json");
+    fs::write(&path, content).unwrap();
+    path
+  }
+
+  fn cache_json(fetched_at: u64, regions: &[&str]) -> String {
+    serde_json::to_string(&Cache {
+      fetched_at,
+      regions: strings(regions),
+    })
+    .unwrap()
+  }
+
+  #[test]
+  fn fresh_cache_is_read() {
+    let now = now();
+    let path = cache_file("fresh", &cache_json(now - 60, &["eu-west-1"]));
+    assert_eq!(read_cache(&path, now), Some(strings(&["eu-west-1"])));
+    // Still fresh on the last day
+    let ttl = CACHE_TTL.as_secs();
+    assert!(read_cache(&path, now - 60 + ttl).is_some());
+    let _ = fs::remove_dir_all(path.parent().unwrap());
+  }
+
+  #[test]
+  fn stale_or_broken_cache_falls_back() {
+    let now = now();
+    let stale_at = now - CACHE_TTL.as_secs() - 1;
+    for (name, content) in [
+      ("stale", cache_json(stale_at, &["eu-west-1"])),
+      ("empty", cache_json(now, &[])),
+      ("corrupt", "{\"fetched_at\": ".to_string()),
+      ("shape", "[\"eu-west-1\"]".to_string()),
+    ] {
+      let path = cache_file(name, &content);
+      assert_eq!(read_cache(&path, now), None, "{}", name);
+      let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+    let missing = env::temp_dir().join("fuoco-regions-missing.json");
+    assert_eq!(read_cache(&missing, now), None);
+  }
+}
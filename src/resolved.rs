@@ -0,0 +1,187 @@
+//! `--params-format json`: the params as tooling reads them, with what
+//! fuoco resolves filled in (the region it picked, the default instance
+//! type, the SSH rule) instead of the placeholders the table shows.
+
+use crate::{
+  resolve_inbound_rules, DeployParams, InboundRule, UndeployParams,
+  DEFAULT_SSH_PORT,
+};
+use serde::Serialize;
+use std::{
+  collections::{BTreeMap, HashMap},
+  path::{Path, PathBuf},
+  time::{Duration, Instant},
+};
+
+#[derive(Serialize)]
+pub struct Deploy<'a> {
+  name: &'a str,
+  provider: &'a str,
+  region: &'a str,
+  /// GCP only: the zone in `region`.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  zone: Option<&'a str>,
+  /// As a string, like in deployment.json.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  region_seed: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  region_rtt_ms: Option<u64>,
+  instance_type: &'a str,
+  arch: &'a str,
+  template_path: &'a Path,
+  script_path: &'a [PathBuf],
+  #[serde(skip_serializing_if = "Option::is_none")]
+  script_url: Option<&'a str>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  cloud_config: Option<&'a Path>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  cloud_init: Option<&'a Path>,
+  /// Names only: the values are often secrets.
+  env: Vec<&'a str>,
+  user_data_bytes: usize,
+  user_data_gzip: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  availability_zone: Option<&'a str>,
+  inbound_rules: Vec<InboundRule>,
+  ssh_port: u16,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  key_name: Option<&'a str>,
+  ssh_public_key_path: &'a [String],
+  public_ip: bool,
+  static_ip: bool,
+  spot: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  spot_max_price: Option<&'a str>,
+  /// GiB; absent for the template default.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  disk_size: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  extra_volume: Option<ExtraVolume<'a>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  image: Option<&'a str>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  user: Option<&'a str>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  hostname: Option<&'a str>,
+  tags: &'a BTreeMap<String, String>,
+  metadata: Vec<&'a str>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  estimated_hourly_usd: Option<f64>,
+  retries: u32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  deploy_timeout_secs: Option<u64>,
+  destroy_timeout_secs: u64,
+  /// What is left of --timeout.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  timeout_secs: Option<u64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  idle_timeout_secs: Option<u64>,
+  keep: bool,
+  /// Keys only, as --var values may be secrets.
+  extra_vars: Vec<&'a str>,
+}
+
+#[derive(Serialize)]
+struct ExtraVolume<'a> {
+  size: u32,
+  mount: &'a str,
+}
+
+#[derive(Serialize)]
+pub struct Undeploy<'a> {
+  name: &'a str,
+  provider: &'a str,
+  region: &'a str,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  zone: Option<&'a str>,
+  instance_type: &'a str,
+  arch: &'a str,
+  template_path: &'a Path,
+  force: bool,
+  retries: u32,
+  destroy_timeout_secs: u64,
+  extra_vars: Vec<&'a str>,
+}
+
+/// `params` with the values `hash_map`, the Terraform variables from its
+/// `to_atar_map`, resolved.
+pub fn deploy<'a>(
+  params: &'a DeployParams,
+  hash_map: &'a HashMap<String, String>,
+) -> Deploy<'a> {
+  let user_data = params.encoded_user_data();
+  Deploy {
+    name: &params.name,
+    provider: crate::provider_str(&params.provider),
+    region: &hash_map["region"],
+    zone: hash_map.get("zone").map(String::as_str),
+    region_seed: params.region_seed.map(|seed| seed.to_string()),
+    region_rtt_ms: params.region_rtt.map(|rtt| rtt.as_millis() as u64),
+    instance_type: &hash_map["instance_type"],
+    arch: &hash_map["arch"],
+    template_path: &params.template_path,
+    script_path: &params.script_path,
+    script_url: params.script_url.as_deref(),
+    cloud_config: params.cloud_config_path.as_deref(),
+    cloud_init: params.cloud_init_path.as_deref(),
+    env: params.env.iter().map(|var| var.key.as_str()).collect(),
+    user_data_bytes: user_data.size,
+    user_data_gzip: user_data.gzip,
+    availability_zone: hash_map.get("availability_zone").map(String::as_str),
+    inbound_rules: resolve_inbound_rules(
+      params.inbound_rules.as_ref(),
+      params.ssh_port,
+    ),
+    ssh_port: params.ssh_port.unwrap_or(DEFAULT_SSH_PORT),
+    key_name: params.key_name.as_deref(),
+    ssh_public_key_path: &params.ssh_public_key_path,
+    public_ip: !params.no_public_ip,
+    static_ip: params.static_ip,
+    spot: params.spot,
+    spot_max_price: params.spot_max_price.as_deref(),
+    disk_size: params.disk_size,
+    extra_volume: params.extra_volume.map(|size| ExtraVolume {
+      size,
+      mount: &params.extra_volume_mount,
+    }),
+    image: params.image.as_deref(),
+    user: params.user.as_deref(),
+    hostname: params.hostname.as_deref(),
+    tags: &params.tags,
+    metadata: params.metadata.keys().map(String::as_str).collect(),
+    estimated_hourly_usd: params.hourly_usd(),
+    retries: params.retries,
+    deploy_timeout_secs: params.deploy_timeout.map(|t| t.as_secs()),
+    destroy_timeout_secs: params.destroy_timeout.as_secs(),
+    timeout_secs: params.deadline.map(|deadline| {
+      deadline.saturating_duration_since(Instant::now()).as_secs()
+    }),
+    idle_timeout_secs: params.idle_timeout.as_ref().map(Duration::as_secs),
+    keep: params.keep,
+    extra_vars: extra_var_keys(&params.extra_vars),
+  }
+}
+
+/// `params` resolved by `hash_map`, its `to_atar_map`.
+pub fn undeploy<'a>(
+  params: &'a UndeployParams,
+  hash_map: &'a HashMap<String, String>,
+) -> Undeploy<'a> {
+  Undeploy {
+    name: &params.name,
+    provider: crate::provider_str(&params.provider),
+    region: &hash_map["region"],
+    zone: hash_map.get("zone").map(String::as_str),
+    instance_type: &hash_map["instance_type"],
+    arch: &hash_map["arch"],
+    template_path: &params.template_path,
+    force: params.force,
+    retries: params.retries,
+    destroy_timeout_secs: params.destroy_timeout.as_secs(),
+    extra_vars: extra_var_keys(&params.extra_vars),
+  }
+}
+
+fn extra_var_keys(vars: &[crate::TemplateVar]) -> Vec<&str> {
+  vars.iter().map(|var| var.key.as_str()).collect()
+}
@@ -0,0 +1,180 @@
+//! Retries for Terraform runs that failed because of the cloud API rather
+//! than the deployment: throttling, timeouts, conflicts and 5xx responses.
+//! Failures are told apart by the error and what Terraform wrote on stderr,
+//! since atar's error alone only says Terraform failed.
+
+use crate::{
+  capture,
+  watchdog::{KillHandle, KILL_CHECK_INTERVAL},
+};
+use anyhow::Result;
+use rand::Rng;
+use std::{
+  thread,
+  time::{Duration, Instant},
+};
+
+/// Wait before the first retry; doubled for each one after it.
+const BASE_DELAY: Duration = Duration::from_secs(5);
+
+/// Errors that will fail the same way however often they are retried.
+/// Checked first: "RequestLimitExceeded" is throttling, but
+/// "VcpuLimitExceeded" is a quota.
+const PERMANENT_PATTERNS: &[&str] = &[
+  "unauthorized",
+  "unauthenticated",
+  "authfailure",
+  "authentication failed",
+  "invalid credentials",
+  "forbidden",
+  "permission denied",
+  "access denied",
+  "accessdenied",
+  "quota",
+  "vcpulimitexceeded",
+  "instancelimitexceeded",
+  "resource_limit_exceeded",
+  "invalid instance type",
+  "invalidinstancetype",
+  "invalidparameter",
+  "server type not found",
+];
+
+const TRANSIENT_PATTERNS: &[&str] = &[
+  "throttl",
+  "rate exceeded",
+  "rate limit",
+  "ratelimit",
+  "requestlimitexceeded",
+  "too many requests",
+  "429",
+  "timeout",
+  "timed out",
+  "conflict",
+  "409",
+  "500",
+  "502",
+  "503",
+  "504",
+  "internal server error",
+  "bad gateway",
+  "service unavailable",
+  "connection reset",
+  "try again",
+];
+
+/// The provider has no room for the instance type where it was asked for;
+/// another availability zone may.
+const CAPACITY_PATTERNS: &[&str] = &[
+  "insufficientinstancecapacity",
+  "insufficient capacity",
+  "is not supported in your requested availability zone",
+];
+
+/// Whether `err` says the zone is out of capacity for the instance type,
+/// which --az-fallback answers by trying another zone.
+pub fn is_capacity_error(err: &anyhow::Error) -> bool {
+  let text = format!("{:#}", err).to_lowercase();
+  CAPACITY_PATTERNS.iter().any(|p| text.contains(p))
+}
+
+/// What a failure says about trying again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+  /// Throttling, timeouts, conflicts, 5xx: likely to pass on a retry.
+  Transient,
+  /// Credentials, permissions, quotas, bad parameters: never will.
+  Permanent,
+  /// Nothing recognised; not retried either, to avoid looping on it.
+  Unknown,
+}
+
+impl Failure {
+  /// Classify `err` together with `output`, what Terraform wrote while
+  /// failing.
+  pub fn classify(err: &anyhow::Error, output: &str) -> Self {
+    let text = format!("{:#}\n{}", err, output).to_lowercase();
+    if PERMANENT_PATTERNS.iter().any(|p| text.contains(p)) {
+      Failure::Permanent
+    } else if TRANSIENT_PATTERNS.iter().any(|p| text.contains(p)) {
+      Failure::Transient
+    } else {
+      Failure::Unknown
+    }
+  }
+}
+
+/// Run `f`, retrying transient failures up to `retries` times with
+/// exponential backoff and jitter. `what` names the step in the log. Once
+/// `kill` is used no attempt starts and the backoff is cut short, failing
+/// with `watchdog::Killed`.
+pub fn with_retries<T>(
+  what: &'static str,
+  retries: u32,
+  kill: &KillHandle,
+  f: impl FnMut() -> Result<T>,
+) -> Result<T> {
+  retry_when(
+    what,
+    retries,
+    kill,
+    |failure| failure == Failure::Transient,
+    f,
+  )
+}
+
+/// `with_retries` for failures `should_retry` picks, transient or not.
+pub fn retry_when<T>(
+  what: &'static str,
+  retries: u32,
+  kill: &KillHandle,
+  should_retry: impl Fn(Failure) -> bool,
+  mut f: impl FnMut() -> Result<T>,
+) -> Result<T> {
+  let mut attempt = 0;
+  loop {
+    kill.check(what)?;
+    let mark = capture::mark();
+    let result = f();
+    let failure = match &result {
+      Ok(_) => None,
+      Err(err) => Some(Failure::classify(err, &capture::since(mark))),
+    };
+    match (result, failure) {
+      (Err(err), Some(failure))
+        if attempt < retries && should_retry(failure) =>
+      {
+        let delay = BASE_DELAY * 2u32.pow(attempt)
+          + Duration::from_millis(rand::thread_rng().gen_range(0..1000));
+        attempt += 1;
+        log::warn!(
+          "{} failed, retrying in {}s (retry {}/{}): {:#}",
+          what,
+          delay.as_secs(),
+          attempt,
+          retries,
+          err
+        );
+        sleep_unless_killed(delay, kill);
+      }
+      (result, failure) => {
+        if let Some(failure) = failure.filter(|_| attempt < retries) {
+          log::debug!("{} failed ({:?}), not retrying", what, failure);
+        }
+        return result;
+      }
+    }
+  }
+}
+
+/// Sleep for `delay`, or until `kill` is used.
+fn sleep_unless_killed(delay: Duration, kill: &KillHandle) {
+  let until = Instant::now() + delay;
+  while !kill.killed() {
+    let left = until.saturating_duration_since(Instant::now());
+    if left.is_zero() {
+      return;
+    }
+    thread::sleep(left.min(KILL_CHECK_INTERVAL));
+  }
+}
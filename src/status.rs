@@ -0,0 +1,273 @@
+//! `fuoco status`: what one deployment looks like right now, from its
+//! Terraform state, from the provider's own CLI and from the network.
+
+use crate::{
+  format_elapsed, instance_ip, provider_str, teardown, workspace,
+  workspace_dir, Provider,
+};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde_json::Value;
+use std::{
+  fs,
+  net::{SocketAddr, TcpStream},
+  path::Path,
+  process::{Command, Stdio},
+  time::{Duration, SystemTime},
+};
+
+/// The instance resource of each built-in template, and the attribute its
+/// state is recorded in.
+const INSTANCES: &[(&str, &str)] = &[
+  ("aws_instance", "instance_state"),
+  ("google_compute_instance", "current_status"),
+  ("hcloud_server", "status"),
+  ("oci_core_instance", "state"),
+  ("linode_instance", "status"),
+  ("scaleway_instance_server", "state"),
+];
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Find the deployment called `name` (narrowed down by provider and region
+/// when several match) and print its state.
+pub fn run(
+  name: &str,
+  provider: Option<&Provider>,
+  region: Option<&str>,
+  ssh_port: u16,
+) -> Result<()> {
+  let mut matches: Vec<_> = workspace::records()
+    .into_iter()
+    .filter(|(_, record)| {
+      record.name == name
+        && provider.is_none_or(|p| record.provider == provider_str(p))
+        && region.is_none_or(|r| record.region == r)
+    })
+    .collect();
+  // Records outlive their deployments: prefer the ones with a state
+  if matches.iter().filter(|(t, _)| has_state(t)).count() > 0 {
+    matches.retain(|(template, _)| has_state(template));
+  }
+  let (template, record) = match matches.len() {
+    0 => anyhow::bail!("no deployment named {:?} on this machine", name),
+    1 => matches.remove(0),
+    _ => {
+      let found: Vec<_> = matches
+        .iter()
+        .map(|(_, record)| format!("{} {}", record.provider, record.region))
+        .collect();
+      anyhow::bail!(
+        "several deployments are named {:?} ({}): pick one with \
+         --provider and --region",
+        name,
+        found.join(", ")
+      )
+    }
+  };
+  let provider = Provider::from_str(&record.provider, true)
+    .map_err(|err| anyhow::anyhow!(err))?;
+  let workspace = workspace_dir(&template)?;
+  println!("{} ({} {})", record.name, record.provider, record.region);
+  println!("  template:  {}", record.template.display());
+  println!("  workspace: {}", workspace.display());
+  let instance = if workspace.exists() {
+    instance(&workspace)?
+  } else {
+    None
+  };
+  let Some(instance) = instance else {
+    println!("  state:     not deployed");
+    return Ok(());
+  };
+  println!(
+    "  instance:  {} ({})",
+    attribute(&instance.values, "id").unwrap_or_default(),
+    instance.recorded.as_deref().unwrap_or("state unknown")
+  );
+  let cloud = match cloud_status(&provider, &record.region, &instance.values) {
+    Ok(state) => state,
+    Err(err) => format!("unknown: {:#}", err),
+  };
+  println!("  cloud:     {}", cloud);
+  let outputs = teardown::workspace_outputs(&workspace).unwrap_or_default();
+  match instance_ip(&provider, &outputs) {
+    Some(ip) => println!("  ip:        {} ({})", ip, probe(ip, ssh_port)),
+    None => println!("  ip:        none"),
+  }
+  if let Some(uptime) = uptime(&workspace) {
+    println!("  up:        {} (since the apply)", format_elapsed(uptime));
+  }
+  Ok(())
+}
+
+struct Instance {
+  /// The resource's attributes as of the last apply or refresh.
+  values: Value,
+  /// The instance state those attributes recorded.
+  recorded: Option<String>,
+}
+
+/// The instance resource in the workspace state, `None` once destroyed.
+fn instance(workspace: &Path) -> Result<Option<Instance>> {
+  let output = Command::new("terraform")
+    .arg(format!("-chdir={}", workspace.display()))
+    .args(["show", "-json"])
+    .stderr(Stdio::null())
+    .output()
+    .context("Failed to run terraform show")?;
+  if !output.status.success() {
+    anyhow::bail!("terraform show failed in {:?}", workspace);
+  }
+  let state: Value = serde_json::from_slice(&output.stdout)
+    .context("Failed to parse terraform show")?;
+  let resources = state["values"]["root_module"]["resources"]
+    .as_array()
+    .cloned()
+    .unwrap_or_default();
+  Ok(resources.into_iter().find_map(|resource| {
+    let (_, state_key) = INSTANCES
+      .iter()
+      .find(|(kind, _)| resource["type"] == *kind)?;
+    let values = resource["values"].clone();
+    let recorded = attribute(&values, state_key);
+    Some(Instance { values, recorded })
+  }))
+}
+
+/// A string or number attribute, as a string.
+fn attribute(values: &Value, key: &str) -> Option<String> {
+  match &values[key] {
+    Value::String(value) => Some(value.clone()),
+    Value::Number(value) => Some(value.to_string()),
+    _ => None,
+  }
+}
+
+/// What the provider reports for the instance now, through its CLI: the
+/// state holds only what Terraform last saw.
+fn cloud_status(
+  provider: &Provider,
+  region: &str,
+  values: &Value,
+) -> Result<String> {
+  let get = |key| {
+    attribute(values, key)
+      .with_context(|| format!("no {} for the instance in the state", key))
+  };
+  let mut command;
+  match provider {
+    Provider::AWS => {
+      command = Command::new("aws");
+      command.args([
+        "ec2",
+        "describe-instances",
+        "--region",
+        region,
+        "--instance-ids",
+        &get("id")?,
+        "--query",
+        "Reservations[0].Instances[0].State.Name",
+        "--output",
+        "text",
+      ]);
+    }
+    Provider::GCP => {
+      command = Command::new("gcloud");
+      command.args([
+        "compute",
+        "instances",
+        "describe",
+        &get("name")?,
+        "--zone",
+        region,
+        "--project",
+        &get("project")?,
+        "--format",
+        "value(status)",
+      ]);
+    }
+    Provider::Hetzner => {
+      command = Command::new("hcloud");
+      command.args(["server", "describe", &get("id")?, "-o"]);
+      command.arg("format={{.Status}}");
+    }
+    Provider::Oracle => {
+      command = Command::new("oci");
+      command.args([
+        "compute",
+        "instance",
+        "get",
+        "--instance-id",
+        &get("id")?,
+        "--query",
+        "data.\"lifecycle-state\"",
+        "--raw-output",
+      ]);
+    }
+    Provider::Linode => {
+      command = Command::new("linode-cli");
+      command.args([
+        "linodes",
+        "view",
+        &get("id")?,
+        "--format",
+        "status",
+        "--text",
+        "--no-headers",
+      ]);
+    }
+    Provider::Scaleway => {
+      // The state keeps zoned IDs, `fr-par-1/<uuid>`
+      let id = get("id")?;
+      let id = id.rsplit('/').next().unwrap_or(&id);
+      command = Command::new("scw");
+      command.args(["instance", "server", "get", id]);
+      command.arg(format!("zone={}", region));
+      command.arg("-o").arg("template={{.State}}");
+    }
+  }
+  let program = command.get_program().to_string_lossy().into_owned();
+  let output = command
+    .stdin(Stdio::null())
+    .output()
+    .with_context(|| format!("could not run {} (is it installed?)", program))?;
+  if !output.status.success() {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    anyhow::bail!(
+      "{} failed: {}",
+      program,
+      stderr.lines().find(|l| !l.trim().is_empty()).unwrap_or("")
+    );
+  }
+  Ok(
+    String::from_utf8_lossy(&output.stdout)
+      .trim()
+      .to_lowercase(),
+  )
+}
+
+/// Whether SSH answers on `ip`.
+fn probe(ip: &str, port: u16) -> &'static str {
+  let Ok(ip) = ip.parse() else {
+    return "not an IP address";
+  };
+  match TcpStream::connect_timeout(&SocketAddr::new(ip, port), PROBE_TIMEOUT) {
+    Ok(_) => "ssh reachable",
+    Err(_) => "ssh unreachable",
+  }
+}
+
+fn has_state(template: &Path) -> bool {
+  workspace_dir(template)
+    .is_ok_and(|workspace| workspace.join("terraform.tfstate").exists())
+}
+
+/// Time since the apply last wrote the state.
+fn uptime(workspace: &Path) -> Option<Duration> {
+  let modified = fs::metadata(workspace.join("terraform.tfstate"))
+    .ok()?
+    .modified()
+    .ok()?;
+  SystemTime::now().duration_since(modified).ok()
+}
@@ -0,0 +1,154 @@
+//! Colors for the console, and the aligned tables the parameters are shown
+//! in. Colors are off with `--no-color`, when `NO_COLOR` is set, and on
+//! any stream that is not a terminal, so pipes and the log file stay plain.
+
+use anstyle::{AnsiColor, Style};
+use std::{
+  env, fmt,
+  io::{self, IsTerminal},
+  sync::atomic::{AtomicBool, Ordering},
+};
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+const BOLD: Style = Style::new().bold();
+const YELLOW: Style = AnsiColor::Yellow.on_default();
+const RED: Style = AnsiColor::Red.on_default();
+const ALERT: Style = AnsiColor::Red.on_default().bold();
+
+#[derive(Clone, Copy)]
+pub enum Stream {
+  Stdout,
+  Stderr,
+}
+
+/// Turn colors off for good when asked to, by flag or by `NO_COLOR`.
+pub fn init(no_color: bool) {
+  let no_color_env = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+  if no_color || no_color_env {
+    DISABLED.store(true, Ordering::Relaxed);
+  }
+}
+
+pub fn bold(text: impl fmt::Display, stream: Stream) -> String {
+  paint(BOLD, text, stream)
+}
+
+pub fn yellow(text: impl fmt::Display, stream: Stream) -> String {
+  paint(YELLOW, text, stream)
+}
+
+pub fn red(text: impl fmt::Display, stream: Stream) -> String {
+  paint(RED, text, stream)
+}
+
+/// For what must not be missed, like the window to abandon a destroy.
+pub fn alert(text: impl fmt::Display, stream: Stream) -> String {
+  paint(ALERT, text, stream)
+}
+
+fn paint(style: Style, text: impl fmt::Display, stream: Stream) -> String {
+  render(style, text, colored(stream))
+}
+
+/// Whether what goes to `stream` is colored.
+fn colored(stream: Stream) -> bool {
+  let terminal = match stream {
+    Stream::Stdout => io::stdout().is_terminal(),
+    Stream::Stderr => io::stderr().is_terminal(),
+  };
+  terminal && !DISABLED.load(Ordering::Relaxed)
+}
+
+fn render(style: Style, text: impl fmt::Display, colored: bool) -> String {
+  if !colored {
+    return text.to_string();
+  }
+  format!("{}{}{}", style.render(), text, style.render_reset())
+}
+
+/// A title over `key  value` rows, the values lined up.
+pub struct Table {
+  title: &'static str,
+  rows: Vec<(&'static str, String)>,
+}
+
+impl Table {
+  pub fn new(title: &'static str) -> Self {
+    Table {
+      title,
+      rows: Vec::new(),
+    }
+  }
+
+  /// Add a row; an empty `key` continues the row above.
+  pub fn row(&mut self, key: &'static str, value: impl fmt::Display) {
+    self.rows.push((key, value.to_string()));
+  }
+}
+
+impl fmt::Display for Table {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.title)?;
+    let width = self.rows.iter().map(|(key, _)| key.len()).max();
+    for (key, value) in &self.rows {
+      write!(f, "\n  {:<2$}  {}", key, value, width.unwrap_or(0))?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn colored_rendering() {
+    assert_eq!(render(BOLD, "public_ip", true), "\x1b[1mpublic_ip\x1b[0m");
+    assert_eq!(
+      render(YELLOW, "WARNING: spot", true),
+      "\x1b[33mWARNING: spot\x1b[0m"
+    );
+    assert_eq!(
+      render(RED, "ERROR: failed", true),
+      "\x1b[31mERROR: failed\x1b[0m"
+    );
+    assert_eq!(
+      render(ALERT, "Destroy in progress", true),
+      "\x1b[1m\x1b[31mDestroy in progress\x1b[0m"
+    );
+  }
+
+  #[test]
+  fn plain_rendering() {
+    for style in [BOLD, YELLOW, RED, ALERT] {
+      assert_eq!(render(style, "public_ip", false), "public_ip");
+    }
+  }
+
+  #[test]
+  fn no_color_turns_colors_off() {
+    init(true);
+    assert!(!colored(Stream::Stdout));
+    assert!(!colored(Stream::Stderr));
+    assert_eq!(
+      alert("Destroy in progress", Stream::Stderr),
+      "Destroy in progress"
+    );
+  }
+
+  #[test]
+  fn table_lines_up_the_values() {
+    let mut table = Table::new("Deploy params");
+    table.row("name", "fuoco-ephemeral");
+    table.row("instance_type", "cx22");
+    table.row("", "continued");
+    let expected = [
+      "Deploy params",
+      "  name           fuoco-ephemeral",
+      "  instance_type  cx22",
+      "                 continued",
+    ];
+    assert_eq!(table.to_string(), expected.join("\n"));
+  }
+}
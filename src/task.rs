@@ -0,0 +1,218 @@
+//! Futures for the blocking deploy and destroy, so Terraform does not hold
+//! up an async caller's executor. Each runs on a thread of its own and
+//! wakes the task once done, so any executor can poll them.
+
+use std::{
+  future::Future,
+  pin::Pin,
+  sync::{Arc, Mutex},
+  task::{Context, Poll, Waker},
+  thread,
+};
+
+/// The result of `f`, run on a worker thread. Dropping it before it is
+/// ready runs what `on_drop` was given, lets `f` finish and then drops
+/// what it returned on the worker, so a deployment nobody waits for
+/// anymore is destroyed.
+pub struct Task<T: Send + 'static> {
+  shared: Arc<Mutex<Shared<T>>>,
+  on_drop: Option<Box<dyn FnOnce() + Send>>,
+}
+
+struct Shared<T> {
+  result: Option<T>,
+  waker: Option<Waker>,
+  finished: bool,
+  abandoned: bool,
+}
+
+/// Start `f` on a worker thread.
+pub fn spawn<T: Send + 'static>(
+  f: impl FnOnce() -> T + Send + 'static,
+) -> Task<T> {
+  let shared = Arc::new(Mutex::new(Shared {
+    result: None,
+    waker: None,
+    finished: false,
+    abandoned: false,
+  }));
+  let worker = shared.clone();
+  thread::spawn(move || {
+    let result = f();
+    let mut shared = worker.lock().unwrap_or_else(|e| e.into_inner());
+    shared.finished = true;
+    if shared.abandoned {
+      // Dropped here, outside the lock: a handle's drop destroys
+      drop(shared);
+      drop(result);
+      return;
+    }
+    shared.result = Some(result);
+    if let Some(waker) = shared.waker.take() {
+      waker.wake();
+    }
+  });
+  Task {
+    shared,
+    on_drop: None,
+  }
+}
+
+impl<T: Send + 'static> Task<T> {
+  /// Run `f` if the task is dropped before the worker is done, e.g. to
+  /// make it give up early.
+  pub fn on_drop(mut self, f: impl FnOnce() + Send + 'static) -> Self {
+    self.on_drop = Some(Box::new(f));
+    self
+  }
+}
+
+impl<T: Send + 'static> Future for Task<T> {
+  type Output = T;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+    let mut shared = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+    match shared.result.take() {
+      Some(result) => Poll::Ready(result),
+      None => {
+        shared.waker = Some(cx.waker().clone());
+        Poll::Pending
+      }
+    }
+  }
+}
+
+impl<T: Send + 'static> Drop for Task<T> {
+  fn drop(&mut self) {
+    let mut shared = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+    shared.abandoned = true;
+    // Finished but never polled: drop it off the caller's thread, since
+    // destroying blocks
+    if let Some(result) = shared.result.take() {
+      thread::spawn(move || drop(result));
+    }
+    let finished = shared.finished;
+    drop(shared);
+    if let (false, Some(on_drop)) = (finished, self.on_drop.take()) {
+      on_drop();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::{
+    sync::{
+      atomic::{AtomicUsize, Ordering},
+      mpsc,
+    },
+    task::Wake,
+    time::{Duration, Instant},
+  };
+
+  /// Counts its drops, as a handle's drop would destroy.
+  struct Counted(Arc<AtomicUsize>);
+
+  impl Drop for Counted {
+    fn drop(&mut self) {
+      self.0.fetch_add(1, Ordering::SeqCst);
+    }
+  }
+
+  struct Woken(AtomicUsize);
+
+  impl Wake for Woken {
+    fn wake(self: Arc<Self>) {
+      self.0.fetch_add(1, Ordering::SeqCst);
+    }
+  }
+
+  fn poll<T: Send + 'static>(task: &mut Task<T>, waker: &Waker) -> Poll<T> {
+    Pin::new(task).poll(&mut Context::from_waker(waker))
+  }
+
+  fn wait_for(what: &str, done: impl Fn() -> bool) {
+    let started = Instant::now();
+    while !done() {
+      assert!(started.elapsed() < Duration::from_secs(10), "{}", what);
+      thread::sleep(Duration::from_millis(10));
+    }
+  }
+
+  #[test]
+  fn ready_once_the_worker_is_done() {
+    let woken = Arc::new(Woken(AtomicUsize::new(0)));
+    let waker = Waker::from(woken.clone());
+    let (release, gate) = mpsc::channel::<()>();
+    let mut task = spawn(move || {
+      gate.recv().unwrap();
+      7
+    });
+    assert!(poll(&mut task, &waker).is_pending());
+    release.send(()).unwrap();
+    wait_for("the waker", || woken.0.load(Ordering::SeqCst) == 1);
+    assert_eq!(poll(&mut task, &waker), Poll::Ready(7));
+  }
+
+  #[test]
+  fn polled_after_the_worker_is_done() {
+    let dropped = Arc::new(AtomicUsize::new(0));
+    let on_drop = Arc::new(AtomicUsize::new(0));
+    let counter = dropped.clone();
+    let mut task = spawn(move || Counted(counter));
+    let ran = on_drop.clone();
+    task = task.on_drop(move || {
+      ran.fetch_add(1, Ordering::SeqCst);
+    });
+    wait_for("the worker", || task.shared.lock().unwrap().finished);
+    let Poll::Ready(result) = poll(&mut task, Waker::noop()) else {
+      panic!("not ready once the worker is done");
+    };
+    drop(task);
+    assert_eq!(dropped.load(Ordering::SeqCst), 0);
+    drop(result);
+    assert_eq!(dropped.load(Ordering::SeqCst), 1);
+    assert_eq!(on_drop.load(Ordering::SeqCst), 0);
+  }
+
+  #[test]
+  fn dropped_before_ready() {
+    let dropped = Arc::new(AtomicUsize::new(0));
+    let on_drop = Arc::new(AtomicUsize::new(0));
+    let (release, gate) = mpsc::channel::<()>();
+    let counter = dropped.clone();
+    let ran = on_drop.clone();
+    let mut task = spawn(move || {
+      gate.recv().unwrap();
+      Counted(counter)
+    })
+    .on_drop(move || {
+      ran.fetch_add(1, Ordering::SeqCst);
+    });
+    assert!(poll(&mut task, Waker::noop()).is_pending());
+    drop(task);
+    assert_eq!(on_drop.load(Ordering::SeqCst), 1);
+    // The worker still finishes, and drops what it made
+    assert_eq!(dropped.load(Ordering::SeqCst), 0);
+    release.send(()).unwrap();
+    wait_for("the drop", || dropped.load(Ordering::SeqCst) == 1);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(dropped.load(Ordering::SeqCst), 1);
+  }
+
+  #[test]
+  fn dropped_done_but_never_polled() {
+    let dropped = Arc::new(AtomicUsize::new(0));
+    let on_drop = Arc::new(AtomicUsize::new(0));
+    let counter = dropped.clone();
+    let ran = on_drop.clone();
+    let task = spawn(move || Counted(counter)).on_drop(move || {
+      ran.fetch_add(1, Ordering::SeqCst);
+    });
+    wait_for("the worker", || task.shared.lock().unwrap().finished);
+    drop(task);
+    wait_for("the drop", || dropped.load(Ordering::SeqCst) == 1);
+    assert_eq!(on_drop.load(Ordering::SeqCst), 0);
+  }
+}
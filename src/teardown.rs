@@ -0,0 +1,144 @@
+//! Remote teardown scripts, run over SSH just before the VM is destroyed.
+
+use anyhow::{Context, Result};
+use std::{
+  collections::HashMap,
+  fs,
+  io::Write,
+  path::{Path, PathBuf},
+  process::{Command, Stdio},
+  thread,
+  time::{Duration, Instant},
+};
+
+/// ssh's own exit status when it could not connect or authenticate.
+pub const SSH_FAILED: i32 = 255;
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A `--teardown-script`, read when fuoco starts so a bad path fails before
+/// anything is deployed.
+#[derive(Clone)]
+pub struct Script {
+  pub path: PathBuf,
+  pub content: String,
+  pub timeout: Duration,
+}
+
+impl Script {
+  pub fn read(path: PathBuf, timeout: Duration) -> Result<Self> {
+    let content = fs::read_to_string(&path)
+      .with_context(|| format!("Failed to read teardown script {:?}", path))?;
+    if content.trim().is_empty() {
+      anyhow::bail!("Teardown script {:?} is empty", path);
+    }
+    Ok(Script {
+      path,
+      content,
+      timeout,
+    })
+  }
+}
+
+/// Where and how to reach the instance.
+pub struct Target {
+  pub ip: String,
+  pub port: u16,
+  pub user: String,
+  /// Private key to authenticate with; ssh's defaults when `None`.
+  pub identity: Option<PathBuf>,
+}
+
+/// Pipe the script into `sh -s` on the instance, streaming its output, and
+/// kill it once its timeout expires.
+pub fn run(target: &Target, script: &Script) -> Result<()> {
+  let timeout = script.timeout;
+  status!(
+    "Running teardown script on {}@{} (timeout {}s)...",
+    target.user,
+    target.ip,
+    timeout.as_secs()
+  );
+  let mut command = ssh(target);
+  let mut child = command
+    .arg("sh -s")
+    .stdin(Stdio::piped())
+    .stdout(crate::child_stdout())
+    .spawn()
+    .context("Failed to run ssh")?;
+  // A script that exits before reading all of stdin closes the pipe early;
+  // its exit status is what matters
+  if let Some(mut stdin) = child.stdin.take() {
+    let _ = stdin.write_all(script.content.as_bytes());
+  }
+  let deadline = Instant::now() + timeout;
+  let status = loop {
+    if let Some(status) = child.try_wait().context("Failed to wait on ssh")? {
+      break status;
+    }
+    if Instant::now() >= deadline {
+      let _ = child.kill();
+      let _ = child.wait();
+      anyhow::bail!("teardown script timed out after {}s", timeout.as_secs());
+    }
+    thread::sleep(POLL_INTERVAL);
+  };
+  match status.code() {
+    Some(0) => Ok(()),
+    Some(SSH_FAILED) => {
+      anyhow::bail!("could not reach {} over SSH", target.ip)
+    }
+    _ => anyhow::bail!("teardown script exited with {}", status),
+  }
+}
+
+/// An `ssh` command logged in to `target` without prompts, to which the
+/// remote command is added.
+pub fn ssh(target: &Target) -> Command {
+  let mut command = Command::new("ssh");
+  command
+    .args(["-o", "BatchMode=yes"])
+    .args(["-o", "StrictHostKeyChecking=no"])
+    .args(["-o", "UserKnownHostsFile=/dev/null"])
+    .args(["-o", "LogLevel=ERROR"])
+    .args(["-o", "ConnectTimeout=10"])
+    .arg("-p")
+    .arg(target.port.to_string());
+  if let Some(identity) = &target.identity {
+    command.arg("-i").arg(identity);
+  }
+  command.arg(format!("{}@{}", target.user, target.ip));
+  command
+}
+
+/// The private key next to `public_key_path` (`id_ed25519` for
+/// `id_ed25519.pub`), if there is one.
+pub fn identity_for(public_key_path: &str) -> Option<PathBuf> {
+  let private = crate::expand_home(public_key_path.strip_suffix(".pub")?);
+  private.is_file().then_some(private)
+}
+
+/// Terraform outputs recorded in the workspace at `dir`, as strings. This is
+/// how `undeploy` finds the instance a previous deploy left running.
+pub fn workspace_outputs(dir: &Path) -> Result<HashMap<String, String>> {
+  let output = Command::new("terraform")
+    .arg(format!("-chdir={}", dir.display()))
+    .args(["output", "-json"])
+    .stderr(Stdio::null())
+    .output()
+    .context("Failed to run terraform output")?;
+  if !output.status.success() {
+    anyhow::bail!("terraform output failed in {:?}", dir);
+  }
+  let outputs: HashMap<String, serde_json::Value> =
+    serde_json::from_slice(&output.stdout)
+      .context("Failed to parse terraform output")?;
+  Ok(
+    outputs
+      .into_iter()
+      .filter_map(|(key, output)| {
+        let value = output.get("value")?.as_str()?.to_string();
+        Some((key, value))
+      })
+      .collect(),
+  )
+}
@@ -0,0 +1,92 @@
+//! How long each phase of a deploy or destroy took, to tell a slow apply
+//! from a slow destroy. atar runs `terraform init` and `apply` in one call,
+//! so they are timed together as `apply`, outputs included.
+
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+use std::{
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+/// How --timings prints the summary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TimingsFormat {
+  /// `timings: prepare=0.4s apply=58.1s total=58.5s`
+  Text,
+  /// `{"timings":{"prepare":0.4,"apply":58.1,"total":58.5}}`
+  Json,
+}
+
+#[derive(Default)]
+pub struct Timings {
+  phases: Mutex<Vec<(&'static str, Duration)>>,
+}
+
+impl Timings {
+  /// Run `f` and add how long it took to `phase`.
+  pub fn time<T>(&self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    let elapsed = started.elapsed();
+    let mut phases = self.phases.lock().unwrap_or_else(|e| e.into_inner());
+    match phases.iter_mut().find(|(name, _)| *name == phase) {
+      Some((_, total)) => *total += elapsed,
+      None => phases.push((phase, elapsed)),
+    }
+    result
+  }
+
+  /// Each phase in the order it first ran, then `total`, their sum: the
+  /// time spent waiting for a signal is not a phase.
+  fn phases(&self) -> Vec<(&'static str, Duration)> {
+    let mut phases = self
+      .phases
+      .lock()
+      .unwrap_or_else(|e| e.into_inner())
+      .clone();
+    let total = phases.iter().map(|(_, elapsed)| *elapsed).sum();
+    phases.push(("total", total));
+    phases
+  }
+
+  /// Seconds for each phase, rounded to tenths as the text summary shows.
+  pub fn to_json(&self) -> Value {
+    let phases: Map<_, _> = self
+      .phases()
+      .into_iter()
+      .map(|(name, elapsed)| {
+        let secs = (elapsed.as_secs_f64() * 10.0).round() / 10.0;
+        (name.to_string(), secs.into())
+      })
+      .collect();
+    Value::Object(phases)
+  }
+
+  /// Print the summary, prefixed with `label` when there are several
+  /// deployments to tell apart.
+  pub fn print(&self, format: TimingsFormat, label: Option<&str>) {
+    match format {
+      TimingsFormat::Text => {
+        let phases: Vec<_> = self
+          .phases()
+          .into_iter()
+          .map(|(name, elapsed)| {
+            format!("{}={:.1}s", name, elapsed.as_secs_f64())
+          })
+          .collect();
+        match label {
+          Some(label) => status!("timings [{}]: {}", label, phases.join(" ")),
+          None => status!("timings: {}", phases.join(" ")),
+        }
+      }
+      TimingsFormat::Json => {
+        let mut line = json!({ "timings": self.to_json() });
+        if let Some(label) = label {
+          line["provider"] = label.into();
+        }
+        status!("{}", line);
+      }
+    }
+  }
+}
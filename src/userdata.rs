@@ -0,0 +1,558 @@
+//! Rendering of the user data that the templates hand to cloud-init.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use flate2::{write::GzEncoder, Compression};
+use serde_yaml::{Mapping, Value};
+use std::{fmt, fs, io::Write, path::PathBuf};
+
+/// Separates the parts of a multipart user data archive.
+const MIME_BOUNDARY: &str = "==FUOCO_BOUNDARY==";
+
+/// Ends the heredoc that writes the startup script, unless the script has
+/// a line like it.
+const STARTUP_DELIMITER: &str = "FUOCO_STARTUP_EOF";
+
+/// Everything fuoco needs to run on the VM at first boot.
+#[derive(Clone, Default)]
+pub struct UserData {
+  /// Contents of the user's startup script.
+  pub script: Option<String>,
+  /// Mount point for the extra data volume, when one is attached.
+  pub extra_volume_mount: Option<String>,
+  /// Login user to create and hand the instance's SSH keys to.
+  pub user: Option<String>,
+  /// Extra SSH public keys to authorize, for providers that take only one.
+  pub authorized_keys: Vec<String>,
+  /// A `#cloud-config` document passed through as is.
+  pub cloud_config: Option<String>,
+  /// Port to move sshd to.
+  pub ssh_port: Option<u16>,
+  /// User data that replaces all of the above, passed through unchanged.
+  pub verbatim: Option<String>,
+  /// Variables exported to the startup script, in order.
+  pub env: Vec<(String, String)>,
+}
+
+impl UserData {
+  /// Render the user data: --cloud-init's as is, the cloud-config or the
+  /// boot script on their own, or a multipart MIME archive when there are
+  /// both. Returns an empty
+  /// string when there is nothing to run so the templates can skip it.
+  pub fn render(&self) -> String {
+    if let Some(verbatim) = &self.verbatim {
+      return verbatim.clone();
+    }
+    let script = self.render_script();
+    match &self.cloud_config {
+      None => script,
+      Some(cloud_config) if script.is_empty() => cloud_config.clone(),
+      Some(cloud_config) => render_multipart(&[
+        ("text/cloud-config", cloud_config),
+        ("text/x-shellscript", &script),
+      ]),
+    }
+  }
+
+  /// Render everything but the cloud-config as a single Bash script.
+  fn render_script(&self) -> String {
+    if self.script.is_none()
+      && self.extra_volume_mount.is_none()
+      && self.user.is_none()
+      && self.authorized_keys.is_empty()
+      && self.ssh_port.is_none()
+    {
+      return String::new();
+    }
+    let mut out = String::from("#!/bin/bash\n");
+    // Before the user snippet, which copies every authorized key it finds
+    if !self.authorized_keys.is_empty() {
+      out.push_str(&render_authorized_keys(&self.authorized_keys));
+    }
+    if let Some(user) = &self.user {
+      out.push_str(&render_user(user));
+    }
+    if let Some(port) = self.ssh_port {
+      out.push_str(&render_ssh_port(port));
+    }
+    if let Some(mount) = &self.extra_volume_mount {
+      out.push_str(&render_extra_volume(mount));
+    }
+    if let Some(script) = &self.script {
+      if !self.env.is_empty() {
+        out.push_str("# fuoco: environment\n");
+        for (key, value) in &self.env {
+          out.push_str(&format!("export {}={}\n", key, shell_quote(value)));
+        }
+      }
+      // Run the user's script as its own file so its shebang is honoured
+      let delimiter = heredoc_delimiter(script);
+      out.push_str("# fuoco: startup script\n");
+      out.push_str("mkdir -p /var/lib/fuoco\n");
+      out
+        .push_str(&format!("cat > /var/lib/fuoco/startup <<'{}'\n", delimiter));
+      out.push_str(script);
+      if !script.ends_with('\n') {
+        out.push('\n');
+      }
+      out.push_str(&delimiter);
+      out.push('\n');
+      out.push_str("chmod +x /var/lib/fuoco/startup\n");
+      out.push_str("/var/lib/fuoco/startup\n");
+    }
+    out
+  }
+}
+
+/// A heredoc delimiter that is no line of `script`: one that is would end
+/// the heredoc early and run the rest of the script outside the file.
+fn heredoc_delimiter(script: &str) -> String {
+  let mut delimiter = STARTUP_DELIMITER.to_string();
+  let mut n = 0;
+  while script.lines().any(|line| line == delimiter) {
+    n += 1;
+    delimiter = format!("{}_{}", STARTUP_DELIMITER, n);
+  }
+  delimiter
+}
+
+/// How much user data a provider accepts and how it can be shipped.
+pub struct Limit {
+  pub max_bytes: usize,
+  /// The limit applies to the base64-encoded payload.
+  pub base64: bool,
+  /// The template can take gzipped user data (sent base64-encoded).
+  pub gzip: bool,
+}
+
+/// User data ready for the template.
+pub struct Encoded {
+  /// The rendered user data, or its gzip as base64 when `gzip` is set.
+  pub payload: String,
+  pub gzip: bool,
+  /// Size of the rendered user data.
+  pub raw_size: usize,
+  /// Size as the provider counts it against `Limit::max_bytes`.
+  pub size: usize,
+}
+
+/// Ship `rendered` as is when it fits `limit`, gzipped when it does not and
+/// the provider allows it (cloud-init recognises the gzip magic). The caller
+/// checks the resulting `size` against the limit.
+pub fn encode(rendered: String, limit: &Limit) -> Encoded {
+  let counted = |bytes: usize| {
+    if limit.base64 {
+      bytes.div_ceil(3) * 4
+    } else {
+      bytes
+    }
+  };
+  let raw_size = rendered.len();
+  if counted(raw_size) <= limit.max_bytes || !limit.gzip {
+    return Encoded {
+      payload: rendered,
+      gzip: false,
+      raw_size,
+      size: counted(raw_size),
+    };
+  }
+  let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+  // Writing into a Vec cannot fail
+  let _ = encoder.write_all(rendered.as_bytes());
+  let gzipped = encoder.finish().unwrap_or_default();
+  Encoded {
+    payload: BASE64.encode(&gzipped),
+    gzip: true,
+    raw_size,
+    size: counted(gzipped.len()),
+  }
+}
+
+/// A local file written onto the VM by cloud-init before any script runs,
+/// given on the command line as `local:remote[:mode]`.
+#[derive(Clone)]
+pub struct WriteFile {
+  pub local: PathBuf,
+  pub remote: String,
+  /// Octal permissions, e.g. `0600`.
+  pub mode: Option<String>,
+  pub content: Vec<u8>,
+}
+
+impl std::str::FromStr for WriteFile {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let parts: Vec<&str> = s.split(':').collect();
+    let (local, remote, mode) = match parts[..] {
+      [local, remote] => (local, remote, None),
+      [local, remote, mode] => (local, remote, Some(mode)),
+      _ => return Err("File must be in format local:remote[:mode]".into()),
+    };
+    if !remote.starts_with('/') {
+      return Err(format!("Destination {:?} must be an absolute path", remote));
+    }
+    if let Some(mode) = mode {
+      let valid = (3..=4).contains(&mode.len())
+        && mode.chars().all(|c| ('0'..='7').contains(&c));
+      if !valid {
+        return Err(format!("Mode {:?} must be octal, e.g. 0644", mode));
+      }
+    }
+    let content = fs::read(local)
+      .map_err(|err| format!("Failed to read {:?}: {}", local, err))?;
+    Ok(WriteFile {
+      local: PathBuf::from(local),
+      remote: remote.to_string(),
+      mode: mode.map(|mode| format!("{:0>4}", mode)),
+      content,
+    })
+  }
+}
+
+/// `local -> remote (mode)`, for the params dump.
+impl fmt::Display for WriteFile {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} -> {}", self.local.display(), self.remote)?;
+    if let Some(mode) = &self.mode {
+      write!(f, " ({})", mode)?;
+    }
+    Ok(())
+  }
+}
+
+/// Size of the `write_files` entries once base64-encoded, which is what
+/// counts against the providers' user data limits.
+pub fn encoded_size(files: &[WriteFile]) -> usize {
+  files
+    .iter()
+    .map(|file| file.content.len().div_ceil(3) * 4)
+    .sum()
+}
+
+/// Add `files` to the `write_files` list of `cloud_config` (a validated
+/// `#cloud-config` document), or to a fresh one when there is none.
+/// cloud-init writes them in its init stage, before any user script runs.
+pub fn add_write_files(
+  cloud_config: Option<String>,
+  files: &[WriteFile],
+) -> Result<Option<String>, serde_yaml::Error> {
+  if files.is_empty() {
+    return Ok(cloud_config);
+  }
+  let mut document = match &cloud_config {
+    Some(cloud_config) => serde_yaml::from_str(cloud_config)?,
+    None => Mapping::new(),
+  };
+  let key = Value::from("write_files");
+  if !matches!(document.get(&key), Some(Value::Sequence(_))) {
+    document.insert(key.clone(), Value::Sequence(Vec::new()));
+  }
+  if let Some(Value::Sequence(entries)) = document.get_mut(&key) {
+    for file in files {
+      let mut entry = Mapping::new();
+      entry.insert("path".into(), file.remote.clone().into());
+      entry.insert("encoding".into(), "b64".into());
+      entry.insert("content".into(), BASE64.encode(&file.content).into());
+      if let Some(mode) = &file.mode {
+        entry.insert("permissions".into(), mode.clone().into());
+      }
+      entries.push(Value::Mapping(entry));
+    }
+  }
+  let yaml = serde_yaml::to_string(&document)?;
+  Ok(Some(format!("#cloud-config\n{}", yaml)))
+}
+
+/// Have cloud-init set the hostname in its init stage, by adding it to
+/// `cloud_config` or to a fresh cloud-config when there is none.
+pub fn set_hostname(
+  cloud_config: Option<String>,
+  hostname: Option<&str>,
+) -> Result<Option<String>, serde_yaml::Error> {
+  let Some(hostname) = hostname else {
+    return Ok(cloud_config);
+  };
+  let mut document = match &cloud_config {
+    Some(cloud_config) => serde_yaml::from_str(cloud_config)?,
+    None => Mapping::new(),
+  };
+  document.insert("hostname".into(), hostname.into());
+  // Some images keep the hostname the provider assigned unless told not to
+  document.insert("preserve_hostname".into(), false.into());
+  let yaml = serde_yaml::to_string(&document)?;
+  Ok(Some(format!("#cloud-config\n{}", yaml)))
+}
+
+/// Bundle `(content type, body)` parts into a multipart MIME archive, which
+/// cloud-init processes part by part in order.
+fn render_multipart(parts: &[(&str, &String)]) -> String {
+  let mut out = format!(
+    "Content-Type: multipart/mixed; boundary=\"{}\"\nMIME-Version: 1.0\n",
+    MIME_BOUNDARY
+  );
+  for (content_type, body) in parts {
+    out.push_str(&format!("\n--{}\n", MIME_BOUNDARY));
+    out.push_str(&format!(
+      "Content-Type: {}; charset=\"utf-8\"\nMIME-Version: 1.0\n\n",
+      content_type
+    ));
+    out.push_str(body);
+    if !body.ends_with('\n') {
+      out.push('\n');
+    }
+  }
+  out.push_str(&format!("--{}--\n", MIME_BOUNDARY));
+  out
+}
+
+/// Join `(label, script)` pairs into one Bash script that runs them in
+/// order and stops at the first failure. Each stage is announced on stdout so
+/// the console log shows which one is running.
+pub fn join_scripts(scripts: &[(String, String)]) -> String {
+  let mut out = String::from("#!/bin/bash\nset -euo pipefail\n");
+  for (i, (label, script)) in scripts.iter().enumerate() {
+    let stage = format!("[{}/{}] {}", i + 1, scripts.len(), label);
+    out.push_str(&format!("\n# ===== fuoco: {} =====\n", stage));
+    // Quoted so file names cannot expand in the shell
+    out.push_str(&format!(
+      "echo {}\n",
+      shell_quote(&format!("fuoco: running {}", stage))
+    ));
+    out.push_str(script);
+    if !script.ends_with('\n') {
+      out.push('\n');
+    }
+  }
+  out
+}
+
+/// Single-quote `value` for Bash, so it is taken literally.
+pub fn shell_quote(value: &str) -> String {
+  format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Append `keys` to every authorized_keys file the image set up for its
+/// default user (cloud-init has written them by the time scripts run).
+fn render_authorized_keys(keys: &[String]) -> String {
+  let mut out = String::from("# fuoco: extra SSH keys\n");
+  out.push_str("for fuoco_keys in /home/*/.ssh/authorized_keys; do\n");
+  out.push_str("  [ -f \"$fuoco_keys\" ] || continue\n");
+  for key in keys {
+    out.push_str(&format!("  echo {} >> \"$fuoco_keys\"\n", shell_quote(key)));
+  }
+  out.push_str("done\n");
+  out
+}
+
+/// Create `user` with passwordless sudo and authorize every key the image
+/// installed for its default user. Root's keys on some images are wrapped in
+/// a "please login as" command, so those lines are skipped.
+fn render_user(user: &str) -> String {
+  format!(
+    r#"# fuoco: login user
+id -u {user} >/dev/null 2>&1 || useradd -m -s /bin/bash {user}
+echo "{user} ALL=(ALL) NOPASSWD:ALL" > /etc/sudoers.d/90-fuoco-{user}
+chmod 440 /etc/sudoers.d/90-fuoco-{user}
+fuoco_home="$(getent passwd {user} | cut -d: -f6)"
+mkdir -p "$fuoco_home/.ssh"
+cat /root/.ssh/authorized_keys /home/*/.ssh/authorized_keys 2>/dev/null | grep -v 'command=' | sort -u > /tmp/fuoco_authorized_keys
+mv /tmp/fuoco_authorized_keys "$fuoco_home/.ssh/authorized_keys"
+chmod 700 "$fuoco_home/.ssh"
+chmod 600 "$fuoco_home/.ssh/authorized_keys"
+chown -R {user}: "$fuoco_home/.ssh"
+"#,
+    user = user
+  )
+}
+
+/// Move sshd to `port`. Images where ssh.socket starts sshd take the port
+/// from sshd_config at daemon-reload; the SELinux and iptables rules some
+/// images ship only allow 22.
+fn render_ssh_port(port: u16) -> String {
+  format!(
+    r#"# fuoco: ssh port
+sed -i '/^[[:space:]]*Port[[:space:]]/d' /etc/ssh/sshd_config
+sed -i '1i Port {port}' /etc/ssh/sshd_config
+if command -v semanage >/dev/null 2>&1; then
+  semanage port -a -t ssh_port_t -p tcp {port} 2>/dev/null || semanage port -m -t ssh_port_t -p tcp {port}
+fi
+if command -v iptables >/dev/null 2>&1; then
+  iptables -I INPUT -p tcp --dport {port} -j ACCEPT
+fi
+systemctl daemon-reload
+if systemctl is-active --quiet ssh.socket; then
+  systemctl restart ssh.socket
+else
+  systemctl restart ssh 2>/dev/null || systemctl restart sshd
+fi
+"#,
+    port = port
+  )
+}
+
+/// Wait for the extra volume to be attached, format it if it is blank and
+/// mount it by UUID. The volume is the first whole disk that is neither the
+/// root disk nor mounted anywhere, which holds for every provider template.
+fn render_extra_volume(mount: &str) -> String {
+  format!(
+    r#"# fuoco: extra volume
+fuoco_root_disk="$(lsblk -no PKNAME "$(findmnt -no SOURCE /)" | head -n1)"
+fuoco_volume=""
+for _ in $(seq 1 60); do
+  for disk in $(lsblk -dno NAME,TYPE | awk '$2 == "disk" {{print $1}}'); do
+    if [ "$disk" != "$fuoco_root_disk" ] && [ -z "$(lsblk -no MOUNTPOINT "/dev/$disk" | tr -d '[:space:]')" ]; then
+      fuoco_volume="/dev/$disk"
+      break 2
+    fi
+  done
+  sleep 5
+done
+if [ -n "$fuoco_volume" ]; then
+  if [ -z "$(blkid -o value -s TYPE "$fuoco_volume")" ]; then
+    mkfs.ext4 -q "$fuoco_volume"
+  fi
+  fuoco_uuid="$(blkid -o value -s UUID "$fuoco_volume")"
+  mkdir -p {mount}
+  grep -q "$fuoco_uuid" /etc/fstab || echo "UUID=$fuoco_uuid {mount} ext4 defaults,nofail 0 2" >> /etc/fstab
+  mount {mount}
+else
+  echo "fuoco: extra volume never appeared, not mounting {mount}" >&2
+fi
+"#,
+    mount = mount
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{user_data_limit, Provider};
+  use clap::ValueEnum;
+  use flate2::read::GzDecoder;
+  use rand::{distributions::Alphanumeric, rngs::StdRng, Rng, SeedableRng};
+  use std::io::Read;
+
+  /// `len` characters gzip cannot shrink much.
+  fn noise(len: usize) -> String {
+    StdRng::seed_from_u64(7)
+      .sample_iter(&Alphanumeric)
+      .take(len)
+      .map(char::from)
+      .collect()
+  }
+
+  /// The most raw bytes `limit` takes before anything is gzipped.
+  fn largest_raw(limit: &Limit) -> usize {
+    if limit.base64 {
+      limit.max_bytes / 4 * 3
+    } else {
+      limit.max_bytes
+    }
+  }
+
+  fn gunzip(payload: &str) -> String {
+    let gzipped = BASE64.decode(payload).unwrap();
+    let mut out = String::new();
+    GzDecoder::new(&gzipped[..])
+      .read_to_string(&mut out)
+      .unwrap();
+    out
+  }
+
+  #[test]
+  fn user_data_under_and_at_the_limit_is_sent_as_is() {
+    for provider in Provider::value_variants() {
+      let limit = user_data_limit(provider);
+      for len in [largest_raw(&limit) - 1, largest_raw(&limit)] {
+        let rendered = noise(len);
+        let encoded = encode(rendered.clone(), &limit);
+        assert!(!encoded.gzip, "{:?} at {} bytes", provider, len);
+        assert_eq!(encoded.payload, rendered);
+        assert_eq!(encoded.raw_size, len);
+        assert!(encoded.size <= limit.max_bytes, "{:?}", provider);
+      }
+    }
+  }
+
+  #[test]
+  fn user_data_over_the_limit_is_gzipped_where_the_provider_allows() {
+    for provider in Provider::value_variants() {
+      let limit = user_data_limit(provider);
+      let rendered = noise(largest_raw(&limit) + 1);
+      let encoded = encode(rendered.clone(), &limit);
+      assert_eq!(encoded.gzip, limit.gzip, "{:?}", provider);
+      assert_eq!(encoded.raw_size, rendered.len());
+      if limit.gzip {
+        assert_eq!(gunzip(&encoded.payload), rendered);
+        assert!(encoded.size <= limit.max_bytes, "{:?}", provider);
+      } else {
+        assert_eq!(encoded.payload, rendered);
+        assert!(encoded.size > limit.max_bytes, "{:?}", provider);
+      }
+    }
+  }
+
+  #[test]
+  fn user_data_too_big_even_gzipped_stays_over_the_limit() {
+    for provider in Provider::value_variants() {
+      let limit = user_data_limit(provider);
+      if !limit.gzip {
+        continue;
+      }
+      let encoded = encode(noise(limit.max_bytes * 2), &limit);
+      assert!(encoded.gzip, "{:?}", provider);
+      assert!(encoded.size > limit.max_bytes, "{:?}", provider);
+    }
+  }
+
+  #[test]
+  fn base64_limits_count_the_encoded_size() {
+    let limit = user_data_limit(&Provider::Oracle);
+    assert!(limit.base64);
+    let encoded = encode(noise(3), &limit);
+    assert_eq!((encoded.raw_size, encoded.size), (3, 4));
+  }
+
+  fn with_script(script: &str) -> UserData {
+    UserData {
+      script: Some(script.to_string()),
+      ..Default::default()
+    }
+  }
+
+  /// The lines the heredoc writes to /var/lib/fuoco/startup, as Bash reads
+  /// them.
+  fn heredoc_body(rendered: &str) -> Vec<&str> {
+    let mut lines = rendered.lines();
+    let opener = lines
+      .find(|line| line.starts_with("cat > /var/lib/fuoco/startup <<'"))
+      .expect("no heredoc");
+    let delimiter = opener
+      .trim_start_matches("cat > /var/lib/fuoco/startup <<'")
+      .trim_end_matches('\'');
+    lines.take_while(|line| *line != delimiter).collect()
+  }
+
+  #[test]
+  fn startup_script_is_written_whole() {
+    let script = "#!/bin/sh\necho one\necho two";
+    let rendered = with_script(script).render();
+    assert_eq!(heredoc_body(&rendered), script.lines().collect::<Vec<_>>());
+    assert!(rendered.contains("\nFUOCO_STARTUP_EOF\n"));
+  }
+
+  #[test]
+  fn delimiter_in_script_does_not_end_the_heredoc() {
+    let script = "#!/bin/sh\necho before\nFUOCO_STARTUP_EOF\nrm -rf /\n\
+                  FUOCO_STARTUP_EOF_1\necho after\n";
+    let rendered = with_script(script).render();
+    assert_eq!(heredoc_body(&rendered), script.lines().collect::<Vec<_>>());
+    assert!(rendered.contains("<<'FUOCO_STARTUP_EOF_2'\n"));
+  }
+
+  #[test]
+  fn delimiter_inside_a_line_is_kept() {
+    let script = "echo FUOCO_STARTUP_EOF\n  FUOCO_STARTUP_EOF\n";
+    assert_eq!(heredoc_delimiter(script), STARTUP_DELIMITER);
+  }
+}
@@ -0,0 +1,536 @@
+//! Checks of the deploy params against what each provider accepts, run
+//! before anything is created so a bad flag fails at once rather than
+//! halfway through an apply.
+
+use crate::{
+  disk_size_limits, extra_volume_limits, firewall_rule_limit,
+  instance_type_arch, known_instance_types, notify, provider_str,
+  resolve_inbound_rules, resolve_region, user_data_limit,
+  userdata::{self, WriteFile},
+  validate_gcp_location, Arch, DeployParams, Provider,
+};
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+/// Combined base64 size allowed for --file, AWS's user data limit.
+const FILES_MAX_BYTES: usize = 16 * 1024;
+
+/// Every check of `params`, for the provider it names.
+pub fn deploy_params(params: &DeployParams) -> Result<()> {
+  let provider = &params.provider;
+  validate_name(&params.name)?;
+  if let Some(url) = &params.callback_url {
+    notify::validate_url(url, "--callback-url")?;
+  }
+  if let Some(user) = &params.user {
+    validate_user(user)?;
+  }
+  if params.no_public_ip && params.inbound_rules.is_some() {
+    anyhow::bail!(
+      "--inbound-rule opens ports to the internet, which a \
+       --no-public-ip instance cannot be reached from"
+    );
+  }
+  if let Some(image) = &params.image {
+    validate_image(provider, image)?;
+  }
+  require_compartment_id(provider, &params.compartment_id)?;
+  require_spot_support(provider, params.spot)?;
+  require_static_ip_support(provider, params.static_ip)?;
+  require_network_support(provider, &params.vpc_id, params.no_public_ip)?;
+  require_arch_support(provider, params.arch)?;
+  validate_spot_max_price(provider, &params.spot_max_price)?;
+  validate_instance_type(
+    provider,
+    &params.instance_type,
+    params.strict_instance_type,
+  )?;
+  validate_inbound_rule_count(
+    provider,
+    resolve_inbound_rules(params.inbound_rules.as_ref(), params.ssh_port).len(),
+  )?;
+  if let (Provider::GCP, Some((region, _))) =
+    (provider, resolve_region(provider, params.region.as_deref()))
+  {
+    validate_gcp_location(&region, false)?;
+  }
+  validate_key_name(provider, params.key_name.as_deref())?;
+  require_availability_zone_support(
+    provider,
+    params.availability_zone.is_some() || params.az_fallback,
+  )?;
+  validate_metadata(provider, &params.metadata)?;
+  validate_disk_size(provider, params.disk_size)?;
+  validate_extra_volume(
+    provider,
+    params.extra_volume,
+    &params.extra_volume_mount,
+  )?;
+  validate_files(&params.files)?;
+  validate_user_data_size(params)?;
+  warn_on_arch_mismatch(provider, params.arch, &params.instance_type);
+  Ok(())
+}
+
+/// Fail before Terraform does, and with the numbers that matter.
+fn validate_user_data_size(params: &DeployParams) -> Result<()> {
+  let limit = user_data_limit(&params.provider);
+  let user_data = params.encoded_user_data();
+  if user_data.size > limit.max_bytes {
+    anyhow::bail!(
+      "User data is {} bytes{} but {} accepts at most {} bytes; shrink the \
+       startup script, cloud-config or --file contents",
+      user_data.size,
+      if user_data.gzip { " even gzipped" } else { "" },
+      provider_str(&params.provider),
+      limit.max_bytes
+    );
+  }
+  Ok(())
+}
+
+/// Files travel inside the user data, which AWS caps at 16 KiB; check the
+/// total here rather than let the deploy fail halfway.
+fn validate_files(files: &[WriteFile]) -> Result<()> {
+  let total = userdata::encoded_size(files);
+  if total > FILES_MAX_BYTES {
+    anyhow::bail!(
+      "--file contents add up to {} bytes once base64-encoded, over the {} \
+       byte user data budget",
+      total,
+      FILES_MAX_BYTES
+    );
+  }
+  Ok(())
+}
+
+/// The name ends up in resource names on every provider, so hold it to the
+/// strictest rules (GCP) and leave room for the templates' suffixes.
+fn validate_name(name: &str) -> Result<()> {
+  let valid = name.len() <= 40
+    && name.starts_with(|c: char| c.is_ascii_lowercase())
+    && !name.ends_with('-')
+    && name
+      .chars()
+      .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+  if !valid {
+    anyhow::bail!(
+      "--name must start with a lowercase letter, contain only lowercase \
+       letters, digits and '-', not end with '-', and be at most 40 \
+       characters (got {:?})",
+      name
+    );
+  }
+  Ok(())
+}
+
+/// Catch an --image meant for another provider before Terraform does.
+fn validate_image(provider: &Provider, image: &str) -> Result<()> {
+  let is_name_part = |part: &str| {
+    !part.is_empty()
+      && part
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+  };
+  let (valid, expected) = match provider {
+    Provider::AWS => (
+      image.strip_prefix("ami-").is_some_and(|id| {
+        is_name_part(id) && id.chars().all(|c| c.is_ascii_hexdigit())
+      }),
+      "an AMI ID like ami-0123456789abcdef0",
+    ),
+    Provider::GCP => (
+      image.split('/').count() <= 2 && image.split('/').all(is_name_part),
+      "an image family like ubuntu-2204-lts or debian-cloud/debian-12",
+    ),
+    Provider::Hetzner => (
+      !image.is_empty() && !image.contains(char::is_whitespace),
+      "an image name like ubuntu-24.04",
+    ),
+    Provider::Oracle => (
+      image.starts_with("ocid1.image."),
+      "an image OCID like ocid1.image.oc1...",
+    ),
+    Provider::Linode => (
+      image
+        .split_once('/')
+        .is_some_and(|(owner, name)| !owner.is_empty() && !name.is_empty()),
+      "an image ID like linode/ubuntu24.04 or private/12345",
+    ),
+    Provider::Scaleway => (
+      !image.is_empty()
+        && image.chars().all(|c| {
+          c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-'
+        }),
+      "an image label like ubuntu_jammy, or an image ID",
+    ),
+  };
+  if !valid {
+    anyhow::bail!(
+      "--image {:?} is not valid for {:?}: expected {}",
+      image,
+      provider,
+      expected
+    );
+  }
+  Ok(())
+}
+
+/// Keep --user to names useradd accepts everywhere; it also ends up
+/// unquoted in the boot script.
+fn validate_user(user: &str) -> Result<()> {
+  let valid = user.len() <= 32
+    && user.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+    && user.chars().all(|c| {
+      c.is_ascii_lowercase() || c.is_ascii_digit() || "_-".contains(c)
+    });
+  if !valid {
+    anyhow::bail!(
+      "--user must start with a lowercase letter or '_', contain only \
+       lowercase letters, digits, '_' and '-', and be at most 32 characters \
+       (got {:?})",
+      user
+    );
+  }
+  Ok(())
+}
+
+/// OCI resources must live in a compartment, which has no sensible default.
+pub fn require_compartment_id(
+  provider: &Provider,
+  compartment_id: &Option<String>,
+) -> Result<()> {
+  if let Provider::Oracle = provider {
+    if compartment_id.is_none() {
+      anyhow::bail!("--compartment-id is required for the oracle provider");
+    }
+  }
+  Ok(())
+}
+
+/// Hetzner, Linode and Scaleway have no interruptible pricing tier, so
+/// refuse rather than ignore.
+fn require_spot_support(provider: &Provider, spot: bool) -> Result<()> {
+  if let (Provider::Hetzner | Provider::Linode | Provider::Scaleway, true) =
+    (provider, spot)
+  {
+    anyhow::bail!(
+      "--spot is not supported by the {} provider",
+      provider_str(provider)
+    );
+  }
+  Ok(())
+}
+
+/// Only the AWS, GCP, Hetzner and Scaleway templates reserve an address, so
+/// refuse rather than hand out an IP that changes.
+fn require_static_ip_support(
+  provider: &Provider,
+  static_ip: bool,
+) -> Result<()> {
+  if let (Provider::Oracle | Provider::Linode, true) = (provider, static_ip) {
+    anyhow::bail!(
+      "--static-ip is not supported by the {} provider",
+      provider_str(provider)
+    );
+  }
+  Ok(())
+}
+
+/// The Hetzner, Linode and Scaleway templates have no notion of a private
+/// network to join, so refuse rather than ignore.
+fn require_network_support(
+  provider: &Provider,
+  vpc_id: &Option<String>,
+  no_public_ip: bool,
+) -> Result<()> {
+  if let Provider::Hetzner | Provider::Linode | Provider::Scaleway = provider {
+    if vpc_id.is_some() {
+      anyhow::bail!(
+        "--vpc-id/--subnet-id are not supported by the {} provider",
+        provider_str(provider)
+      );
+    }
+    if no_public_ip {
+      anyhow::bail!(
+        "--no-public-ip is not supported by the {} provider",
+        provider_str(provider)
+      );
+    }
+  }
+  Ok(())
+}
+
+/// Linode only sells x86_64 instances.
+fn require_arch_support(provider: &Provider, arch: Option<Arch>) -> Result<()> {
+  if let (Provider::Linode, Some(Arch::Arm64)) = (provider, arch) {
+    anyhow::bail!("--arch arm64 is not supported by the linode provider");
+  }
+  Ok(())
+}
+
+/// The instance catalogs are large and move fast, so an unknown type is only
+/// a warning unless the user asked for strictness.
+fn validate_instance_type(
+  provider: &Provider,
+  instance_type: &Option<String>,
+  strict: bool,
+) -> Result<()> {
+  let Some(instance_type) = instance_type else {
+    return Ok(());
+  };
+  if known_instance_types(provider).contains(&instance_type.as_str()) {
+    return Ok(());
+  }
+  if strict {
+    anyhow::bail!(
+      "Unknown instance type {} for {:?} (drop --strict-instance-type to \
+       deploy it anyway)",
+      instance_type,
+      provider
+    );
+  }
+  log::warn!(
+    "instance type {} is not a known {:?} type; deploying anyway \
+     (use --strict-instance-type to make this an error)",
+    instance_type,
+    provider
+  );
+  Ok(())
+}
+
+/// A firewall over its provider's rule limit only fails once Terraform is
+/// creating it, with the instance already up.
+fn validate_inbound_rule_count(
+  provider: &Provider,
+  inbound: usize,
+) -> Result<()> {
+  let Some((max, egress)) = firewall_rule_limit(provider) else {
+    return Ok(());
+  };
+  if inbound + egress > max {
+    anyhow::bail!(
+      "too many --inbound-rule: the {} firewall holds {} rules, outbound \
+       ones included, so at most {} inbound; got {}",
+      provider_str(provider),
+      max,
+      max - egress,
+      inbound
+    );
+  }
+  Ok(())
+}
+
+/// Only AWS lets us cap the spot price; GCP and OCI bill a fixed discount.
+fn validate_spot_max_price(
+  provider: &Provider,
+  spot_max_price: &Option<String>,
+) -> Result<()> {
+  let Some(spot_max_price) = spot_max_price else {
+    return Ok(());
+  };
+  if !matches!(provider, Provider::AWS) {
+    anyhow::bail!("--spot-max-price is only supported by the aws provider");
+  }
+  match spot_max_price.parse::<f64>() {
+    Ok(price) if price > 0.0 => Ok(()),
+    _ => anyhow::bail!(
+      "--spot-max-price must be a positive USD amount (got {:?})",
+      spot_max_price
+    ),
+  }
+}
+
+/// Only the AWS template places the instance in a zone of its choosing.
+fn require_availability_zone_support(
+  provider: &Provider,
+  requested: bool,
+) -> Result<()> {
+  if requested && !matches!(provider, Provider::AWS) {
+    anyhow::bail!(
+      "--availability-zone and --az-fallback are only supported on AWS, \
+       not {:?}",
+      provider
+    );
+  }
+  Ok(())
+}
+
+/// Only AWS and Hetzner templates launch with a key registered by name.
+fn validate_key_name(
+  provider: &Provider,
+  key_name: Option<&str>,
+) -> Result<()> {
+  match (provider, key_name) {
+    (_, None) | (Provider::AWS | Provider::Hetzner, Some(_)) => Ok(()),
+    (_, Some(_)) => anyhow::bail!(
+      "--key-name is not supported by the {:?} provider: it has no named \
+       key pairs; pass --ssh-public-key-path instead",
+      provider
+    ),
+  }
+}
+
+/// Hold --metadata to the rules of whatever the provider keeps it in, and
+/// keep it off the keys the templates set themselves.
+fn validate_metadata(
+  provider: &Provider,
+  metadata: &BTreeMap<String, String>,
+) -> Result<()> {
+  if metadata.is_empty() {
+    return Ok(());
+  }
+  let reserved: &[&str] = match provider {
+    Provider::AWS => &["Name"],
+    Provider::GCP => &["ssh-keys", "user-data", "startup-script"],
+    Provider::Oracle => &["ssh_authorized_keys", "user_data"],
+    Provider::Scaleway => &["cloud-init"],
+    Provider::Hetzner => &[],
+    Provider::Linode => anyhow::bail!(
+      "--metadata is not supported by the Linode provider: Linode has no \
+       custom instance metadata; use --tag"
+    ),
+  };
+  let label = |s: &str| {
+    s.len() <= 63
+      && s
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+      && (s.is_empty()
+        || s.starts_with(|c: char| c.is_ascii_alphanumeric())
+          && s.ends_with(|c: char| c.is_ascii_alphanumeric()))
+  };
+  for (key, value) in metadata {
+    if reserved.contains(&key.as_str()) {
+      anyhow::bail!("--metadata {}: fuoco sets this key itself", key);
+    }
+    let rule = match provider {
+      Provider::AWS
+        if key.len() > 128
+          || value.len() > 256
+          || key.contains(['/', ' '])
+          || key == "."
+          || key == ".." =>
+      {
+        "AWS tag keys are at most 128 characters without '/' or spaces, \
+         values at most 256"
+      }
+      Provider::GCP
+        if key.len() > 128
+          || !key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+      {
+        "GCP metadata keys are at most 128 letters, digits, '-' and '_'"
+      }
+      Provider::GCP if value.len() > 256 * 1024 => {
+        "GCP metadata values are at most 256 KiB"
+      }
+      Provider::Hetzner if key.is_empty() || !label(key) || !label(value) => {
+        "Hetzner labels are at most 63 letters, digits, '.', '_' and '-', \
+         starting and ending with a letter or digit"
+      }
+      _ => continue,
+    };
+    anyhow::bail!("--metadata {}: {}", key, rule);
+  }
+  let total: usize = metadata.iter().map(|(k, v)| k.len() + v.len()).sum();
+  let limit = match provider {
+    Provider::GCP => Some(512 * 1024),
+    // Shared with the user data
+    Provider::Oracle => Some(32 * 1024),
+    _ => None,
+  };
+  if let Some(limit) = limit.filter(|limit| total > *limit) {
+    anyhow::bail!(
+      "--metadata adds up to {} bytes, over the {} bytes {:?} allows",
+      total,
+      limit,
+      provider
+    );
+  }
+  Ok(())
+}
+
+/// Check a requested root disk size against what the provider accepts.
+fn validate_disk_size(
+  provider: &Provider,
+  disk_size: Option<u32>,
+) -> Result<()> {
+  let Some(disk_size) = disk_size else {
+    return Ok(());
+  };
+  let Some((min, max)) = disk_size_limits(provider) else {
+    anyhow::bail!(
+      "--disk-size is not supported by the {:?} provider: the disk size is \
+       fixed by the instance type",
+      provider
+    );
+  };
+  if disk_size < min || disk_size > max {
+    anyhow::bail!(
+      "--disk-size {} is out of range for {:?} (must be between {} and {} GiB)",
+      disk_size,
+      provider,
+      min,
+      max
+    );
+  }
+  Ok(())
+}
+
+/// Check the extra volume size and that the mount point is safe to splice
+/// into the boot script and /etc/fstab.
+fn validate_extra_volume(
+  provider: &Provider,
+  extra_volume: Option<u32>,
+  mount: &str,
+) -> Result<()> {
+  let Some(extra_volume) = extra_volume else {
+    return Ok(());
+  };
+  let (min, max) = extra_volume_limits(provider);
+  if extra_volume < min || extra_volume > max {
+    anyhow::bail!(
+      "--extra-volume {} is out of range for {:?} (must be between {} and {} GiB)",
+      extra_volume,
+      provider,
+      min,
+      max
+    );
+  }
+  let valid_mount = mount.starts_with('/')
+    && mount.len() > 1
+    && mount
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || "/_.-".contains(c));
+  if !valid_mount {
+    anyhow::bail!(
+      "--extra-volume-mount must be an absolute path made of letters, \
+       digits, '/', '_', '.' and '-' (got {:?})",
+      mount
+    );
+  }
+  Ok(())
+}
+
+/// An explicit instance type wins over --arch, so make a conflict obvious.
+fn warn_on_arch_mismatch(
+  provider: &Provider,
+  arch: Option<Arch>,
+  instance_type: &Option<String>,
+) {
+  if let (Some(requested), Some(instance_type)) = (arch, instance_type) {
+    if let Some(actual) = instance_type_arch(provider, instance_type) {
+      if actual != requested {
+        log::warn!(
+          "instance type {} is {} but --arch {} was requested; \
+           the image will follow --arch and the deploy will likely fail",
+          instance_type,
+          actual,
+          requested
+        );
+      }
+    }
+  }
+}
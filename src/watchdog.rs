@@ -0,0 +1,340 @@
+//! Deadlines for Terraform runs, which can hang for a long time on a zone
+//! out of capacity or a resource stuck deleting.
+
+use crate::progress;
+use anyhow::Result;
+use std::{
+  fmt, fs,
+  path::Path,
+  process,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{self, RecvTimeoutError},
+    Arc,
+  },
+  thread,
+  time::{Duration, Instant},
+};
+
+/// How long a stopped Terraform gets to write its state and release the
+/// lock before fuoco carries on.
+const STOP_GRACE: Duration = Duration::from_secs(60);
+
+/// How often a run checks its `KillHandle`.
+pub const KILL_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The deadline passed and Terraform was stopped.
+#[derive(Debug)]
+pub struct TimedOut {
+  pub what: &'static str,
+  pub after: Duration,
+}
+
+impl fmt::Display for TimedOut {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} did not finish within {} and was stopped",
+      self.what,
+      crate::format_elapsed(self.after)
+    )
+  }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// The run was stopped through its `KillHandle`.
+#[derive(Debug)]
+pub struct Killed {
+  pub what: &'static str,
+}
+
+impl fmt::Display for Killed {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} was stopped: the deployment was cancelled", self.what)
+  }
+}
+
+impl std::error::Error for Killed {}
+
+/// Stops the Terraform of one deployment from another thread: the run
+/// going on, and any it would start after. Clones share the same switch.
+#[derive(Clone, Debug, Default)]
+pub struct KillHandle(Arc<AtomicBool>);
+
+impl KillHandle {
+  pub fn kill(&self) {
+    self.0.store(true, Ordering::SeqCst);
+  }
+
+  pub fn killed(&self) -> bool {
+    self.0.load(Ordering::SeqCst)
+  }
+
+  /// `Killed` once `kill` was called, for a caller about to start another
+  /// Terraform run.
+  pub fn check(&self, what: &'static str) -> Result<()> {
+    if self.killed() {
+      return Err(Killed { what }.into());
+    }
+    Ok(())
+  }
+}
+
+/// Run `f` on a worker thread, showing progress while it runs. Once
+/// `timeout` expires, stop Terraform and fail with `TimedOut`. Without a
+/// timeout `f` just runs here.
+///
+/// `f` is handed a `KillHandle` that is killed once the run is given up
+/// on, to check before it starts any more Terraform: a retry must not
+/// outlive the run it belongs to.
+pub fn run<T: Send + 'static>(
+  what: &'static str,
+  timeout: Option<Duration>,
+  f: impl FnOnce(&KillHandle) -> Result<T> + Send + 'static,
+) -> Result<T> {
+  let _progress = progress::phase(what);
+  let Some(timeout) = timeout else {
+    return f(&KillHandle::default());
+  };
+  watch(what, Some(timeout), None, stop_terraform, f)
+}
+
+/// `run` for the Terraform running in `workspace`, which alone is stopped
+/// at the deadline or as soon as `kill` is used; the Terraform of other
+/// deployments in the process goes on.
+pub fn run_in<T: Send + 'static>(
+  what: &'static str,
+  workspace: &Path,
+  timeout: Option<Duration>,
+  kill: &KillHandle,
+  f: impl FnOnce(&KillHandle) -> Result<T> + Send + 'static,
+) -> Result<T> {
+  let _progress = progress::phase(what);
+  kill.check(what)?;
+  watch(
+    what,
+    timeout,
+    Some(kill),
+    || stop_terraform_in(workspace),
+    f,
+  )
+}
+
+/// Run `f` on a worker thread until it returns, the timeout expires or
+/// `kill` is used, calling `stop` in the last two cases.
+fn watch<T: Send + 'static>(
+  what: &'static str,
+  timeout: Option<Duration>,
+  kill: Option<&KillHandle>,
+  stop: impl Fn(),
+  f: impl FnOnce(&KillHandle) -> Result<T> + Send + 'static,
+) -> Result<T> {
+  // Killed before `stop`, so `f` starts nothing once it is stopped
+  let stopped = KillHandle::default();
+  let worker = stopped.clone();
+  let (tx, rx) = mpsc::channel();
+  thread::spawn(move || {
+    let _ = tx.send(f(&worker));
+  });
+  let started = Instant::now();
+  loop {
+    let remaining =
+      timeout.map(|timeout| timeout.saturating_sub(started.elapsed()));
+    let wait = match (remaining, kill) {
+      (Some(remaining), Some(_)) => remaining.min(KILL_CHECK_INTERVAL),
+      (Some(remaining), None) => remaining,
+      (None, _) => KILL_CHECK_INTERVAL,
+    };
+    match rx.recv_timeout(wait) {
+      Ok(result) => return result,
+      Err(RecvTimeoutError::Timeout)
+        if kill.is_some_and(KillHandle::killed) =>
+      {
+        stopped.kill();
+        stop();
+        let _ = rx.recv_timeout(STOP_GRACE);
+        return Err(Killed { what }.into());
+      }
+      // Not at the deadline yet
+      Err(RecvTimeoutError::Timeout)
+        if timeout.is_none_or(|timeout| started.elapsed() < timeout) => {}
+      Err(RecvTimeoutError::Timeout) => {
+        stopped.kill();
+        stop();
+        let _ = rx.recv_timeout(STOP_GRACE);
+        return Err(
+          TimedOut {
+            what,
+            after: timeout.unwrap_or_default(),
+          }
+          .into(),
+        );
+      }
+      Err(RecvTimeoutError::Disconnected) => {
+        anyhow::bail!("{} stopped unexpectedly", what)
+      }
+    }
+  }
+}
+
+/// Terraform runs as our child; a signal sent to fuoco alone (SIGTERM)
+/// would otherwise leave it running.
+pub fn stop_terraform() {
+  let _ = process::Command::new("pkill")
+    .args(["-TERM", "-P", &process::id().to_string()])
+    .status();
+}
+
+/// Stop the Terraform our child running in `workspace`, told apart from
+/// the others by its working directory or its `-chdir`. Without `/proc`
+/// to tell them apart, every child is stopped.
+pub fn stop_terraform_in(workspace: &Path) {
+  let Ok(processes) = fs::read_dir("/proc") else {
+    return stop_terraform();
+  };
+  let workspace =
+    fs::canonicalize(workspace).unwrap_or_else(|_| workspace.to_path_buf());
+  let chdir = format!("-chdir={}", workspace.display());
+  let parent = process::id().to_string();
+  for entry in processes.flatten() {
+    let dir = entry.path();
+    // The parent PID follows the state, after the parenthesized name
+    let Ok(stat) = fs::read_to_string(dir.join("stat")) else {
+      continue;
+    };
+    let ppid = stat
+      .rsplit_once(')')
+      .and_then(|(_, rest)| rest.split_whitespace().nth(1));
+    if ppid != Some(parent.as_str()) {
+      continue;
+    }
+    let in_workspace = fs::read_link(dir.join("cwd"))
+      .is_ok_and(|cwd| cwd == workspace)
+      || fs::read(dir.join("cmdline")).is_ok_and(|cmdline| {
+        cmdline
+          .split(|b| *b == 0)
+          .any(|arg| arg == chdir.as_bytes())
+      });
+    if in_workspace {
+      let _ = process::Command::new("kill")
+        .args(["-TERM", &entry.file_name().to_string_lossy()])
+        .status();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::retry;
+  use std::{
+    env,
+    os::unix::process::ExitStatusExt,
+    path::PathBuf,
+    process::{Child, Command},
+    sync::atomic::AtomicUsize,
+  };
+
+  fn workspace(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!(
+      "fuoco-watchdog-{}-{}",
+      name,
+      process::id()
+    ));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  /// A stand-in for Terraform, running in `workspace` until stopped.
+  fn terraform_in(workspace: &Path) -> Child {
+    Command::new("sleep")
+      .arg("30")
+      .current_dir(workspace)
+      .spawn()
+      .unwrap()
+  }
+
+  #[test]
+  fn stops_only_the_terraform_of_its_workspace() {
+    let (ours, theirs) = (workspace("ours"), workspace("theirs"));
+    let mut stopped = terraform_in(&ours);
+    let mut spared = terraform_in(&theirs);
+    stop_terraform_in(&ours);
+    assert_eq!(stopped.wait().unwrap().signal(), Some(15));
+    assert!(spared.try_wait().unwrap().is_none());
+    spared.kill().unwrap();
+    spared.wait().unwrap();
+    let _ = fs::remove_dir_all(ours);
+    let _ = fs::remove_dir_all(theirs);
+  }
+
+  #[test]
+  fn kill_stops_the_run() {
+    let dir = workspace("kill");
+    let kill = KillHandle::default();
+    let killer = kill.clone();
+    thread::spawn(move || {
+      thread::sleep(Duration::from_millis(300));
+      killer.kill();
+    });
+    let started = Instant::now();
+    let terraform_dir = dir.clone();
+    let err = run_in("terraform apply", &dir, None, &kill, move |_| {
+      Ok(terraform_in(&terraform_dir).wait()?)
+    })
+    .unwrap_err();
+    assert!(err.is::<Killed>(), "{}", err);
+    assert!(started.elapsed() < Duration::from_secs(10));
+    // Nothing starts once killed
+    let err = run_in("terraform apply", &dir, None, &kill, |_| Ok(()));
+    assert!(err.unwrap_err().is::<Killed>());
+    let _ = fs::remove_dir_all(dir);
+  }
+
+  #[test]
+  fn timeout_stops_the_run() {
+    let dir = workspace("timeout");
+    let timeout = Some(Duration::from_millis(300));
+    let terraform_dir = dir.clone();
+    let err = run_in(
+      "terraform apply",
+      &dir,
+      timeout,
+      &KillHandle::default(),
+      move |_| Ok(terraform_in(&terraform_dir).wait()?),
+    )
+    .unwrap_err();
+    assert!(err.is::<TimedOut>(), "{}", err);
+    let _ = fs::remove_dir_all(dir);
+  }
+
+  #[test]
+  fn timeout_stops_the_retries() {
+    let dir = workspace("retries");
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let counted = attempts.clone();
+    let started = Instant::now();
+    // An apply that fails transiently until long past the deadline
+    let err = run_in(
+      "terraform apply",
+      &dir,
+      Some(Duration::from_millis(300)),
+      &KillHandle::default(),
+      move |stopped| {
+        retry::with_retries("terraform apply", 5, stopped, || -> Result<()> {
+          counted.fetch_add(1, Ordering::SeqCst);
+          thread::sleep(Duration::from_millis(500));
+          anyhow::bail!("503 Service Unavailable")
+        })
+      },
+    )
+    .unwrap_err();
+    assert!(err.is::<TimedOut>(), "{}", err);
+    // The backoff is cut short and no retry starts once timed out
+    assert!(started.elapsed() < Duration::from_secs(5));
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    let _ = fs::remove_dir_all(dir);
+  }
+}
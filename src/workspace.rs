@@ -0,0 +1,173 @@
+//! Which Terraform workspace a deployment gets. atar keys its workspaces on
+//! the template's directory alone, so fuoco hands it a copy of the template
+//! in a directory of its own for each template, provider, deployment name
+//! and region: two deployments only share state when all four match.
+
+use crate::workspace_dir;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+  env, fs,
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+/// Written next to the staged template: which deployment it stands for.
+const IDENTITY_FILE: &str = "deployment.json";
+
+#[derive(Serialize)]
+pub struct Identity<'a> {
+  /// The template the user picked, not the staged copy.
+  pub template: &'a Path,
+  pub provider: &'a str,
+  pub name: &'a str,
+  /// The resolved region (for GCP, the zone).
+  pub region: &'a str,
+}
+
+impl Identity<'_> {
+  /// The template path to give atar: `main.tf` in a directory named after
+  /// the hash of the identity, next to atar's workspaces.
+  pub fn template(&self) -> PathBuf {
+    let mut hasher = Sha256::new();
+    let dir = self.template.parent().unwrap_or(self.template);
+    for field in [
+      &*dir.to_string_lossy(),
+      self.provider,
+      self.name,
+      self.region,
+    ] {
+      hasher.update(field.as_bytes());
+      hasher.update(b"\0");
+    }
+    templates_dir()
+      .join(format!("{:x}", hasher.finalize()))
+      .join("main.tf")
+  }
+
+  /// Copy the files of the template's directory to where `template` points,
+  /// and record the identity beside them.
+  pub fn stage(&self) -> Result<PathBuf> {
+    let staged = self.template();
+    let staged_dir = staged.parent().context("Invalid staged template path")?;
+    fs::create_dir_all(staged_dir)
+      .with_context(|| format!("Failed to create {:?}", staged_dir))?;
+    let source_dir = self
+      .template
+      .parent()
+      .context("Cannot determine the template directory")?;
+    for entry in fs::read_dir(source_dir)
+      .with_context(|| format!("Failed to read {:?}", source_dir))?
+    {
+      let path = entry?.path();
+      if path.is_file() {
+        let target = staged_dir.join(path.file_name().unwrap_or_default());
+        fs::copy(&path, &target)
+          .with_context(|| format!("Failed to copy {:?}", path))?;
+      }
+    }
+    // The template may not be named main.tf in its own directory
+    fs::copy(self.template, &staged)
+      .with_context(|| format!("Failed to copy {:?}", self.template))?;
+    fs::write(
+      staged_dir.join(IDENTITY_FILE),
+      serde_json::to_string_pretty(self)?,
+    )
+    .context("Failed to record the deployment identity")?;
+    Ok(staged)
+  }
+
+  /// For `undeploy`: the staged template, unless only a workspace from a
+  /// fuoco that keyed workspaces on the template directory exists, in
+  /// which case the template itself, so that deployment can be destroyed.
+  pub fn find(&self) -> Result<PathBuf> {
+    let staged = self.template();
+    if workspace_dir(&staged)?.exists() {
+      // Staged by the deploy: keep its record
+      if staged.exists() {
+        return Ok(staged);
+      }
+    } else {
+      let legacy = workspace_dir(self.template)?;
+      if legacy.exists() {
+        log::warn!(
+          "no workspace for this deployment; using {:?}, which an older \
+           fuoco shared between all deployments of this template",
+          legacy
+        );
+        return Ok(self.template.to_path_buf());
+      }
+    }
+    self.stage()
+  }
+}
+
+/// An identity read back from beside its staged template.
+#[derive(Deserialize)]
+pub struct Record {
+  pub template: PathBuf,
+  pub provider: String,
+  pub name: String,
+  pub region: String,
+}
+
+/// Every staged template with the identity recorded beside it.
+pub fn records() -> Vec<(PathBuf, Record)> {
+  let Ok(entries) = fs::read_dir(templates_dir()) else {
+    return Vec::new();
+  };
+  entries
+    .filter_map(|entry| {
+      let dir = entry.ok()?.path();
+      let content = fs::read_to_string(dir.join(IDENTITY_FILE)).ok()?;
+      let record = serde_json::from_str(&content).ok()?;
+      Some((dir.join("main.tf"), record))
+    })
+    .collect()
+}
+
+/// Where the staged templates are, one directory each.
+pub fn templates_dir() -> PathBuf {
+  env::temp_dir().join("fuoco").join("templates")
+}
+
+/// Add how long each phase of `command` (deploy or undeploy) took to the
+/// identity recorded beside a staged template, for later inspection.
+pub fn record_timings(
+  staged: &Path,
+  command: &str,
+  timings: Value,
+) -> Result<()> {
+  update_record(staged, |record| record["timings"][command] = timings)
+}
+
+/// Add how the region was picked: the round-trip time measured to it as
+/// the closest, or the seed it was drawn with at random.
+pub fn record_region_choice(
+  staged: &Path,
+  rtt: Option<Duration>,
+  seed: Option<u64>,
+) -> Result<()> {
+  update_record(staged, |record| {
+    if let Some(rtt) = rtt {
+      record["region_rtt_ms"] = (rtt.as_millis() as u64).into();
+    }
+    // A string: JSON readers like jq would round a large number
+    if let Some(seed) = seed {
+      record["region_seed"] = seed.to_string().into();
+    }
+  })
+}
+
+fn update_record(staged: &Path, update: impl FnOnce(&mut Value)) -> Result<()> {
+  let path = staged.with_file_name(IDENTITY_FILE);
+  let content = fs::read_to_string(&path)
+    .with_context(|| format!("Failed to read {:?}", path))?;
+  let mut record: Value = serde_json::from_str(&content)
+    .with_context(|| format!("Failed to parse {:?}", path))?;
+  update(&mut record);
+  fs::write(&path, serde_json::to_string_pretty(&record)?)
+    .with_context(|| format!("Failed to write {:?}", path))
+}
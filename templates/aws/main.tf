@@ -1,9 +1,93 @@
 variable "region" {}
 variable "instance_type" {}
-variable "script_path" {}
-variable "ssh_public_key_path" {
+variable "name" { default = "fuoco-ephemeral" }
+// Login user; fuoco creates it when --user is given
+variable "user" { default = "ec2-user" }
+// Set on boot by fuoco through cloud-init; here for templates that need it
+variable "hostname" { default = null }
+variable "arch" {
   type        = string
-  description = "Path to SSH public key file"
+  description = "CPU architecture of the instance (x86_64 or arm64)"
+  default     = "x86_64"
+}
+variable "image" {
+  type        = string
+  description = "AMI ID to boot (null picks the latest Amazon Linux 2023)"
+  default     = null
+}
+variable "tags" {
+  type        = map(string)
+  description = "Extra tags for the instance (--tag, --labels-from-git)"
+  default     = {}
+}
+variable "metadata" {
+  type        = map(string)
+  description = "Instance tags readable from the instance metadata service (--metadata)"
+  default     = {}
+}
+variable "user_data" {
+  type        = string
+  description = "Boot script rendered by fuoco (empty for none)"
+  default     = ""
+}
+variable "user_data_base64" {
+  type        = bool
+  description = "user_data is gzipped and base64-encoded"
+  default     = false
+}
+variable "extra_volume_size" {
+  type        = number
+  description = "Size in GiB of an extra EBS volume (null for none)"
+  default     = null
+}
+variable "disk_size" {
+  type        = number
+  description = "Root volume size in GiB (null keeps the AMI default)"
+  default     = null
+}
+variable "use_spot" {
+  type        = bool
+  description = "Launch the instance as a one-time spot request"
+  default     = false
+}
+variable "spot_max_price" {
+  type        = string
+  description = "Maximum hourly spot price in USD (null caps at on-demand)"
+  default     = null
+}
+variable "vpc_id" {
+  type        = string
+  description = "Existing VPC to deploy into (null uses the default VPC)"
+  default     = null
+}
+variable "subnet_id" {
+  type        = string
+  description = "Subnet of vpc_id for the instance (null lets AWS pick)"
+  default     = null
+}
+variable "availability_zone" {
+  type        = string
+  description = "Availability zone to launch in (null lets AWS pick)"
+  default     = null
+}
+variable "assign_public_ip" {
+  type        = bool
+  description = "Give the instance a public IP (false for private-only)"
+  default     = true
+}
+variable "static_ip" {
+  type        = bool
+  description = "Attach an Elastic IP, released on destroy"
+  default     = false
+}
+// Public keys to authorize, read from disk by fuoco (not by Terraform)
+variable "ssh_public_keys" {
+  type    = list(string)
+  default = []
+}
+variable "key_name" {
+  type        = string
+  description = "Existing key pair to launch with, instead of uploading ssh_public_keys"
   default     = null
 }
 variable "inbound_rules" {
@@ -19,75 +103,34 @@ provider "aws" {
 }
 
 locals {
-  # List of fallback public key paths to auto-detect from
-  fallback_key_paths = [
-    pathexpand("~/.ssh/id_rsa.pub"),
-    pathexpand("~/.ssh/id_ed25519.pub"),
-    pathexpand("~/.ssh/id_ecdsa.pub")
-  ]
-
-  # Determine the first available fallback key path
-  auto_detected_key_path = (
-    length([
-      for path in local.fallback_key_paths : path if fileexists(path)
-    ]) > 0 ?
-    [
-      for path in local.fallback_key_paths : path if fileexists(path)
-    ][0] :
-    null
-  )
-
-  # Normalize the user-provided path: treat "none" or null as no input
-  normalized_ssh_public_key_path = (
-    var.ssh_public_key_path == null || var.ssh_public_key_path == "none"
-    ? null
-    : pathexpand(var.ssh_public_key_path)
-  )
-
-  # Final SSH public key path to use
-  effective_ssh_public_key_path = (
-    local.normalized_ssh_public_key_path != null
-    ? local.normalized_ssh_public_key_path
-    : local.auto_detected_key_path
-  )
-}
-
-locals {
-  # List of known ARM64-compatible instance types (add more if needed)
-  arm64_instance_types = [
-    "a1.medium", "a1.large", "a1.xlarge", "a1.2xlarge", "a1.4xlarge", "a1.metal",
-    "t4g.nano", "t4g.micro", "t4g.small", "t4g.medium", "t4g.large", "t4g.xlarge", "t4g.2xlarge",
-    "m6g.medium", "m6g.large", "m6g.xlarge", "m6g.2xlarge", "m6g.4xlarge", "m6g.8xlarge", "m6g.12xlarge", "m6g.16xlarge"
-  ]
-
-  # Infer architecture from instance type
-  arch = contains(local.arm64_instance_types, var.instance_type) ? "arm64" : "x86_64"
-
   # Pick correct SSM parameter name for the AMI
-  ami_ssm_param = local.arch == "arm64" ? "/aws/service/ami-amazon-linux-latest/al2023-ami-kernel-default-arm64" : "/aws/service/ami-amazon-linux-latest/al2023-ami-kernel-default-x86_64"
+  ami_ssm_param = var.arch == "arm64" ? "/aws/service/ami-amazon-linux-latest/al2023-ami-kernel-default-arm64" : "/aws/service/ami-amazon-linux-latest/al2023-ami-kernel-default-x86_64"
 }
 
-# Resolve AMI from SSM Parameter Store
+# Resolve AMI from SSM Parameter Store unless --image pins one
 data "aws_ssm_parameter" "ami" {
-  name = local.ami_ssm_param
+  count = var.image == null ? 1 : 0
+  name  = local.ami_ssm_param
 }
 
-# Use default VPC
+# Use default VPC unless --vpc-id names one
 data "aws_vpc" "default" {
+  count   = var.vpc_id == null ? 1 : 0
   default = true
 }
 
 resource "aws_key_pair" "deployer" {
-  count      = local.effective_ssh_public_key_path != null ? 1 : 0
-  key_name   = "fuoco-ephemeral-key"
-  public_key = file(local.effective_ssh_public_key_path)
+  count      = length(var.ssh_public_keys) > 0 ? 1 : 0
+  key_name   = "${var.name}-key"
+  // A key pair holds one key; fuoco's boot script installs any others
+  public_key = var.ssh_public_keys[0]
 }
 
 # Security group allowing all traffic (for development/testing)
 resource "aws_security_group" "allow_all" {
-  name        = "fuoco-ephemeral-sg"
+  name        = "${var.name}-sg"
   description = "Allow all inbound and outbound traffic"
-  vpc_id      = data.aws_vpc.default.id
+  vpc_id      = var.vpc_id != null ? var.vpc_id : data.aws_vpc.default[0].id
 
   dynamic "ingress" {
     for_each = var.inbound_rules
@@ -107,38 +150,130 @@ resource "aws_security_group" "allow_all" {
   }
 
   tags = {
-    Name = "fuoco-ephemeral-sg"
+    Name = "${var.name}-sg"
   }
 }
 
 # EC2 Instance
 resource "aws_instance" "vm" {
-  ami                         = data.aws_ssm_parameter.ami.value
+  ami                         = var.image != null ? var.image : data.aws_ssm_parameter.ami[0].value
   instance_type               = var.instance_type
-  user_data                   = var.script_path != "" ? var.script_path : null
+  user_data                   = var.user_data != "" && !var.user_data_base64 ? var.user_data : null
+  user_data_base64            = var.user_data_base64 ? var.user_data : null
   vpc_security_group_ids      = [aws_security_group.allow_all.id]
-  key_name                    = aws_key_pair.deployer[0].key_name
+  subnet_id                   = var.subnet_id
+  // In the default VPC, AWS launches into the zone's default subnet
+  availability_zone           = var.availability_zone
+  // Subnets outside the default VPC rarely assign public IPs by default
+  associate_public_ip_address = !var.assign_public_ip ? false : var.subnet_id != null ? true : null
+  key_name                    = var.key_name != null ? var.key_name : length(aws_key_pair.deployer) > 0 ? aws_key_pair.deployer[0].key_name : null
+
+  dynamic "root_block_device" {
+    for_each = var.disk_size != null ? [1] : []
+    content {
+      volume_size = var.disk_size
+      volume_type = "gp3"
+    }
+  }
+
+  dynamic "instance_market_options" {
+    for_each = var.use_spot ? [1] : []
+    content {
+      market_type = "spot"
+      spot_options {
+        instance_interruption_behavior = "terminate"
+        spot_instance_type             = "one-time"
+        max_price                      = var.spot_max_price
+      }
+    }
+  }
+
+  // EC2 has no free-form metadata: --metadata becomes instance tags that
+  // the instance can read at /latest/meta-data/tags/instance/<key>
+  dynamic "metadata_options" {
+    for_each = length(var.metadata) > 0 ? [1] : []
+    content {
+      http_endpoint          = "enabled"
+      instance_metadata_tags = "enabled"
+    }
+  }
+
+  tags = merge(var.metadata, var.tags, {
+    Name = var.name
+  })
+}
+
+# Elastic IP; destroying it releases the address, so it stops billing
+resource "aws_eip" "static" {
+  count  = var.static_ip ? 1 : 0
+  domain = "vpc"
 
   tags = {
-    Name = "fuoco-ephemeral"
+    Name = "${var.name}-ip"
   }
 }
 
+resource "aws_eip_association" "static" {
+  count         = var.static_ip ? 1 : 0
+  allocation_id = aws_eip.static[0].id
+  instance_id   = aws_instance.vm.id
+}
+
+# Extra data volume, mounted by the fuoco boot script
+resource "aws_ebs_volume" "extra" {
+  count             = var.extra_volume_size != null ? 1 : 0
+  availability_zone = aws_instance.vm.availability_zone
+  size              = var.extra_volume_size
+  type              = "gp3"
+
+  tags = {
+    Name = "${var.name}-extra"
+  }
+}
+
+resource "aws_volume_attachment" "extra" {
+  count       = var.extra_volume_size != null ? 1 : 0
+  device_name = "/dev/sdf"
+  volume_id   = aws_ebs_volume.extra[0].id
+  instance_id = aws_instance.vm.id
+}
+
 # Outputs
 output "public_ip" {
-  value = aws_instance.vm.public_ip
+  value = try(aws_eip.static[0].public_ip, aws_instance.vm.public_ip)
+}
+
+output "private_ip" {
+  value = aws_instance.vm.private_ip
 }
 
 output "region" {
   value = var.region
 }
 
+output "availability_zone" {
+  value = aws_instance.vm.availability_zone
+}
+
 output "ssh_key_used" {
-  value       = local.effective_ssh_public_key_path
-  description = "Path to the SSH public key used for the instance"
+  value       = try(aws_key_pair.deployer[0].fingerprint, null)
+  description = "Fingerprint of the SSH key pair used for the instance"
 }
 
 output "inbound_rules" {
   value       = var.inbound_rules
   description = "List of inbound rules applied to the security group"
 }
+
+output "instance_lifecycle" {
+  value = var.use_spot ? "spot" : "on-demand"
+}
+
+output "extra_volume_id" {
+  value       = try(aws_ebs_volume.extra[0].id, null)
+  description = "ID of the extra EBS volume, if any"
+}
+
+output "ssh_user" {
+  value = var.user
+}
@@ -1,38 +1,186 @@
-variable "project" {}
+// Falls back to GOOGLE_CLOUD_PROJECT / the gcloud default project when unset
+variable "project" { default = null }
+// The region (e.g. us-central1) and the zone in it the instance lives in
+// (us-central1-a); fuoco always passes both
+variable "region" {}
 variable "zone" {}
 variable "instance_type" {}
-variable "script_path" { default = "" }
+variable "name" { default = "fuoco-ephemeral" }
+// Login user; fuoco creates it when --user is given
+variable "user" { default = "ubuntu" }
+// Set on boot by fuoco through cloud-init; here for templates that need it
+variable "hostname" { default = null }
+variable "arch" { default = "x86_64" }
+// Image family, optionally as project/family (null picks Ubuntu 20.04)
+variable "image" { default = null }
+variable "tags" {
+  type    = map(string)
+  default = {}
+}
+// Custom metadata keys, read from the metadata server (--metadata)
+variable "metadata" {
+  type    = map(string)
+  default = {}
+}
+variable "user_data" { default = "" }
+variable "extra_volume_size" {
+  type    = number
+  default = null
+}
+variable "use_spot" { default = false }
+variable "disk_size" {
+  type    = number
+  default = null
+}
+// Existing network and subnetwork to join (null uses the default network)
+variable "vpc_id" { default = null }
+variable "subnet_id" { default = null }
+// False for a private-only instance
+variable "assign_public_ip" { default = true }
+// Reserve a static external address, deleted on destroy
+variable "static_ip" { default = false }
+// Public keys to authorize, read from disk by fuoco (not by Terraform)
+variable "ssh_public_keys" {
+  type    = list(string)
+  default = []
+}
+variable "inbound_rules" {
+  type = list(object({
+    protocol    = string
+    port_number = number
+  }))
+  default = []
+}
 
 provider "google" {
   project = var.project
-  zone    = var.region
+  zone    = var.zone
+}
+
+locals {
+  # Plain scripts go through the startup-script agent; cloud-config and
+  # multipart user data need cloud-init's user-data key
+  user_data_is_script = var.user_data == "" || startswith(var.user_data, "#!")
+}
+
+locals {
+  network = var.vpc_id != null ? var.vpc_id : "default"
 }
 
-// Use latest Ubuntu 20.04 LTS image family (dynamic alias)
+locals {
+  default_image_family = var.arch == "arm64" ? "ubuntu-2004-lts-arm64" : "ubuntu-2004-lts"
+  image_parts          = var.image != null ? split("/", var.image) : []
+}
+
+// Use the latest image of the family (Ubuntu 20.04 LTS unless --image is set)
 data "google_compute_image" "ubuntu" {
-  family  = "ubuntu-2004-lts"
-  project = "ubuntu-os-cloud"
+  family  = var.image != null ? element(local.image_parts, length(local.image_parts) - 1) : local.default_image_family
+  project = length(local.image_parts) == 2 ? local.image_parts[0] : "ubuntu-os-cloud"
+}
+
+resource "google_compute_firewall" "inbound" {
+  count         = length(var.inbound_rules) > 0 ? 1 : 0
+  name          = "${var.name}-inbound"
+  network       = local.network
+  source_ranges = ["0.0.0.0/0"]
+  target_tags   = [var.name]
+
+  dynamic "allow" {
+    for_each = var.inbound_rules
+    content {
+      protocol = allow.value.protocol
+      ports    = [tostring(allow.value.port_number)]
+    }
+  }
+}
+
+// Addresses are regional
+resource "google_compute_address" "static" {
+  count  = var.static_ip ? 1 : 0
+  name   = "${var.name}-ip"
+  region = var.region
 }
 
 resource "google_compute_instance" "vm" {
-  name         = "fuoco-ephemeral"
+  name         = var.name
   machine_type = var.instance_type
+  tags         = [var.name]
+  labels       = var.tags
   boot_disk {
     initialize_params {
       image = data.google_compute_image.ubuntu.self_link
+      size  = var.disk_size
     }
   }
   network_interface {
-    network = "default"
-    access_config {}
+    network    = local.network
+    subnetwork = var.subnet_id
+    dynamic "access_config" {
+      for_each = var.assign_public_ip ? [1] : []
+      content {
+        nat_ip = try(google_compute_address.static[0].address, null)
+      }
+    }
+  }
+  scheduling {
+    preemptible                 = var.use_spot
+    automatic_restart           = !var.use_spot
+    provisioning_model          = var.use_spot ? "SPOT" : "STANDARD"
+    instance_termination_action = var.use_spot ? "DELETE" : null
+  }
+  metadata = merge(
+    var.metadata,
+    length(var.ssh_public_keys) > 0 ? {
+      ssh-keys = join("\n", [
+        for key in var.ssh_public_keys : "${var.user}:${key}"
+      ])
+    } : {},
+    local.user_data_is_script ? {} : { user-data = var.user_data },
+  )
+  metadata_startup_script = local.user_data_is_script ? var.user_data : null
+
+  lifecycle {
+    ignore_changes = [attached_disk]
   }
-  metadata_startup_script = var.script
+}
+
+resource "google_compute_disk" "extra" {
+  count = var.extra_volume_size != null ? 1 : 0
+  name  = "${var.name}-extra"
+  size  = var.extra_volume_size
+  zone  = google_compute_instance.vm.zone
+}
+
+resource "google_compute_attached_disk" "extra" {
+  count    = var.extra_volume_size != null ? 1 : 0
+  disk     = google_compute_disk.extra[0].id
+  instance = google_compute_instance.vm.id
 }
 
 output "public_ip" {
-  value = google_compute_instance.vm.network_interface[0].access_config[0].nat_ip
+  value = try(google_compute_instance.vm.network_interface[0].access_config[0].nat_ip, "")
+}
+
+output "private_ip" {
+  value = google_compute_instance.vm.network_interface[0].network_ip
 }
 
 output "region" {
   value = var.region
 }
+
+output "zone" {
+  value = var.zone
+}
+
+output "instance_lifecycle" {
+  value = var.use_spot ? "spot" : "on-demand"
+}
+
+output "extra_volume_id" {
+  value = try(google_compute_disk.extra[0].id, null)
+}
+
+output "ssh_user" {
+  value = var.user
+}
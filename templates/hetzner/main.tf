@@ -1,19 +1,97 @@
 variable "token" {}
 variable "instance_type" {}
+variable "name" { default = "fuoco-ephemeral" }
+// Login user; fuoco creates it when --user is given
+variable "user" { default = "root" }
+// Set on boot by fuoco through cloud-init; here for templates that need it
+variable "hostname" { default = null }
+// Hetzner resolves the image architecture from the server type
+variable "arch" { default = "x86_64" }
 variable "region" {}
-variable "script_path" { default = "" }
+variable "image" { default = "ubuntu-22.04" }
+variable "tags" {
+  type    = map(string)
+  default = {}
+}
+// Server labels, which Hetzner has in place of metadata (--metadata)
+variable "metadata" {
+  type    = map(string)
+  default = {}
+}
+variable "user_data" { default = "" }
+variable "extra_volume_size" {
+  type    = number
+  default = null
+}
+// Hetzner has no spot market; fuoco rejects --spot before reaching here
+variable "use_spot" { default = false }
+// Reserve a primary IPv4, deleted on destroy
+variable "static_ip" { default = false }
+// Public keys to authorize, read from disk by fuoco (not by Terraform)
+variable "ssh_public_keys" {
+  type    = list(string)
+  default = []
+}
+// An SSH key already registered with the project, by name
+variable "key_name" { default = null }
 
 provider "hcloud" {
   token    = var.token
   location = var.region
 }
 
+// Hetzner only takes keys registered with the project
+resource "hcloud_ssh_key" "deployer" {
+  count      = length(var.ssh_public_keys)
+  name       = "${var.name}-key-${count.index}"
+  public_key = var.ssh_public_keys[count.index]
+}
+
+// Primary IPs belong to a datacenter, not a location
+locals {
+  datacenters = {
+    fsn1 = "fsn1-dc14"
+    nbg1 = "nbg1-dc3"
+    hel1 = "hel1-dc2"
+    ash  = "ash-dc1"
+    hil  = "hil-dc1"
+    sin  = "sin-dc1"
+  }
+}
+
+// Not auto-deleted with the server: Terraform deletes it on destroy itself
+resource "hcloud_primary_ip" "static" {
+  count         = var.static_ip ? 1 : 0
+  name          = "${var.name}-ip"
+  type          = "ipv4"
+  datacenter    = local.datacenters[var.region]
+  assignee_type = "server"
+  auto_delete   = false
+  labels        = var.tags
+}
+
 resource "hcloud_server" "vm" {
-  name        = "fuoco-ephemeral"
-  image       = "ubuntu-22.04"
+  name        = var.name
+  image       = var.image
   server_type = var.instance_type
   location    = var.region
-  user_data   = var.script_path
+  user_data   = var.user_data
+  labels      = merge(var.metadata, var.tags)
+  ssh_keys    = concat(hcloud_ssh_key.deployer[*].id, var.key_name != null ? [var.key_name] : [])
+
+  public_net {
+    ipv4_enabled = true
+    ipv4         = try(hcloud_primary_ip.static[0].id, null)
+  }
+}
+
+// Formatting and mounting is left to the fuoco boot script
+resource "hcloud_volume" "extra" {
+  count     = var.extra_volume_size != null ? 1 : 0
+  name      = "${var.name}-extra"
+  size      = var.extra_volume_size
+  server_id = hcloud_server.vm.id
+  automount = false
 }
 
 output "public_ip" {
@@ -23,3 +101,15 @@ output "public_ip" {
 output "region" {
   value = var.region
 }
+
+output "instance_lifecycle" {
+  value = "on-demand"
+}
+
+output "extra_volume_id" {
+  value = try(hcloud_volume.extra[0].id, null)
+}
+
+output "ssh_user" {
+  value = var.user
+}
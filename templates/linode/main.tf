@@ -0,0 +1,113 @@
+terraform {
+  required_providers {
+    linode = {
+      source = "linode/linode"
+    }
+  }
+}
+
+variable "region" {}
+variable "instance_type" {}
+variable "name" { default = "fuoco-ephemeral" }
+// Login user; fuoco creates it when --user is given
+variable "user" { default = "root" }
+// Set on boot by fuoco through cloud-init; here for templates that need it
+variable "hostname" { default = null }
+// Linode only offers x86_64; fuoco rejects --arch arm64 before reaching here
+variable "arch" { default = "x86_64" }
+variable "image" { default = "linode/ubuntu22.04" }
+variable "tags" {
+  type    = map(string)
+  default = {}
+}
+variable "user_data" { default = "" }
+// Set when user_data is already gzipped and base64-encoded
+variable "user_data_base64" { default = false }
+variable "extra_volume_size" {
+  type    = number
+  default = null
+}
+// Linode has no spot market; fuoco rejects --spot before reaching here
+variable "use_spot" { default = false }
+// Public keys to authorize, read from disk by fuoco (not by Terraform)
+variable "ssh_public_keys" {
+  type    = list(string)
+  default = []
+}
+variable "inbound_rules" {
+  type = list(object({
+    protocol    = string
+    port_number = number
+  }))
+  default = []
+}
+
+// Authenticates with the LINODE_TOKEN environment variable
+provider "linode" {}
+
+resource "linode_instance" "vm" {
+  label  = var.name
+  image  = var.image
+  region = var.region
+  type   = var.instance_type
+  // Linode tags are plain strings
+  tags   = [for key, value in var.tags : "${key}:${value}"]
+
+  authorized_keys = var.ssh_public_keys
+
+  // Picked up by cloud-init through the Linode metadata service
+  dynamic "metadata" {
+    for_each = var.user_data != "" ? [1] : []
+    content {
+      user_data = var.user_data_base64 ? var.user_data : base64encode(var.user_data)
+    }
+  }
+}
+
+resource "linode_firewall" "inbound" {
+  label           = "${var.name}-inbound"
+  inbound_policy  = "DROP"
+  outbound_policy = "ACCEPT"
+  linodes         = [linode_instance.vm.id]
+
+  dynamic "inbound" {
+    for_each = var.inbound_rules
+    content {
+      label    = "${inbound.value.protocol}-${inbound.value.port_number}"
+      action   = "ACCEPT"
+      protocol = upper(inbound.value.protocol)
+      ports    = tostring(inbound.value.port_number)
+      ipv4     = ["0.0.0.0/0"]
+    }
+  }
+}
+
+// Formatting and mounting is left to the fuoco boot script
+resource "linode_volume" "extra" {
+  count     = var.extra_volume_size != null ? 1 : 0
+  // Volume labels are capped at 32 characters
+  label     = substr("${var.name}-extra", 0, 32)
+  size      = var.extra_volume_size
+  region    = var.region
+  linode_id = linode_instance.vm.id
+}
+
+output "public_ip" {
+  value = linode_instance.vm.ip_address
+}
+
+output "region" {
+  value = var.region
+}
+
+output "instance_lifecycle" {
+  value = "on-demand"
+}
+
+output "extra_volume_id" {
+  value = try(linode_volume.extra[0].id, null)
+}
+
+output "ssh_user" {
+  value = var.user
+}
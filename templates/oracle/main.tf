@@ -0,0 +1,285 @@
+variable "region" {}
+variable "instance_type" {}
+variable "name" { default = "fuoco-ephemeral" }
+// Login user; fuoco creates it when --user is given
+variable "user" { default = "ubuntu" }
+// Set on boot by fuoco through cloud-init; here for templates that need it
+variable "hostname" { default = null }
+# The image lookup below filters on the shape, which already implies the arch
+variable "arch" { default = "arm64" }
+variable "compartment_id" {}
+# Image OCID (null picks the latest Canonical Ubuntu 22.04 for the shape)
+variable "image" { default = null }
+variable "tags" {
+  type    = map(string)
+  default = {}
+}
+# Extra instance metadata, read from the metadata service (--metadata)
+variable "metadata" {
+  type    = map(string)
+  default = {}
+}
+variable "user_data" { default = "" }
+// Set when user_data is already gzipped and base64-encoded
+variable "user_data_base64" { default = false }
+variable "extra_volume_size" {
+  type    = number
+  default = null
+}
+variable "use_spot" { default = false }
+variable "disk_size" {
+  type    = number
+  default = null
+}
+// Existing VCN and subnet to join (null creates a fresh VCN)
+variable "vpc_id" { default = null }
+variable "subnet_id" { default = null }
+// False for a private-only instance
+variable "assign_public_ip" { default = true }
+// Public keys to authorize, read from disk by fuoco (not by Terraform)
+variable "ssh_public_keys" {
+  type    = list(string)
+  default = []
+}
+variable "inbound_rules" {
+  type = list(object({
+    protocol    = string
+    port_number = number
+  }))
+  default = []
+}
+
+provider "oci" {
+  region = var.region
+}
+
+locals {
+  # OCI security rules take IANA protocol numbers
+  protocol_numbers = {
+    tcp  = "6"
+    udp  = "17"
+    icmp = "1"
+  }
+
+  # Flex shapes need an explicit size; stay inside the Always Free allowance
+  is_flex_shape = length(regexall("\\.Flex$", var.instance_type)) > 0
+
+  # Without --subnet-id the template builds its own VCN around the instance
+  create_network = var.subnet_id == null
+}
+
+data "oci_identity_availability_domains" "ads" {
+  compartment_id = var.compartment_id
+}
+
+# Latest Canonical Ubuntu image compatible with the selected shape
+data "oci_core_images" "ubuntu" {
+  compartment_id           = var.compartment_id
+  operating_system         = "Canonical Ubuntu"
+  operating_system_version = "22.04"
+  shape                    = var.instance_type
+  sort_by                  = "TIMECREATED"
+  sort_order               = "DESC"
+}
+
+resource "oci_core_vcn" "vcn" {
+  count          = local.create_network ? 1 : 0
+  compartment_id = var.compartment_id
+  cidr_blocks    = ["10.0.0.0/16"]
+  display_name   = "${var.name}-vcn"
+}
+
+resource "oci_core_internet_gateway" "igw" {
+  count          = local.create_network ? 1 : 0
+  compartment_id = var.compartment_id
+  vcn_id         = oci_core_vcn.vcn[0].id
+  display_name   = "${var.name}-igw"
+}
+
+resource "oci_core_route_table" "rt" {
+  count          = local.create_network ? 1 : 0
+  compartment_id = var.compartment_id
+  vcn_id         = oci_core_vcn.vcn[0].id
+  display_name   = "${var.name}-rt"
+
+  route_rules {
+    destination       = "0.0.0.0/0"
+    network_entity_id = oci_core_internet_gateway.igw[0].id
+  }
+}
+
+resource "oci_core_security_list" "allow" {
+  count          = local.create_network ? 1 : 0
+  compartment_id = var.compartment_id
+  vcn_id         = oci_core_vcn.vcn[0].id
+  display_name   = "${var.name}-sl"
+
+  dynamic "ingress_security_rules" {
+    for_each = var.inbound_rules
+    content {
+      protocol = lookup(local.protocol_numbers, ingress_security_rules.value.protocol, "all")
+      source   = "0.0.0.0/0"
+
+      dynamic "tcp_options" {
+        for_each = ingress_security_rules.value.protocol == "tcp" ? [1] : []
+        content {
+          min = ingress_security_rules.value.port_number
+          max = ingress_security_rules.value.port_number
+        }
+      }
+
+      dynamic "udp_options" {
+        for_each = ingress_security_rules.value.protocol == "udp" ? [1] : []
+        content {
+          min = ingress_security_rules.value.port_number
+          max = ingress_security_rules.value.port_number
+        }
+      }
+    }
+  }
+
+  egress_security_rules {
+    protocol    = "all"
+    destination = "0.0.0.0/0"
+  }
+}
+
+resource "oci_core_subnet" "subnet" {
+  count             = local.create_network ? 1 : 0
+  compartment_id    = var.compartment_id
+  vcn_id            = oci_core_vcn.vcn[0].id
+  cidr_block        = "10.0.1.0/24"
+  display_name      = "${var.name}-subnet"
+  route_table_id    = oci_core_route_table.rt[0].id
+  security_list_ids = [oci_core_security_list.allow[0].id]
+}
+
+// An existing subnet's security lists are not ours to change, so the
+// inbound rules go on a network security group attached to the VNIC
+resource "oci_core_network_security_group" "allow" {
+  count          = local.create_network ? 0 : 1
+  compartment_id = var.compartment_id
+  vcn_id         = var.vpc_id
+  display_name   = "${var.name}-nsg"
+}
+
+resource "oci_core_network_security_group_security_rule" "inbound" {
+  count                     = local.create_network ? 0 : length(var.inbound_rules)
+  network_security_group_id = oci_core_network_security_group.allow[0].id
+  direction                 = "INGRESS"
+  protocol                  = lookup(local.protocol_numbers, var.inbound_rules[count.index].protocol, "all")
+  source                    = "0.0.0.0/0"
+  source_type               = "CIDR_BLOCK"
+
+  dynamic "tcp_options" {
+    for_each = var.inbound_rules[count.index].protocol == "tcp" ? [1] : []
+    content {
+      destination_port_range {
+        min = var.inbound_rules[count.index].port_number
+        max = var.inbound_rules[count.index].port_number
+      }
+    }
+  }
+
+  dynamic "udp_options" {
+    for_each = var.inbound_rules[count.index].protocol == "udp" ? [1] : []
+    content {
+      destination_port_range {
+        min = var.inbound_rules[count.index].port_number
+        max = var.inbound_rules[count.index].port_number
+      }
+    }
+  }
+}
+
+resource "oci_core_network_security_group_security_rule" "outbound" {
+  count                     = local.create_network ? 0 : 1
+  network_security_group_id = oci_core_network_security_group.allow[0].id
+  direction                 = "EGRESS"
+  protocol                  = "all"
+  destination               = "0.0.0.0/0"
+  destination_type          = "CIDR_BLOCK"
+}
+
+resource "oci_core_instance" "vm" {
+  compartment_id      = var.compartment_id
+  availability_domain = data.oci_identity_availability_domains.ads.availability_domains[0].name
+  shape               = var.instance_type
+  display_name        = var.name
+  freeform_tags       = var.tags
+
+  dynamic "shape_config" {
+    for_each = local.is_flex_shape ? [1] : []
+    content {
+      ocpus         = 1
+      memory_in_gbs = 6
+    }
+  }
+
+  dynamic "preemptible_instance_config" {
+    for_each = var.use_spot ? [1] : []
+    content {
+      preemption_action {
+        type                 = "TERMINATE"
+        preserve_boot_volume = false
+      }
+    }
+  }
+
+  source_details {
+    source_type             = "image"
+    source_id               = var.image != null ? var.image : data.oci_core_images.ubuntu.images[0].id
+    boot_volume_size_in_gbs = var.disk_size
+  }
+
+  create_vnic_details {
+    subnet_id        = local.create_network ? oci_core_subnet.subnet[0].id : var.subnet_id
+    nsg_ids          = oci_core_network_security_group.allow[*].id
+    assign_public_ip = var.assign_public_ip
+  }
+
+  metadata = merge(
+    var.metadata,
+    length(var.ssh_public_keys) > 0 ? { ssh_authorized_keys = join("\n", var.ssh_public_keys) } : {},
+    var.user_data != "" ? { user_data = var.user_data_base64 ? var.user_data : base64encode(var.user_data) } : {},
+  )
+}
+
+resource "oci_core_volume" "extra" {
+  count               = var.extra_volume_size != null ? 1 : 0
+  compartment_id      = var.compartment_id
+  availability_domain = oci_core_instance.vm.availability_domain
+  size_in_gbs         = var.extra_volume_size
+  display_name        = "${var.name}-extra"
+}
+
+resource "oci_core_volume_attachment" "extra" {
+  count           = var.extra_volume_size != null ? 1 : 0
+  attachment_type = "paravirtualized"
+  instance_id     = oci_core_instance.vm.id
+  volume_id       = oci_core_volume.extra[0].id
+}
+
+output "public_ip" {
+  value = oci_core_instance.vm.public_ip
+}
+
+output "private_ip" {
+  value = oci_core_instance.vm.private_ip
+}
+
+output "region" {
+  value = var.region
+}
+
+output "instance_lifecycle" {
+  value = var.use_spot ? "preemptible" : "on-demand"
+}
+
+output "extra_volume_id" {
+  value = try(oci_core_volume.extra[0].id, null)
+}
+
+output "ssh_user" {
+  value = var.user
+}
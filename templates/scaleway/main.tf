@@ -0,0 +1,120 @@
+terraform {
+  required_providers {
+    scaleway = {
+      source = "scaleway/scaleway"
+    }
+  }
+}
+
+// A zone, like fr-par-1: Scaleway instances live in zones
+variable "region" {}
+variable "instance_type" {}
+variable "name" { default = "fuoco-ephemeral" }
+// Login user; fuoco creates it when --user is given
+variable "user" { default = "root" }
+// Set on boot by fuoco through cloud-init; here for templates that need it
+variable "hostname" { default = null }
+// Scaleway resolves the image architecture from the commercial type
+variable "arch" { default = "x86_64" }
+variable "image" { default = "ubuntu_jammy" }
+variable "tags" {
+  type    = map(string)
+  default = {}
+}
+// Extra user data keys, read from the metadata API (--metadata)
+variable "metadata" {
+  type    = map(string)
+  default = {}
+}
+variable "user_data" { default = "" }
+variable "extra_volume_size" {
+  type    = number
+  default = null
+}
+// Scaleway has no spot market; fuoco rejects --spot before reaching here
+variable "use_spot" { default = false }
+// The flexible IP below is reserved either way, and deleted on destroy
+variable "static_ip" { default = false }
+// Public keys to authorize, read from disk by fuoco (not by Terraform)
+variable "ssh_public_keys" {
+  type    = list(string)
+  default = []
+}
+variable "inbound_rules" {
+  type = list(object({
+    protocol    = string
+    port_number = number
+  }))
+  default = []
+}
+
+// Authenticates with SCW_ACCESS_KEY, SCW_SECRET_KEY and
+// SCW_DEFAULT_PROJECT_ID
+provider "scaleway" {
+  zone = var.region
+}
+
+resource "scaleway_instance_security_group" "inbound" {
+  name                    = "${var.name}-inbound"
+  inbound_default_policy  = "drop"
+  outbound_default_policy = "accept"
+
+  dynamic "inbound_rule" {
+    for_each = var.inbound_rules
+    content {
+      action   = "accept"
+      protocol = upper(inbound_rule.value.protocol)
+      port     = inbound_rule.value.port_number
+    }
+  }
+}
+
+resource "scaleway_instance_ip" "public" {}
+
+// Formatting and mounting is left to the fuoco boot script
+resource "scaleway_block_volume" "extra" {
+  count      = var.extra_volume_size != null ? 1 : 0
+  name       = "${var.name}-extra"
+  size_in_gb = var.extra_volume_size
+  iops       = 5000
+}
+
+resource "scaleway_instance_server" "vm" {
+  name              = var.name
+  type              = var.instance_type
+  image             = var.image
+  ip_id             = scaleway_instance_ip.public.id
+  security_group_id = scaleway_instance_security_group.inbound.id
+  // Scaleway tags are plain strings. AUTHORIZED_KEY tags authorize a key
+  // on this server alone, where project keys would reach every server
+  tags = concat(
+    [for key, value in var.tags : "${key}=${value}"],
+    [for key in var.ssh_public_keys : "AUTHORIZED_KEY=${replace(trimspace(key), " ", "_")}"],
+  )
+  additional_volume_ids = scaleway_block_volume.extra[*].id
+
+  user_data = merge(
+    var.metadata,
+    var.user_data != "" ? { "cloud-init" = var.user_data } : {},
+  )
+}
+
+output "public_ip" {
+  value = scaleway_instance_ip.public.address
+}
+
+output "region" {
+  value = var.region
+}
+
+output "instance_lifecycle" {
+  value = "on-demand"
+}
+
+output "extra_volume_id" {
+  value = try(scaleway_block_volume.extra[0].id, null)
+}
+
+output "ssh_user" {
+  value = var.user
+}
@@ -0,0 +1,65 @@
+//! Giving up on a deploy before it is ready: its apply is stopped and
+//! what it created is destroyed.
+
+use fuoco::{
+  deployment::Deployment,
+  task, use_workdir,
+  watchdog::{KillHandle, Killed},
+  DeployParams, Provider,
+};
+use std::{
+  env, fs,
+  future::Future,
+  pin::Pin,
+  sync::{mpsc, Arc},
+  task::{Context, Waker},
+  thread,
+  time::Duration,
+};
+
+/// `deploy_async` with an apply that runs until it is killed, and a
+/// destroy that reports each call.
+#[test]
+fn dropped_deploy_is_killed_and_cleaned_up() {
+  let dir =
+    env::temp_dir().join(format!("fuoco-cancel-{}", std::process::id()));
+  use_workdir(&dir.join("work")).unwrap();
+  env::set_var("XDG_DATA_HOME", dir.join("data"));
+  env::set_var("XDG_CACHE_HOME", dir.join("cache"));
+  let mut params = DeployParams::new(Provider::Hetzner).unwrap();
+  params.region = Some("nbg1".to_string());
+  let (applying, applied) = mpsc::channel();
+  let (destroying, destroyed) = mpsc::channel();
+  let kill = KillHandle::default();
+  let deployment = Deployment::new(params)
+    .with_kill_handle(kill.clone())
+    .with_apply(Arc::new(move |_, _, kill| {
+      applying.send(()).unwrap();
+      while !kill.killed() {
+        thread::sleep(Duration::from_millis(10));
+      }
+      Err(
+        Killed {
+          what: "terraform apply",
+        }
+        .into(),
+      )
+    }))
+    .with_destroy(Arc::new(move |_, _| {
+      destroying.send(()).unwrap();
+      Ok(())
+    }));
+  let mut task =
+    task::spawn(move || deployment.deploy()).on_drop(move || kill.kill());
+  applied.recv_timeout(Duration::from_secs(10)).unwrap();
+  let poll = Pin::new(&mut task).poll(&mut Context::from_waker(Waker::noop()));
+  assert!(poll.is_pending());
+  drop(task);
+  destroyed.recv_timeout(Duration::from_secs(10)).unwrap();
+  // Once: the deployment, and with it the sender, is gone after that
+  assert_eq!(
+    destroyed.recv_timeout(Duration::from_secs(10)),
+    Err(mpsc::RecvTimeoutError::Disconnected)
+  );
+  let _ = fs::remove_dir_all(&dir);
+}
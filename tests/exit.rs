@@ -0,0 +1,99 @@
+//! The exit statuses a wrapper script tells failures apart by.
+
+use fuoco::{
+  deployment::Deployment, exit, use_workdir, DeployParams, Provider,
+};
+use std::{
+  env, fs, os::unix::fs::PermissionsExt, path::Path, process::Command,
+  sync::Arc,
+};
+
+#[test]
+fn rejected_arguments_exit_invalid() {
+  // Not under env::temp_dir(), which the test below moves
+  let home = Path::new(env!("CARGO_TARGET_TMPDIR")).join("exit-home");
+  for args in [
+    &["--name", "Not_A_Name"][..],
+    &["--disk-size", "20"],
+    &["--aws-profile", "work"],
+    &["--bogus"],
+  ] {
+    let output = Command::new(env!("CARGO_BIN_EXE_fuoco"))
+      .args(["deploy", "-c", "hetzner", "-y", "--skip-credential-check"])
+      .args(args)
+      // Not the directories the test below points this process at
+      .env_clear()
+      .env("CARGO_MANIFEST_DIR", env!("CARGO_MANIFEST_DIR"))
+      .env("HOME", &home)
+      .output()
+      .unwrap();
+    assert_eq!(output.status.code(), Some(exit::INVALID), "{:?}", args);
+    // Refused for that argument, not for something else
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(args[0]), "{}", stderr);
+  }
+  let _ = fs::remove_dir_all(&home);
+}
+
+/// The AWS CLI of the credential check runs with --aws-profile.
+#[test]
+fn rejected_credentials_exit_invalid() {
+  let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("exit-credentials");
+  fs::create_dir_all(dir.join("bin")).unwrap();
+  let aws = dir.join("bin").join("aws");
+  fs::write(
+    &aws,
+    "#!/bin/sh\necho \"no credentials for profile $AWS_PROFILE\" >&2\nexit 255\n",
+  )
+  .unwrap();
+  fs::set_permissions(&aws, fs::Permissions::from_mode(0o755)).unwrap();
+  let output = Command::new(env!("CARGO_BIN_EXE_fuoco"))
+    .args(["deploy", "-c", "aws", "--region", "eu-west-1", "-y"])
+    .args(["--aws-profile", "work"])
+    .env_clear()
+    .env("CARGO_MANIFEST_DIR", env!("CARGO_MANIFEST_DIR"))
+    .env("HOME", &dir)
+    .env(
+      "PATH",
+      format!("{}:/usr/bin:/bin", dir.join("bin").display()),
+    )
+    .output()
+    .unwrap();
+  assert_eq!(output.status.code(), Some(exit::INVALID));
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  assert!(
+    stderr.contains("no credentials for profile work"),
+    "{}",
+    stderr
+  );
+  assert!(
+    stderr.contains("aws configure --profile work"),
+    "{}",
+    stderr
+  );
+  let _ = fs::remove_dir_all(&dir);
+}
+
+/// An apply that fails, and a cleanup that fails after it, with nothing
+/// run for real.
+#[test]
+fn failed_apply_and_cleanup_exit_leaked() {
+  let dir = env::temp_dir().join(format!("fuoco-leak-{}", std::process::id()));
+  // The only test in this file that changes the environment
+  use_workdir(&dir.join("work")).unwrap();
+  env::set_var("XDG_DATA_HOME", dir.join("data"));
+  env::set_var("XDG_CACHE_HOME", dir.join("cache"));
+  let mut params = DeployParams::new(Provider::Hetzner).unwrap();
+  params.region = Some("nbg1".to_string());
+  let deployment = Deployment::new(params)
+    .with_apply(Arc::new(|_, _, _| {
+      anyhow::bail!("Error: server type not found")
+    }))
+    .with_destroy(Arc::new(|_, _| anyhow::bail!("Error: still in use")));
+  let err = deployment.deploy().err().expect("the deploy succeeded");
+  assert_eq!(exit::code(&err), exit::LEAKED);
+  // And the leak is on record for `fuoco clean`
+  let records = fs::read_dir(dir.join("data/fuoco/failed")).unwrap().count();
+  assert_eq!(records, 1);
+  let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,107 @@
+//! `fuoco deploy` run end to end against a stand-in `terraform`, to check
+//! that stdout carries the payload and nothing else.
+
+use std::{
+  env, fs,
+  os::unix::fs::PermissionsExt,
+  path::{Path, PathBuf},
+  process::{Command, Output},
+};
+
+/// Answers every Terraform command, with a public IP for `output`.
+const FAKE_TERRAFORM: &str = r#"#!/bin/sh
+for arg in "$@"; do
+  if [ "$arg" = output ]; then
+    echo '{"public_ip":{"sensitive":false,"type":"string","value":"203.0.113.7"}}'
+  fi
+done
+"#;
+
+/// A scratch directory for `test`, with the fake `terraform` in `bin`.
+fn sandbox(test: &str) -> PathBuf {
+  let dir =
+    env::temp_dir().join(format!("fuoco-it-{}-{}", test, std::process::id()));
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(dir.join("bin")).unwrap();
+  fs::create_dir_all(dir.join("home")).unwrap();
+  let terraform = dir.join("bin").join("terraform");
+  fs::write(&terraform, FAKE_TERRAFORM).unwrap();
+  fs::set_permissions(&terraform, fs::Permissions::from_mode(0o755)).unwrap();
+  dir
+}
+
+/// Deploy to Hetzner and let --timeout destroy it a second later.
+fn deploy(dir: &Path, args: &[&str]) -> Output {
+  let path = format!(
+    "{}:{}",
+    dir.join("bin").display(),
+    env::var("PATH").unwrap_or_default()
+  );
+  let output = Command::new(env!("CARGO_BIN_EXE_fuoco"))
+    .arg("--workdir")
+    .arg(dir.join("work"))
+    .args(["deploy", "-c", "hetzner", "--region", "nbg1", "-y"])
+    .args(["--timeout", "1s", "--skip-credential-check"])
+    .args(args)
+    .env("PATH", path)
+    .env("HOME", dir.join("home"))
+    .env_remove("XDG_CONFIG_HOME")
+    .env_remove("XDG_CACHE_HOME")
+    .env_remove("NO_COLOR")
+    .output()
+    .unwrap();
+  let _ = fs::remove_dir_all(dir);
+  // exit::TIMEOUT: deployed, then destroyed by --timeout
+  assert_eq!(output.status.code(), Some(8), "{:?}", output);
+  output
+}
+
+fn stdout(output: &Output) -> String {
+  String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+  String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+#[test]
+fn json_events_are_all_of_stdout() {
+  let output = deploy(&sandbox("events"), &["--format", "json-events"]);
+  let stdout = stdout(&output);
+  let events: Vec<serde_json::Value> = stdout
+    .lines()
+    .map(|line| serde_json::from_str(line).expect(line))
+    .collect();
+  assert!(events.len() >= 2, "{}", stdout);
+  for event in &events {
+    assert!(event["timestamp"].is_u64(), "{}", event);
+    assert_eq!(event["deployment"], "fuoco-ephemeral");
+    assert!(event["type"].is_string(), "{}", event);
+  }
+  assert_eq!(events[0]["type"], "deploy_started");
+  assert!(stderr(&output).contains("Deploy params"));
+}
+
+#[test]
+fn text_outputs_are_all_of_stdout() {
+  let output = deploy(&sandbox("text"), &[]);
+  for line in stdout(&output).lines() {
+    let (key, _) = line.split_once(": ").expect(line);
+    assert!(
+      key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+      "{:?} is not an output",
+      line
+    );
+  }
+  let stderr = stderr(&output);
+  assert!(stderr.contains("Deploy params"));
+  assert!(stderr.contains("Resources deployed."));
+}
+
+#[test]
+fn quiet_leaves_only_warnings_on_stderr() {
+  let output = deploy(&sandbox("quiet"), &["--quiet"]);
+  let stderr = stderr(&output);
+  assert!(!stderr.contains("Deploy params"), "{}", stderr);
+  assert!(!stderr.contains("Resources deployed."), "{}", stderr);
+}